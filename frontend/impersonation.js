@@ -0,0 +1,39 @@
+// Shows a banner while an admin or instructor is viewing the site as
+// someone else, with a way back to their own account.
+
+function csrfToken() {
+    const cookie = document.cookie
+        .split('; ')
+        .find((cookie) => cookie.startsWith('__Host-ceresforge_csrf='));
+    return cookie ? cookie.split('=')[1] : '';
+}
+
+async function stopImpersonating() {
+    await fetch('/auth/impersonation', {
+        method: 'DELETE',
+        headers: { 'x-csrf-token': csrfToken() },
+    });
+    location.reload();
+}
+
+async function showImpersonationBanner() {
+    const response = await fetch('/api/user');
+    if (!response.ok) {
+        return;
+    }
+    const user = await response.json();
+    if (!user.impersonator) {
+        return;
+    }
+
+    const banner = document.createElement('div');
+    banner.id = 'impersonation-banner';
+    banner.textContent = `${user.impersonator.username}, you are viewing CeresForge as ${user.username}. `;
+    const stop = document.createElement('button');
+    stop.textContent = 'Stop';
+    stop.addEventListener('click', stopImpersonating);
+    banner.appendChild(stop);
+    document.body.prepend(banner);
+}
+
+showImpersonationBanner();
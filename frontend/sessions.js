@@ -0,0 +1,86 @@
+// Lists the sessions and access tokens of the current user, or with
+// `?user=name` those of another user for admins, and revokes them.
+
+const username = new URLSearchParams(location.search).get('user');
+const base = username ? `/api/users/${encodeURIComponent(username)}` : null;
+const sessionsUrl = base ? `${base}/sessions` : '/api/user/sessions';
+const tokensUrl = base ? `${base}/tokens` : '/api/tokens';
+
+function csrfToken() {
+    const cookie = document.cookie
+        .split('; ')
+        .find((cookie) => cookie.startsWith('__Host-ceresforge_csrf='));
+    return cookie ? cookie.split('=')[1] : '';
+}
+
+function time(seconds) {
+    return seconds ? new Date(seconds * 1000).toLocaleString() : '';
+}
+
+function row(cells, onRevoke) {
+    const tr = document.createElement('tr');
+    for (const cell of cells) {
+        const td = document.createElement('td');
+        td.textContent = cell ?? '';
+        tr.appendChild(td);
+    }
+    const td = document.createElement('td');
+    if (onRevoke) {
+        const button = document.createElement('button');
+        button.textContent = 'Revoke';
+        button.addEventListener('click', onRevoke);
+        td.appendChild(button);
+    }
+    tr.appendChild(td);
+    return tr;
+}
+
+async function revoke(url) {
+    const response = await fetch(url, {
+        method: 'DELETE',
+        headers: { 'x-csrf-token': csrfToken() },
+    });
+    if (!response.ok) {
+        document.getElementById('status').textContent = `Revoking failed: ${response.status}`;
+    }
+    await load();
+}
+
+async function load() {
+    const [sessions, tokens] = await Promise.all([fetch(sessionsUrl), fetch(tokensUrl)]);
+    if (!sessions.ok) {
+        document.getElementById('status').textContent = `Loading sessions failed: ${sessions.status}`;
+        return;
+    }
+    document.getElementById('status').textContent = username ? `Sessions and tokens of ${username}` : '';
+
+    const sessionRows = (await sessions.json()).map((session) => row(
+        [
+            (session.device ?? 'Unknown device') + (session.current ? ' (this session)' : ''),
+            session.addr,
+            session.auth_method,
+            time(session.created_at),
+            time(session.last_seen_at),
+        ],
+        session.current ? null : () => revoke(`${sessionsUrl}/${session.id}`),
+    ));
+    document.getElementById('sessions').replaceChildren(...sessionRows);
+
+    const tokenRows = tokens.ok ? (await tokens.json()).map((token) => row(
+        [
+            token.name,
+            token.scopes.join(', '),
+            time(token.created_at),
+            time(token.last_used_at),
+            token.expires_at ? time(token.expires_at) : 'never',
+        ],
+        () => revoke(`${tokensUrl}/${token.id}`),
+    )) : [];
+    document.getElementById('tokens').replaceChildren(...tokenRows);
+}
+
+document.getElementById('revoke-all').addEventListener('click', async () => {
+    await revoke(sessionsUrl);
+});
+
+load();
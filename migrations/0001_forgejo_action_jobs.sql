@@ -0,0 +1,11 @@
+CREATE TABLE forgejo_action_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    repository TEXT NOT NULL,
+    sha TEXT NOT NULL,
+    context TEXT NOT NULL,
+    state TEXT NOT NULL,
+    description TEXT,
+    target_url TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (repository, sha, context)
+);
@@ -0,0 +1,17 @@
+CREATE TABLE forgejo_pull_requests (
+    repository TEXT NOT NULL,
+    number BIGINT NOT NULL,
+    head_sha TEXT NOT NULL,
+    state TEXT NOT NULL,
+    PRIMARY KEY (repository, number)
+);
+
+-- Commits whose analysis has been published on a pull request, so that it
+-- is published once however many status events arrive.
+CREATE TABLE forgejo_reviews (
+    repository TEXT NOT NULL,
+    number BIGINT NOT NULL,
+    sha TEXT NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (repository, number, sha)
+);
@@ -0,0 +1,24 @@
+ALTER TABLE forgejo_pull_requests
+    ADD COLUMN author_id BIGINT,
+    ADD COLUMN author_username TEXT;
+
+CREATE TABLE peer_review_rounds (
+    id BIGSERIAL PRIMARY KEY,
+    repository TEXT NOT NULL,
+    deadline TIMESTAMPTZ NOT NULL,
+    reviewers INTEGER NOT NULL,
+    anonymous BOOLEAN NOT NULL,
+    assigned_at TIMESTAMPTZ
+);
+
+CREATE TABLE peer_reviews (
+    round_id BIGINT NOT NULL REFERENCES peer_review_rounds (id) ON DELETE CASCADE,
+    number BIGINT NOT NULL,
+    reviewer_id BIGINT NOT NULL,
+    reviewer_username TEXT NOT NULL,
+    completed_at TIMESTAMPTZ,
+    -- When the reviewer was requested on the pull request, so that requests
+    -- that failed are retried. Anonymous rounds request nobody.
+    notified_at TIMESTAMPTZ,
+    PRIMARY KEY (round_id, number, reviewer_id)
+);
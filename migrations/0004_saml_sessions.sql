@@ -0,0 +1,17 @@
+CREATE TABLE saml_requests (
+    id TEXT PRIMARY KEY,
+    idp TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE saml_assertions (
+    id TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    name_id TEXT NOT NULL,
+    session_index TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN name_id_format TEXT;
+
+CREATE INDEX sessions_name_id ON sessions (name_id);
@@ -0,0 +1,13 @@
+CREATE TYPE role AS ENUM ('student', 'ta', 'instructor', 'admin');
+
+CREATE TABLE users (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    email TEXT,
+    display_name TEXT,
+    role role NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+ALTER TABLE sessions ADD COLUMN user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE;
@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+    ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ALTER COLUMN name_id DROP NOT NULL;
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
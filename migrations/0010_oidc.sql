@@ -0,0 +1,8 @@
+CREATE TABLE oidc_requests (
+    state TEXT PRIMARY KEY,
+    provider TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    return_to TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,27 @@
+CREATE TYPE course_role AS ENUM ('student', 'ta', 'instructor');
+
+CREATE TABLE courses (
+    id BIGSERIAL PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    -- Forgejo organization holding the course's repositories.
+    organization TEXT UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE course_members (
+    course_id BIGINT NOT NULL REFERENCES courses ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE,
+    role course_role NOT NULL,
+    PRIMARY KEY (course_id, user_id)
+);
+
+CREATE INDEX course_members_user_id ON course_members (user_id);
+
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT REFERENCES users ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
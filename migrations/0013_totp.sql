@@ -0,0 +1,18 @@
+CREATE TABLE totp_credentials (
+    user_id BIGINT PRIMARY KEY REFERENCES users ON DELETE CASCADE,
+    secret BYTEA NOT NULL,
+    -- Time step of the last accepted code, so no code is accepted twice.
+    last_used_step BIGINT NOT NULL DEFAULT 0,
+    confirmed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE recovery_codes (
+    user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+-- Sessions of users who still have to set up a required second factor.
+ALTER TABLE sessions ADD COLUMN restricted BOOLEAN NOT NULL DEFAULT false;
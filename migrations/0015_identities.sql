@@ -0,0 +1,57 @@
+CREATE TYPE identity_kind AS ENUM ('saml', 'oidc', 'forgejo');
+
+-- External identities of users. The issuer is the SAML entity ID or OpenID
+-- issuer, and empty for Forgejo, of which there is only one.
+CREATE TABLE identities (
+    id BIGSERIAL PRIMARY KEY,
+    kind identity_kind NOT NULL,
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE,
+    -- What the provider last called the user, for showing the identity.
+    display_name TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (kind, issuer, subject)
+);
+
+CREATE INDEX identities_user_id ON identities (user_id);
+
+-- The identity a user was created through, the only one whose logins
+-- update their profile. Users who registered locally or were provisioned
+-- through SCIM have none.
+ALTER TABLE users ADD COLUMN primary_identity_id BIGINT REFERENCES identities ON DELETE SET NULL;
+
+-- The user who started a login to link another identity to their account.
+ALTER TABLE saml_requests ADD COLUMN link_user_id BIGINT REFERENCES users ON DELETE CASCADE;
+ALTER TABLE oidc_requests ADD COLUMN link_user_id BIGINT REFERENCES users ON DELETE CASCADE;
+
+-- Identities proven by a linking login, awaiting confirmation by the
+-- browser of the user they are to be linked to.
+CREATE TABLE pending_links (
+    token TEXT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE,
+    kind identity_kind NOT NULL,
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    display_name TEXT,
+    return_to TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Pushes, attributed to users through their Forgejo identities.
+CREATE TABLE forgejo_pushes (
+    id BIGSERIAL PRIMARY KEY,
+    repository TEXT NOT NULL,
+    ref TEXT NOT NULL,
+    before_sha TEXT NOT NULL,
+    after_sha TEXT NOT NULL,
+    pusher_id BIGINT NOT NULL,
+    pusher_username TEXT NOT NULL,
+    user_id BIGINT REFERENCES users ON DELETE SET NULL,
+    pushed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX forgejo_pushes_user_id ON forgejo_pushes (user_id);
+
+ALTER TABLE forgejo_pull_requests
+    ADD COLUMN author_user_id BIGINT REFERENCES users ON DELETE SET NULL;
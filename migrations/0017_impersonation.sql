@@ -0,0 +1,4 @@
+-- The user an admin or instructor is viewing the site as in a session;
+-- `user_id` stays the one who logged in.
+ALTER TABLE sessions
+    ADD COLUMN impersonating_id BIGINT REFERENCES users ON DELETE SET NULL;
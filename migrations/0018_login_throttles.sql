@@ -0,0 +1,11 @@
+CREATE TYPE throttle_kind AS ENUM ('account', 'ip');
+
+-- Failed login attempts per username and per source address.
+CREATE TABLE login_throttles (
+    kind throttle_kind NOT NULL,
+    key TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_until TIMESTAMPTZ,
+    PRIMARY KEY (kind, key)
+);
@@ -0,0 +1,25 @@
+-- Deactivated users cannot log in, but their work is kept.
+ALTER TABLE users ADD COLUMN active BOOLEAN NOT NULL DEFAULT true;
+
+-- Users created through SCIM, and their ID at the identity management
+-- system.
+ALTER TABLE users ADD COLUMN scim_provisioned BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN external_id TEXT UNIQUE;
+
+-- SCIM groups, each enrolling its members in a course with a role.
+CREATE TABLE scim_groups (
+    id BIGSERIAL PRIMARY KEY,
+    course_id BIGINT NOT NULL REFERENCES courses ON DELETE CASCADE,
+    role course_role NOT NULL,
+    external_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (course_id, role)
+);
+
+-- The SCIM group an enrollment was made through. Groups only change or end
+-- the enrollments they made.
+ALTER TABLE course_members
+    ADD COLUMN scim_group_id BIGINT REFERENCES scim_groups ON DELETE SET NULL;
+
+CREATE INDEX course_members_scim_group_id ON course_members (scim_group_id);
@@ -0,0 +1,17 @@
+-- The address a user proved to receive mail at. The email address is
+-- verified as long as it has not changed since.
+ALTER TABLE users ADD COLUMN verified_email TEXT;
+
+CREATE TYPE email_token_purpose AS ENUM ('verification', 'password_reset');
+
+-- Single-use tokens sent by mail, valid only for the address they were
+-- sent to.
+CREATE TABLE email_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE,
+    purpose email_token_purpose NOT NULL,
+    email TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX email_tokens_user_id ON email_tokens (user_id);
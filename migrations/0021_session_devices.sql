@@ -0,0 +1,9 @@
+CREATE TYPE auth_method AS ENUM ('password', 'passkey', 'saml', 'oidc');
+
+-- What users see of their sessions: a handle that, unlike the session ID,
+-- is no secret, and how and from where they logged in.
+ALTER TABLE sessions
+    ADD COLUMN handle BIGSERIAL UNIQUE,
+    ADD COLUMN auth_method auth_method NOT NULL,
+    ADD COLUMN addr TEXT NOT NULL,
+    ADD COLUMN user_agent TEXT;
@@ -0,0 +1,20 @@
+-- Work handed in to a course: a commit of one of its repositories.
+CREATE TABLE submissions (
+    id BIGSERIAL PRIMARY KEY,
+    course_id BIGINT NOT NULL REFERENCES courses ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users ON DELETE CASCADE,
+    repository TEXT NOT NULL,
+    sha TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX submissions_course_id ON submissions (course_id);
+
+-- What the grading runner reported for a submission.
+CREATE TABLE grading_results (
+    submission_id BIGINT PRIMARY KEY REFERENCES submissions ON DELETE CASCADE,
+    score DOUBLE PRECISION NOT NULL,
+    max_score DOUBLE PRECISION NOT NULL,
+    feedback TEXT,
+    reported_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
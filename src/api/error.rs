@@ -26,6 +26,28 @@ pub enum ApiError {
     MismatchedSignature(MismatchedSignature),
     UnsupportedWebhookEvent(UnsupportedWebhookEvent),
     JsonError(JsonError),
+    InvalidSamlResponse(InvalidSamlResponse),
+    InvalidSamlRequest(InvalidSamlRequest),
+    UnknownIdentityProvider(UnknownIdentityProvider),
+    UsernameTaken(UsernameTaken),
+    Unauthenticated(Unauthenticated),
+    WeakPassword(WeakPassword),
+    InvalidUsername(InvalidUsername),
+    InvalidOidcResponse(InvalidOidcResponse),
+    UnknownOidcProvider(UnknownOidcProvider),
+    InsufficientScope(InsufficientScope),
+    Forbidden(Forbidden),
+    CourseExists(CourseExists),
+    CsrfTokenMismatch(CsrfTokenMismatch),
+    SecondFactorRequired(SecondFactorRequired),
+    SecondFactorEnabled(SecondFactorEnabled),
+    InvalidWebauthnResponse(InvalidWebauthnResponse),
+    IdentityLinked(IdentityLinked),
+    OriginNotAllowed(OriginNotAllowed),
+    Impersonating(Impersonating),
+    LoginThrottled(LoginThrottled),
+    NoEmailAddress(NoEmailAddress),
+    InvalidGrade(InvalidGrade),
 }
 
 impl ApiError {
@@ -41,6 +63,28 @@ impl ApiError {
             ApiError::MismatchedSignature(err) => err.status(),
             ApiError::UnsupportedWebhookEvent(err) => err.status(),
             ApiError::JsonError(err) => err.status(),
+            ApiError::InvalidSamlResponse(err) => err.status(),
+            ApiError::InvalidSamlRequest(err) => err.status(),
+            ApiError::UnknownIdentityProvider(err) => err.status(),
+            ApiError::UsernameTaken(err) => err.status(),
+            ApiError::Unauthenticated(err) => err.status(),
+            ApiError::WeakPassword(err) => err.status(),
+            ApiError::InvalidUsername(err) => err.status(),
+            ApiError::InvalidOidcResponse(err) => err.status(),
+            ApiError::UnknownOidcProvider(err) => err.status(),
+            ApiError::InsufficientScope(err) => err.status(),
+            ApiError::Forbidden(err) => err.status(),
+            ApiError::CourseExists(err) => err.status(),
+            ApiError::CsrfTokenMismatch(err) => err.status(),
+            ApiError::SecondFactorRequired(err) => err.status(),
+            ApiError::SecondFactorEnabled(err) => err.status(),
+            ApiError::InvalidWebauthnResponse(err) => err.status(),
+            ApiError::IdentityLinked(err) => err.status(),
+            ApiError::OriginNotAllowed(err) => err.status(),
+            ApiError::Impersonating(err) => err.status(),
+            ApiError::LoginThrottled(err) => err.status(),
+            ApiError::NoEmailAddress(err) => err.status(),
+            ApiError::InvalidGrade(err) => err.status(),
         }
     }
 }
@@ -58,6 +102,28 @@ impl std::fmt::Display for ApiError {
             ApiError::MismatchedSignature(err) => write!(f, "{err}"),
             ApiError::UnsupportedWebhookEvent(err) => write!(f, "{err}"),
             ApiError::JsonError(err) => write!(f, "{err}"),
+            ApiError::InvalidSamlResponse(err) => write!(f, "{err}"),
+            ApiError::InvalidSamlRequest(err) => write!(f, "{err}"),
+            ApiError::UnknownIdentityProvider(err) => write!(f, "{err}"),
+            ApiError::UsernameTaken(err) => write!(f, "{err}"),
+            ApiError::Unauthenticated(err) => write!(f, "{err}"),
+            ApiError::WeakPassword(err) => write!(f, "{err}"),
+            ApiError::InvalidUsername(err) => write!(f, "{err}"),
+            ApiError::InvalidOidcResponse(err) => write!(f, "{err}"),
+            ApiError::UnknownOidcProvider(err) => write!(f, "{err}"),
+            ApiError::InsufficientScope(err) => write!(f, "{err}"),
+            ApiError::Forbidden(err) => write!(f, "{err}"),
+            ApiError::CourseExists(err) => write!(f, "{err}"),
+            ApiError::CsrfTokenMismatch(err) => write!(f, "{err}"),
+            ApiError::SecondFactorRequired(err) => write!(f, "{err}"),
+            ApiError::SecondFactorEnabled(err) => write!(f, "{err}"),
+            ApiError::InvalidWebauthnResponse(err) => write!(f, "{err}"),
+            ApiError::IdentityLinked(err) => write!(f, "{err}"),
+            ApiError::OriginNotAllowed(err) => write!(f, "{err}"),
+            ApiError::Impersonating(err) => write!(f, "{err}"),
+            ApiError::LoginThrottled(err) => write!(f, "{err}"),
+            ApiError::NoEmailAddress(err) => write!(f, "{err}"),
+            ApiError::InvalidGrade(err) => write!(f, "{err}"),
         }
     }
 }
@@ -75,6 +141,28 @@ impl Error for ApiError {
             ApiError::MismatchedSignature(err) => err.source(),
             ApiError::UnsupportedWebhookEvent(err) => err.source(),
             ApiError::JsonError(err) => err.source(),
+            ApiError::InvalidSamlResponse(err) => err.source(),
+            ApiError::InvalidSamlRequest(err) => err.source(),
+            ApiError::UnknownIdentityProvider(err) => err.source(),
+            ApiError::UsernameTaken(err) => err.source(),
+            ApiError::Unauthenticated(err) => err.source(),
+            ApiError::WeakPassword(err) => err.source(),
+            ApiError::InvalidUsername(err) => err.source(),
+            ApiError::InvalidOidcResponse(err) => err.source(),
+            ApiError::UnknownOidcProvider(err) => err.source(),
+            ApiError::InsufficientScope(err) => err.source(),
+            ApiError::Forbidden(err) => err.source(),
+            ApiError::CourseExists(err) => err.source(),
+            ApiError::CsrfTokenMismatch(err) => err.source(),
+            ApiError::SecondFactorRequired(err) => err.source(),
+            ApiError::SecondFactorEnabled(err) => err.source(),
+            ApiError::InvalidWebauthnResponse(err) => err.source(),
+            ApiError::IdentityLinked(err) => err.source(),
+            ApiError::OriginNotAllowed(err) => err.source(),
+            ApiError::Impersonating(err) => err.source(),
+            ApiError::LoginThrottled(err) => err.source(),
+            ApiError::NoEmailAddress(err) => err.source(),
+            ApiError::InvalidGrade(err) => err.source(),
         }
     }
 }
@@ -97,6 +185,66 @@ impl From<axum::http::Error> for ApiError {
     }
 }
 
+impl From<std::io::Error> for ApiError {
+    fn from(err: std::io::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<quick_xml::SeError> for ApiError {
+    fn from(err: quick_xml::SeError) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<base64::DecodeError> for ApiError {
+    fn from(err: base64::DecodeError) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<x509_cert::der::Error> for ApiError {
+    fn from(err: x509_cert::der::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<rsa::pkcs8::spki::Error> for ApiError {
+    fn from(err: rsa::pkcs8::spki::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<rsa::pkcs8::Error> for ApiError {
+    fn from(err: rsa::pkcs8::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<argon2::password_hash::Error> for ApiError {
+    fn from(err: argon2::password_hash::Error) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
+impl From<tokio::task::JoinError> for ApiError {
+    fn from(err: tokio::task::JoinError) -> ApiError {
+        ApiError::InternalError(InternalError::new(Box::new(err)))
+    }
+}
+
 impl From<ResourceNotFound> for ApiError {
     fn from(err: ResourceNotFound) -> ApiError {
         ApiError::ResourceNotFound(err)
@@ -145,6 +293,138 @@ impl From<UnsupportedWebhookEvent> for ApiError {
     }
 }
 
+impl From<InvalidSamlResponse> for ApiError {
+    fn from(err: InvalidSamlResponse) -> ApiError {
+        ApiError::InvalidSamlResponse(err)
+    }
+}
+
+impl From<InvalidSamlRequest> for ApiError {
+    fn from(err: InvalidSamlRequest) -> ApiError {
+        ApiError::InvalidSamlRequest(err)
+    }
+}
+
+impl From<UnknownIdentityProvider> for ApiError {
+    fn from(err: UnknownIdentityProvider) -> ApiError {
+        ApiError::UnknownIdentityProvider(err)
+    }
+}
+
+impl From<UsernameTaken> for ApiError {
+    fn from(err: UsernameTaken) -> ApiError {
+        ApiError::UsernameTaken(err)
+    }
+}
+
+impl From<Unauthenticated> for ApiError {
+    fn from(err: Unauthenticated) -> ApiError {
+        ApiError::Unauthenticated(err)
+    }
+}
+
+impl From<WeakPassword> for ApiError {
+    fn from(err: WeakPassword) -> ApiError {
+        ApiError::WeakPassword(err)
+    }
+}
+
+impl From<InvalidUsername> for ApiError {
+    fn from(err: InvalidUsername) -> ApiError {
+        ApiError::InvalidUsername(err)
+    }
+}
+
+impl From<InvalidOidcResponse> for ApiError {
+    fn from(err: InvalidOidcResponse) -> ApiError {
+        ApiError::InvalidOidcResponse(err)
+    }
+}
+
+impl From<UnknownOidcProvider> for ApiError {
+    fn from(err: UnknownOidcProvider) -> ApiError {
+        ApiError::UnknownOidcProvider(err)
+    }
+}
+
+impl From<InsufficientScope> for ApiError {
+    fn from(err: InsufficientScope) -> ApiError {
+        ApiError::InsufficientScope(err)
+    }
+}
+
+impl From<Forbidden> for ApiError {
+    fn from(err: Forbidden) -> ApiError {
+        ApiError::Forbidden(err)
+    }
+}
+
+impl From<CourseExists> for ApiError {
+    fn from(err: CourseExists) -> ApiError {
+        ApiError::CourseExists(err)
+    }
+}
+
+impl From<CsrfTokenMismatch> for ApiError {
+    fn from(err: CsrfTokenMismatch) -> ApiError {
+        ApiError::CsrfTokenMismatch(err)
+    }
+}
+
+impl From<SecondFactorRequired> for ApiError {
+    fn from(err: SecondFactorRequired) -> ApiError {
+        ApiError::SecondFactorRequired(err)
+    }
+}
+
+impl From<SecondFactorEnabled> for ApiError {
+    fn from(err: SecondFactorEnabled) -> ApiError {
+        ApiError::SecondFactorEnabled(err)
+    }
+}
+
+impl From<InvalidWebauthnResponse> for ApiError {
+    fn from(err: InvalidWebauthnResponse) -> ApiError {
+        ApiError::InvalidWebauthnResponse(err)
+    }
+}
+
+impl From<IdentityLinked> for ApiError {
+    fn from(err: IdentityLinked) -> ApiError {
+        ApiError::IdentityLinked(err)
+    }
+}
+
+impl From<OriginNotAllowed> for ApiError {
+    fn from(err: OriginNotAllowed) -> ApiError {
+        ApiError::OriginNotAllowed(err)
+    }
+}
+
+impl From<Impersonating> for ApiError {
+    fn from(err: Impersonating) -> ApiError {
+        ApiError::Impersonating(err)
+    }
+}
+
+impl From<LoginThrottled> for ApiError {
+    fn from(err: LoginThrottled) -> ApiError {
+        ApiError::LoginThrottled(err)
+    }
+}
+
+impl From<NoEmailAddress> for ApiError {
+    fn from(err: NoEmailAddress) -> ApiError {
+        ApiError::NoEmailAddress(err)
+    }
+}
+
+impl From<InvalidGrade> for ApiError {
+    fn from(err: InvalidGrade) -> ApiError {
+        ApiError::InvalidGrade(err)
+    }
+}
+
 impl From<JsonRejection> for ApiError {
     fn from(source: JsonRejection) -> ApiError {
         ApiError::JsonError(JsonError::new(source))
@@ -154,11 +434,11 @@ impl From<JsonRejection> for ApiError {
 #[derive(Debug, Serialize)]
 pub struct InternalError {
     #[serde(skip_serializing)]
-    source: Box<dyn Error>,
+    source: Box<dyn Error + Send + Sync>,
 }
 
 impl InternalError {
-    pub fn new(source: Box<dyn Error>) -> Self {
+    pub fn new(source: Box<dyn Error + Send + Sync>) -> Self {
         InternalError { source }
     }
     pub const fn status(&self) -> StatusCode {
@@ -386,3 +666,491 @@ impl Error for JsonError {
         Some(&self.source)
     }
 }
+
+#[derive(Debug, Serialize)]
+pub struct InvalidSamlResponse {
+    reason: String,
+}
+
+impl InvalidSamlResponse {
+    pub fn new(reason: String) -> Self {
+        InvalidSamlResponse { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for InvalidSamlResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for InvalidSamlResponse {}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidSamlRequest {
+    reason: String,
+}
+
+impl InvalidSamlRequest {
+    pub fn new(reason: String) -> Self {
+        InvalidSamlRequest { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for InvalidSamlRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for InvalidSamlRequest {}
+
+#[derive(Debug, Serialize)]
+pub struct UnknownIdentityProvider {
+    entity_id: String,
+}
+
+impl UnknownIdentityProvider {
+    pub fn new(entity_id: String) -> Self {
+        UnknownIdentityProvider { entity_id }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::NOT_FOUND
+    }
+}
+
+impl std::fmt::Display for UnknownIdentityProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.entity_id)
+    }
+}
+
+impl Error for UnknownIdentityProvider {}
+
+#[derive(Debug, Serialize)]
+pub struct UsernameTaken {
+    username: String,
+}
+
+impl UsernameTaken {
+    pub fn new(username: String) -> Self {
+        UsernameTaken { username }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::CONFLICT
+    }
+}
+
+impl std::fmt::Display for UsernameTaken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.username)
+    }
+}
+
+impl Error for UsernameTaken {}
+
+#[derive(Debug, Serialize)]
+pub struct Unauthenticated {
+    reason: String,
+}
+
+impl Unauthenticated {
+    pub fn new(reason: String) -> Self {
+        Unauthenticated { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+}
+
+impl std::fmt::Display for Unauthenticated {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for Unauthenticated {}
+
+#[derive(Debug, Serialize)]
+pub struct WeakPassword {
+    reason: String,
+}
+
+impl WeakPassword {
+    pub fn new(reason: String) -> Self {
+        WeakPassword { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for WeakPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for WeakPassword {}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidUsername {
+    reason: String,
+}
+
+impl InvalidUsername {
+    pub fn new(reason: String) -> Self {
+        InvalidUsername { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for InvalidUsername {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for InvalidUsername {}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidOidcResponse {
+    reason: String,
+}
+
+impl InvalidOidcResponse {
+    pub fn new(reason: String) -> Self {
+        InvalidOidcResponse { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for InvalidOidcResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for InvalidOidcResponse {}
+
+#[derive(Debug, Serialize)]
+pub struct UnknownOidcProvider {
+    provider: String,
+}
+
+impl UnknownOidcProvider {
+    pub fn new(provider: String) -> Self {
+        UnknownOidcProvider { provider }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::NOT_FOUND
+    }
+}
+
+impl std::fmt::Display for UnknownOidcProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.provider)
+    }
+}
+
+impl Error for UnknownOidcProvider {}
+
+#[derive(Debug, Serialize)]
+pub struct InsufficientScope {
+    scope: String,
+}
+
+impl InsufficientScope {
+    pub fn new(scope: String) -> Self {
+        InsufficientScope { scope }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+impl std::fmt::Display for InsufficientScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.scope)
+    }
+}
+
+impl Error for InsufficientScope {}
+
+#[derive(Debug, Serialize)]
+pub struct Forbidden {
+    permission: String,
+}
+
+impl Forbidden {
+    pub fn new(permission: String) -> Self {
+        Forbidden { permission }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+impl std::fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.permission)
+    }
+}
+
+impl Error for Forbidden {}
+
+#[derive(Debug, Serialize)]
+pub struct CourseExists {
+    slug: String,
+}
+
+impl CourseExists {
+    pub fn new(slug: String) -> Self {
+        CourseExists { slug }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::CONFLICT
+    }
+}
+
+impl std::fmt::Display for CourseExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.slug)
+    }
+}
+
+impl Error for CourseExists {}
+
+#[derive(Debug, Serialize)]
+pub struct CsrfTokenMismatch {
+    reason: String,
+}
+
+impl CsrfTokenMismatch {
+    pub fn new(reason: String) -> Self {
+        CsrfTokenMismatch { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+impl std::fmt::Display for CsrfTokenMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for CsrfTokenMismatch {}
+
+#[derive(Debug, Serialize)]
+pub struct SecondFactorRequired {
+    reason: String,
+}
+
+impl SecondFactorRequired {
+    pub fn new(reason: String) -> Self {
+        SecondFactorRequired { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+}
+
+impl std::fmt::Display for SecondFactorRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for SecondFactorRequired {}
+
+#[derive(Debug, Serialize)]
+pub struct SecondFactorEnabled {
+    username: String,
+}
+
+impl SecondFactorEnabled {
+    pub fn new(username: String) -> Self {
+        SecondFactorEnabled { username }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::CONFLICT
+    }
+}
+
+impl std::fmt::Display for SecondFactorEnabled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.username)
+    }
+}
+
+impl Error for SecondFactorEnabled {}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidWebauthnResponse {
+    reason: String,
+}
+
+impl InvalidWebauthnResponse {
+    pub fn new(reason: String) -> Self {
+        InvalidWebauthnResponse { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for InvalidWebauthnResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for InvalidWebauthnResponse {}
+
+#[derive(Debug, Serialize)]
+pub struct IdentityLinked {
+    subject: String,
+}
+
+impl IdentityLinked {
+    pub fn new(subject: String) -> Self {
+        IdentityLinked { subject }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::CONFLICT
+    }
+}
+
+impl std::fmt::Display for IdentityLinked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.subject)
+    }
+}
+
+impl Error for IdentityLinked {}
+
+#[derive(Debug, Serialize)]
+pub struct OriginNotAllowed {
+    origin: String,
+}
+
+impl OriginNotAllowed {
+    pub fn new(origin: String) -> Self {
+        OriginNotAllowed { origin }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+impl std::fmt::Display for OriginNotAllowed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.origin)
+    }
+}
+
+impl Error for OriginNotAllowed {}
+
+#[derive(Debug, Serialize)]
+pub struct Impersonating {
+    impersonator: String,
+}
+
+impl Impersonating {
+    pub fn new(impersonator: String) -> Self {
+        Impersonating { impersonator }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+impl std::fmt::Display for Impersonating {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.impersonator)
+    }
+}
+
+impl Error for Impersonating {}
+
+#[derive(Debug, Serialize)]
+pub struct LoginThrottled {
+    retry_after: u64,
+}
+
+impl LoginThrottled {
+    pub fn new(retry_after: u64) -> Self {
+        LoginThrottled { retry_after }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+    /// Seconds until another attempt may be made.
+    pub const fn retry_after(&self) -> u64 {
+        self.retry_after
+    }
+}
+
+impl std::fmt::Display for LoginThrottled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.retry_after)
+    }
+}
+
+impl Error for LoginThrottled {}
+
+#[derive(Debug, Serialize)]
+pub struct NoEmailAddress {
+    username: String,
+}
+
+impl NoEmailAddress {
+    pub fn new(username: String) -> Self {
+        NoEmailAddress { username }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::CONFLICT
+    }
+}
+
+impl std::fmt::Display for NoEmailAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.username)
+    }
+}
+
+impl Error for NoEmailAddress {}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidGrade {
+    reason: String,
+}
+
+impl InvalidGrade {
+    pub fn new(reason: String) -> Self {
+        InvalidGrade { reason }
+    }
+    pub const fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl std::fmt::Display for InvalidGrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for InvalidGrade {}
@@ -8,14 +8,25 @@ use crate::api::error::{MalformedHeader, MethodNotAllowed, MissingHeader, Resour
 use axum::{
     Json, Router,
     extract::OriginalUri,
-    http::{HeaderMap, Method},
+    http::{HeaderMap, Method, header},
+    middleware,
     response::{IntoResponse, Response},
-    routing::any,
+    routing::{any, get, post},
 };
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        (self.status(), Json(self)).into_response()
+        let retry_after = match &self {
+            ApiError::LoginThrottled(err) => Some(err.retry_after()),
+            _ => None,
+        };
+        let mut response = (self.status(), Json(self)).into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
     }
 }
 
@@ -39,8 +50,25 @@ async fn fallback(uri: OriginalUri) -> ApiError {
 
 pub fn routes() -> Router {
     Router::new()
+        .route("/user", get(crate::users::current))
+        .nest("/user/identities", crate::users::identities::routes())
+        .nest("/user/sessions", crate::users::devices::routes())
+        .route(
+            "/user/peer-reviews",
+            get(crate::forgejo::peer_review::assigned),
+        )
+        .nest("/users/{username}", crate::users::devices::admin_routes())
         .route("/ws", any(ws::handler))
+        .route("/ws/ticket", post(ws::ticket))
+        .nest("/courses", crate::courses::routes())
+        .route(
+            "/submissions/{id}/result",
+            post(crate::courses::submissions::report),
+        )
+        .nest("/tokens", crate::auth::token::routes())
+        .nest("/lockouts", crate::auth::throttle::routes())
         .nest("/forgejo", crate::forgejo::routes())
         .method_not_allowed_fallback(method_not_allowed_fallback)
         .fallback(fallback)
+        .layer(middleware::from_fn(crate::auth::token::bearer))
 }
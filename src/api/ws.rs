@@ -1,24 +1,161 @@
-use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    response::IntoResponse,
+//! The WebSocket endpoint.
+//!
+//! Upgrades are authenticated by session cookie or by a ticket from
+//! `POST /api/ws/ticket`, for clients that cannot send cookies along.
+//! Tickets are good for one upgrade within [`TICKET_LIFETIME`]. Since
+//! browsers attach cookies to WebSocket handshakes from any site, upgrades
+//! carrying an `Origin` have to come from one of `WS_ALLOWED_ORIGINS`
+//! (space-separated, by default the origin of `BASE_URL`), and upgrades
+//! authenticated by cookie have to carry one.
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{InternalError, OriginNotAllowed, Unauthenticated},
+    },
+    auth::{
+        session::{CurrentUser, random_token},
+        token::{self, Scoped},
+    },
+    users::User,
 };
 
+use axum::{
+    Extension, Json,
+    extract::{
+        Query,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
 use futures::{sink::SinkExt, stream::StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+use url::Url;
+
+pub const TICKET_LIFETIME: Duration = Duration::from_secs(30);
+
+fn allowed_origins() -> ApiResult<Vec<String>> {
+    if let Ok(origins) = std::env::var("WS_ALLOWED_ORIGINS") {
+        return Ok(origins.split_whitespace().map(str::to_string).collect());
+    }
+    let base_url = Url::parse(&std::env::var("BASE_URL")?).map_err(|err| {
+        ApiError::InternalError(InternalError::new(format!("bad BASE_URL: {err}").into()))
+    })?;
+    Ok(vec![base_url.origin().ascii_serialization()])
+}
+
+/// Checks the `Origin` of an upgrade; only ticket holders may leave it out.
+fn check_origin(origin: Option<&str>, allowed: &[String], ticket: bool) -> ApiResult<()> {
+    match origin {
+        Some(origin) if allowed.iter().any(|allowed| allowed == origin) => Ok(()),
+        Some(origin) => Err(OriginNotAllowed::new(origin.to_string()).into()),
+        None if ticket => Ok(()),
+        None => Err(OriginNotAllowed::new("none".to_string()).into()),
+    }
+}
 
-pub async fn handler(ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| callback(socket))
+#[derive(Debug, Serialize)]
+pub struct Ticket {
+    ticket: String,
+    /// Seconds the ticket can be used for.
+    expires_in: u64,
 }
 
-async fn callback(socket: WebSocket) {
+pub async fn ticket(
+    Extension(pool): Extension<PgPool>,
+    Scoped(user, _): Scoped<token::require::ReadCourses>,
+) -> ApiResult<Response> {
+    sqlx::query("DELETE FROM ws_tickets WHERE created_at < now() - $1")
+        .bind(TICKET_LIFETIME)
+        .execute(&pool)
+        .await?;
+    let ticket = random_token();
+    sqlx::query("INSERT INTO ws_tickets (ticket_hash, user_id) VALUES ($1, $2)")
+        .bind(token::hash(&ticket))
+        .bind(user.id)
+        .execute(&pool)
+        .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(Ticket {
+            ticket,
+            expires_in: TICKET_LIFETIME.as_secs(),
+        }),
+    )
+        .into_response())
+}
+
+/// Uses up a ticket and returns its user, unless they have been deactivated
+/// since.
+async fn redeem(pool: &PgPool, ticket: &str) -> ApiResult<User> {
+    let user = sqlx::query_as(
+        "WITH redeemed AS (
+             DELETE FROM ws_tickets WHERE ticket_hash = $1 AND created_at > now() - $2
+             RETURNING user_id
+         )
+         SELECT users.id, users.username, users.email, users.display_name, users.role
+         FROM redeemed JOIN users ON users.id = redeemed.user_id AND users.active",
+    )
+    .bind(token::hash(ticket))
+    .bind(TICKET_LIFETIME)
+    .fetch_optional(pool)
+    .await?;
+    Ok(user.ok_or_else(|| Unauthenticated::new("invalid or expired ticket".to_string()))?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpgradeQuery {
+    ticket: Option<String>,
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    Query(query): Query<UpgradeQuery>,
+    session: Result<CurrentUser, ApiError>,
+    ws: WebSocketUpgrade,
+) -> ApiResult<Response> {
+    let origin = headers
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok());
+    check_origin(origin, &allowed_origins()?, query.ticket.is_some())?;
+    let user = match &query.ticket {
+        Some(ticket) => redeem(&pool, ticket).await?,
+        None => session?.0,
+    };
+    tracing::debug!(user = user.username, "WebSocket connected");
+    Ok(ws.on_upgrade(move |socket| callback(socket, user)))
+}
+
+async fn callback(socket: WebSocket, user: User) {
     let (mut sender, mut receiver) = socket.split();
-    loop {
-        let message = receiver.next().await.unwrap().unwrap();
+    while let Some(Ok(message)) = receiver.next().await {
         match &message {
-            Message::Text(_) => match sender.send(message).await {
-                Ok(_) => (),
-                Err(_) => break,
-            },
+            Message::Text(_) => {
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
             _ => break,
         }
     }
+    tracing::debug!(user = user.username, "WebSocket disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        let allowed = vec!["https://ceresforge.example.edu".to_string()];
+        assert!(check_origin(Some("https://ceresforge.example.edu"), &allowed, false).is_ok());
+        assert!(check_origin(Some("https://evil.example"), &allowed, false).is_err());
+        assert!(check_origin(Some("https://evil.example"), &allowed, true).is_err());
+        assert!(check_origin(None, &allowed, false).is_err());
+        assert!(check_origin(None, &allowed, true).is_ok());
+    }
 }
@@ -0,0 +1,22 @@
+//! Audit log of security relevant events, kept in the database and mirrored
+//! to the `audit` tracing target.
+
+use crate::api::ApiResult;
+
+use sqlx::PgPool;
+
+pub async fn record(
+    pool: &PgPool,
+    user_id: Option<i64>,
+    action: &str,
+    detail: &str,
+) -> ApiResult<()> {
+    tracing::info!(target: "audit", user_id, action, detail);
+    sqlx::query("INSERT INTO audit_log (user_id, action, detail) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(action)
+        .bind(detail)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
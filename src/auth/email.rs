@@ -0,0 +1,307 @@
+//! Verifying email addresses and resetting forgotten passwords by mail.
+//!
+//! Both work with single-use tokens mailed to the user, which are only good
+//! for the address they were sent to and expire after
+//! [`VERIFICATION_LIFETIME`] and [`RESET_LIFETIME`]. Passwords can only be
+//! reset through a verified address, and only while local accounts are
+//! enabled. Users with a verified address are also told of every login to
+//! their account. None of this is available without mail; see
+//! [`crate::mail`].
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{InternalError, NoEmailAddress, ResourceNotFound, Unauthenticated, WeakPassword},
+    },
+    audit,
+    auth::{
+        local::{self, Mode, PasswordPolicy},
+        session::{self, OwnUser, random_token},
+        token,
+    },
+    mail::{self, Email},
+    users::User,
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, Query, rejection::JsonRejection},
+    http::StatusCode,
+    response::{Html, Redirect},
+    routing::{get, post},
+};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use std::time::Duration;
+
+pub const VERIFICATION_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+pub const RESET_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// How long after mailing a user a token no other one of the same purpose
+/// is sent, so that nobody can flood their inbox.
+const RESEND_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "email_token_purpose", rename_all = "snake_case")]
+enum Purpose {
+    Verification,
+    PasswordReset,
+}
+
+impl Purpose {
+    fn lifetime(&self) -> Duration {
+        match self {
+            Purpose::Verification => VERIFICATION_LIFETIME,
+            Purpose::PasswordReset => RESET_LIFETIME,
+        }
+    }
+}
+
+/// Returns the absolute URL of `path`.
+fn link(path: &str) -> ApiResult<String> {
+    let base_url = std::env::var("BASE_URL")?;
+    Ok(format!("{}{path}", base_url.trim_end_matches('/')))
+}
+
+/// Rejects the request as if the route did not exist unless mail is on.
+fn require_mail(uri: &OriginalUri) -> ApiResult<()> {
+    if mail::enabled() {
+        Ok(())
+    } else {
+        Err(ResourceNotFound::new(uri.to_string()).into())
+    }
+}
+
+/// Creates a token for mailing to `email`, unless one was sent moments ago.
+async fn issue(
+    pool: &PgPool,
+    user_id: i64,
+    purpose: Purpose,
+    email: &str,
+) -> ApiResult<Option<String>> {
+    sqlx::query(
+        "DELETE FROM email_tokens
+         WHERE user_id = $1 AND purpose = $2 AND created_at < now() - $3",
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .bind(purpose.lifetime())
+    .execute(pool)
+    .await?;
+    let recent: bool = sqlx::query_scalar(
+        "SELECT EXISTS (
+             SELECT 1 FROM email_tokens
+             WHERE user_id = $1 AND purpose = $2 AND created_at > now() - $3
+         )",
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .bind(RESEND_INTERVAL)
+    .fetch_one(pool)
+    .await?;
+    if recent {
+        return Ok(None);
+    }
+    let token = random_token();
+    sqlx::query(
+        "INSERT INTO email_tokens (token_hash, user_id, purpose, email)
+         VALUES ($1, $2, $3, $4)",
+    )
+    .bind(token::hash(&token))
+    .bind(user_id)
+    .bind(purpose)
+    .bind(email)
+    .execute(pool)
+    .await?;
+    Ok(Some(token))
+}
+
+/// Uses up a token and returns its user, if it has not expired and their
+/// email address is still the one it was sent to.
+async fn redeem(conn: &mut PgConnection, purpose: Purpose, token: &str) -> ApiResult<User> {
+    let user = sqlx::query_as(
+        "WITH redeemed AS (
+             DELETE FROM email_tokens
+             WHERE token_hash = $1 AND purpose = $2 AND created_at > now() - $3
+             RETURNING user_id, email
+         )
+         SELECT users.id, users.username, users.email, users.display_name, users.role
+         FROM redeemed
+         JOIN users ON users.id = redeemed.user_id AND users.email = redeemed.email
+         WHERE users.active",
+    )
+    .bind(token::hash(token))
+    .bind(purpose)
+    .bind(purpose.lifetime())
+    .fetch_optional(conn)
+    .await?;
+    Ok(user.ok_or_else(|| Unauthenticated::new("invalid or expired token".to_string()))?)
+}
+
+/// Returns the email address of a user if it is verified.
+async fn verified_email(pool: &PgPool, user_id: i64) -> ApiResult<Option<String>> {
+    Ok(sqlx::query_scalar(
+        "SELECT verified_email FROM users WHERE id = $1 AND verified_email = email",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Tells a user with a verified email address that their account was just
+/// logged in to from `addr`.
+pub async fn notify_login(pool: &PgPool, user: &User, addr: &str) -> ApiResult<()> {
+    if let Some(email) = verified_email(pool, user.id).await? {
+        mail::send(
+            &email,
+            &Email::Login {
+                username: &user.username,
+                addr,
+            },
+        );
+    }
+    Ok(())
+}
+
+async fn request_verification(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    OwnUser(user): OwnUser,
+) -> ApiResult<StatusCode> {
+    require_mail(&uri)?;
+    let email = user
+        .email
+        .ok_or_else(|| NoEmailAddress::new(user.username.clone()))?;
+    if verified_email(&pool, user.id).await?.is_some() {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    if let Some(token) = issue(&pool, user.id, Purpose::Verification, &email).await? {
+        let link = link(&format!("/auth/email/verification?token={token}"))?;
+        mail::send(
+            &email,
+            &Email::Verification {
+                username: &user.username,
+                link: &link,
+            },
+        );
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct Verification {
+    token: String,
+}
+
+/// Where the link in a verification mail leads.
+async fn verify(
+    Extension(pool): Extension<PgPool>,
+    Query(verification): Query<Verification>,
+) -> ApiResult<Redirect> {
+    let mut tx = pool.begin().await?;
+    let user = redeem(&mut tx, Purpose::Verification, &verification.token).await?;
+    sqlx::query("UPDATE users SET verified_email = email, updated_at = now() WHERE id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    audit::record(
+        &pool,
+        Some(user.id),
+        "email_verified",
+        user.email.as_deref().unwrap_or_default(),
+    )
+    .await?;
+    Ok(Redirect::to("/"))
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetRequest {
+    username: String,
+}
+
+/// Mails a password reset link if the user has a verified email address.
+/// Answers the same either way, so as not to reveal who has an account.
+async fn request_reset(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    payload: Result<Json<ResetRequest>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    Mode::require(&uri, |mode| mode != Mode::Disabled)?;
+    require_mail(&uri)?;
+    let Json(request) = payload?;
+    let recipient: Option<(i64, String)> = sqlx::query_as(
+        "SELECT id, verified_email FROM users
+         WHERE username = $1 AND active AND verified_email = email",
+    )
+    .bind(&request.username)
+    .fetch_optional(&pool)
+    .await?;
+    let Some((user_id, email)) = recipient else {
+        tracing::info!(username = request.username, "no verified address for reset");
+        return Ok(StatusCode::NO_CONTENT);
+    };
+    if let Some(token) = issue(&pool, user_id, Purpose::PasswordReset, &email).await? {
+        // In the fragment, the token stays out of server and proxy logs.
+        let link = link(&format!("/auth/email/reset#{token}"))?;
+        mail::send(
+            &email,
+            &Email::PasswordReset {
+                username: &request.username,
+                link: &link,
+            },
+        );
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The page a password reset link leads to.
+async fn reset_page() -> Html<&'static str> {
+    Html(include_str!("../../frontend/password-reset.html"))
+}
+
+#[derive(Debug, Deserialize)]
+struct Reset {
+    token: String,
+    new_password: String,
+}
+
+async fn reset(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    payload: Result<Json<Reset>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    Mode::require(&uri, |mode| mode != Mode::Disabled)?;
+    let Json(reset) = payload?;
+    // The token is only used up if the new password is good.
+    let mut tx = pool.begin().await?;
+    let user = redeem(&mut tx, Purpose::PasswordReset, &reset.token).await?;
+    PasswordPolicy::from_env()
+        .map_err(|err| ApiError::InternalError(InternalError::new(err.into())))?
+        .check(&user.username, &reset.new_password)
+        .map_err(WeakPassword::new)?;
+
+    let password_hash = local::hash_password(reset.new_password).await?;
+    sqlx::query("UPDATE users SET password_hash = $2, updated_at = now() WHERE id = $1")
+        .bind(user.id)
+        .bind(&password_hash)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM email_tokens WHERE user_id = $1 AND purpose = $2")
+        .bind(user.id)
+        .bind(Purpose::PasswordReset)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    // Whoever may have known the old password loses access along with it.
+    session::end_others(&pool, user.id, None).await?;
+    audit::record(&pool, Some(user.id), "password_reset", &user.username).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/verification", get(verify).post(request_verification))
+        .route("/reset", get(reset_page).post(request_reset))
+        .route("/reset/confirm", post(reset))
+}
@@ -0,0 +1,202 @@
+//! Viewing the site as another user, to see what they see.
+//!
+//! Admins may impersonate anyone but other admins, instructors the students
+//! of courses they teach. Impersonating swaps the user a session stands for
+//! while remembering who logged in, so every request made as the
+//! impersonated user is written to the audit log under the impersonator.
+//! Sensitive actions, like changing credentials, are refused; see
+//! [`OwnUser`].
+
+use crate::{
+    api::{
+        ApiResult,
+        error::{Forbidden, ResourceNotFound, Unauthenticated},
+    },
+    audit,
+    auth::session::{self, CurrentUser, OwnUser},
+    users::{Role, User},
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode, request::Parts},
+    routing::post,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// Who is impersonating the current user. Extractors authenticating an
+/// impersonated session add it to the request's extensions.
+#[derive(Clone, Debug, Serialize)]
+pub struct Impersonator {
+    pub id: i64,
+    pub username: String,
+}
+
+/// Records a request made while `impersonator` impersonates `user`.
+pub async fn audit(
+    pool: &PgPool,
+    parts: &Parts,
+    impersonator: &Impersonator,
+    user: &User,
+) -> ApiResult<()> {
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |uri| &uri.0);
+    audit::record(
+        pool,
+        Some(impersonator.id),
+        "impersonated_request",
+        &format!("as {}: {} {uri}", user.username, parts.method),
+    )
+    .await
+}
+
+/// Decides whether `actor` may impersonate `target`; `teaches` is whether
+/// `target` is a student in a course `actor` is an instructor of.
+pub fn allows(actor: &User, target: &User, teaches: bool) -> bool {
+    if actor.id == target.id || target.role == Role::Admin {
+        return false;
+    }
+    match actor.role {
+        Role::Admin => true,
+        Role::Instructor => target.role == Role::Student && teaches,
+        Role::Student | Role::Ta => false,
+    }
+}
+
+async fn teaches(pool: &PgPool, instructor_id: i64, student_id: i64) -> ApiResult<bool> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS (
+             SELECT 1 FROM course_members teacher
+             JOIN course_members student ON student.course_id = teacher.course_id
+             WHERE teacher.user_id = $1 AND teacher.role = 'instructor'
+               AND student.user_id = $2 AND student.role = 'student'
+         )",
+    )
+    .bind(instructor_id)
+    .bind(student_id)
+    .fetch_one(pool)
+    .await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct Start {
+    username: String,
+}
+
+async fn start(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    OwnUser(user): OwnUser,
+    payload: Result<Json<Start>, JsonRejection>,
+) -> ApiResult<Json<User>> {
+    let Json(start) = payload?;
+    // Personal access tokens cannot impersonate; only sessions can.
+    let id = session::from_headers(&headers)?
+        .ok_or_else(|| Unauthenticated::new("not logged in".to_string()))?;
+    let target: User = sqlx::query_as(
+        "SELECT id, username, email, display_name, role FROM users WHERE username = $1",
+    )
+    .bind(&start.username)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?;
+    let teaches = user.role == Role::Instructor && teaches(&pool, user.id, target.id).await?;
+    if !allows(&user, &target, teaches) {
+        audit::record(
+            &pool,
+            Some(user.id),
+            "permission_denied",
+            &format!("impersonate {}", target.username),
+        )
+        .await?;
+        return Err(Forbidden::new("impersonate".to_string()).into());
+    }
+    let started = sqlx::query(
+        "UPDATE sessions SET impersonating_id = $3
+         WHERE id = $1 AND user_id = $2 AND NOT restricted",
+    )
+    .bind(id)
+    .bind(user.id)
+    .bind(target.id)
+    .execute(&pool)
+    .await?;
+    if started.rows_affected() == 0 {
+        return Err(Unauthenticated::new("not logged in".to_string()).into());
+    }
+    audit::record(
+        &pool,
+        Some(user.id),
+        "impersonation_started",
+        &target.username,
+    )
+    .await?;
+    Ok(Json(target))
+}
+
+async fn stop(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    CurrentUser(user): CurrentUser,
+    impersonator: Option<Extension<Impersonator>>,
+) -> ApiResult<StatusCode> {
+    let Some(Extension(impersonator)) = impersonator else {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    };
+    let id = session::from_headers(&headers)?
+        .ok_or_else(|| Unauthenticated::new("not logged in".to_string()))?;
+    sqlx::query("UPDATE sessions SET impersonating_id = NULL WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await?;
+    audit::record(
+        &pool,
+        Some(impersonator.id),
+        "impersonation_ended",
+        &user.username,
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new().route("/", post(start).delete(stop))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, role: Role) -> User {
+        User {
+            id,
+            username: format!("user{id}"),
+            email: None,
+            display_name: None,
+            role,
+        }
+    }
+
+    #[test]
+    fn who_may_impersonate() {
+        let admin = user(1, Role::Admin);
+        let instructor = user(2, Role::Instructor);
+        let student = user(3, Role::Student);
+
+        assert!(allows(&admin, &instructor, false));
+        assert!(allows(&admin, &student, false));
+        assert!(!allows(&admin, &user(4, Role::Admin), false));
+        assert!(!allows(&admin, &admin, false));
+
+        // Instructors only see as the students they teach.
+        assert!(allows(&instructor, &student, true));
+        assert!(!allows(&instructor, &student, false));
+        assert!(!allows(&instructor, &user(5, Role::Ta), true));
+        assert!(!allows(&student, &user(6, Role::Student), true));
+    }
+}
@@ -0,0 +1,156 @@
+//! Job tokens for grading runners.
+//!
+//! Runners grading a submission execute untrusted code, so they are handed
+//! no lasting secret, only a token good for reporting the result of that
+//! one submission until the job times out. The token carries its expiry
+//! time and an HMAC-SHA256 under `JOB_TOKEN_SECRET` of that and the one
+//! path it may be used on, so it needs no storage and cannot be turned
+//! against another submission or endpoint. Handlers accept it with the
+//! [`JobToken`] extractor.
+
+use crate::api::{ApiError, ApiResult, error::Unauthenticated};
+
+use axum::{
+    extract::{FromRequestParts, OriginalUri},
+    http::{header, request::Parts},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prefix telling job tokens apart from personal access tokens.
+pub const PREFIX: &str = "cfjob_";
+
+fn secret() -> ApiResult<String> {
+    Ok(std::env::var("JOB_TOKEN_SECRET")?)
+}
+
+/// Returns the seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn mac(secret: &str, path: &str, expires_at: u64) -> ApiResult<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("{path}\n{expires_at}").as_bytes());
+    Ok(mac)
+}
+
+fn sign(secret: &str, path: &str, expires_at: u64) -> ApiResult<String> {
+    let signature: String = mac(secret, path, expires_at)?
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(format!("{PREFIX}{expires_at}.{signature}"))
+}
+
+/// Returns a token for requests to `path` until `expires_at`, in seconds
+/// since the Unix epoch.
+pub fn issue(path: &str, expires_at: u64) -> ApiResult<String> {
+    sign(&secret()?, path, expires_at)
+}
+
+/// Checks a token for a request to `path` at time `now`, returning why it
+/// is not good if it is not.
+fn verify(secret: &str, token: &str, path: &str, now: u64) -> ApiResult<Result<(), &'static str>> {
+    let invalid = Ok(Err("invalid job token"));
+    let Some((expires_at, signature)) = token
+        .strip_prefix(PREFIX)
+        .and_then(|token| token.split_once('.'))
+    else {
+        return invalid;
+    };
+    let Ok(expires_at) = expires_at.parse::<u64>() else {
+        return invalid;
+    };
+    let Some(signature) = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+    else {
+        return invalid;
+    };
+    if mac(secret, path, expires_at)?
+        .verify_slice(&signature)
+        .is_err()
+    {
+        return invalid;
+    }
+    if expires_at <= now {
+        return Ok(Err("job token has expired"));
+    }
+    Ok(Ok(()))
+}
+
+/// Accepts a request carrying a job token for its own path, rejecting it
+/// with [`Unauthenticated`] otherwise. Unlike other extractors, it stands
+/// for no user.
+#[derive(Debug)]
+pub struct JobToken;
+
+impl<S> FromRequestParts<S> for JobToken
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or_else(|| Unauthenticated::new("no job token".to_string()))?;
+        let path = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or(&parts.uri, |uri| &uri.0)
+            .path();
+        verify(&secret()?, token, path, now())?.map_err(|reason| {
+            tracing::info!(path, reason, "rejected job token");
+            Unauthenticated::new(reason.to_string())
+        })?;
+        Ok(JobToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_to_path_and_time() {
+        let path = "/api/submissions/7/result";
+        let token = sign("secret", path, 1000).unwrap();
+        assert!(token.starts_with("cfjob_1000."));
+        assert_eq!(verify("secret", &token, path, 999).unwrap(), Ok(()));
+        assert_eq!(
+            verify("secret", &token, path, 1000).unwrap(),
+            Err("job token has expired")
+        );
+        assert_eq!(
+            verify("secret", &token, "/api/submissions/8/result", 999).unwrap(),
+            Err("invalid job token")
+        );
+        assert_eq!(
+            verify("other secret", &token, path, 999).unwrap(),
+            Err("invalid job token")
+        );
+        // Pushing the expiry back breaks the signature.
+        let extended = token.replacen("1000", "9000", 1);
+        assert_eq!(
+            verify("secret", &extended, path, 999).unwrap(),
+            Err("invalid job token")
+        );
+        assert_eq!(
+            verify("secret", "cfpat_abc", path, 999).unwrap(),
+            Err("invalid job token")
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Linking another identity to the account of a logged in user.
+//!
+//! The user starts a login at a SAML or OpenID provider with `link=true`,
+//! which records who they are with the outstanding request. The provider's
+//! answer proves the identity, but arrives by a cross-site request that
+//! carries no session cookie, so it is only kept as a pending link. The
+//! browser is then sent to [`confirm`], where the session has to belong to
+//! the user who started linking. Without that check, someone could start
+//! linking and have a victim complete the login, attaching the victim's
+//! identity to their account.
+
+use crate::{
+    api::{ApiResult, error::Forbidden},
+    audit,
+    auth::{
+        saml::local_path,
+        session::{OwnUser, random_token},
+    },
+    users::{Identity, IdentityKind, identities},
+};
+
+use axum::{
+    Extension,
+    extract::Query,
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+use sqlx::{FromRow, PgPool};
+
+/// Keeps an identity proven by a linking login and returns where to send
+/// the browser to confirm it.
+pub async fn start(
+    pool: &PgPool,
+    user_id: i64,
+    identity: &Identity,
+    return_to: Option<&str>,
+) -> ApiResult<String> {
+    sqlx::query("DELETE FROM pending_links WHERE created_at < now() - interval '10 minutes'")
+        .execute(pool)
+        .await?;
+    let token = random_token();
+    sqlx::query(
+        "INSERT INTO pending_links
+             (token, user_id, kind, issuer, subject, display_name, return_to)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&token)
+    .bind(user_id)
+    .bind(identity.kind)
+    .bind(&identity.issuer)
+    .bind(&identity.subject)
+    .bind(&identity.display_name)
+    .bind(local_path(return_to))
+    .execute(pool)
+    .await?;
+    Ok(format!("/auth/link?token={token}"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmQuery {
+    token: String,
+}
+
+#[derive(Debug, FromRow)]
+struct Pending {
+    user_id: i64,
+    kind: IdentityKind,
+    issuer: String,
+    subject: String,
+    display_name: Option<String>,
+    return_to: Option<String>,
+}
+
+pub async fn confirm(
+    Extension(pool): Extension<PgPool>,
+    OwnUser(user): OwnUser,
+    Query(query): Query<ConfirmQuery>,
+) -> ApiResult<Response> {
+    let pending: Option<Pending> = sqlx::query_as(
+        "DELETE FROM pending_links
+         WHERE token = $1 AND created_at > now() - interval '10 minutes'
+         RETURNING user_id, kind, issuer, subject, display_name, return_to",
+    )
+    .bind(&query.token)
+    .fetch_optional(&pool)
+    .await?;
+    let Some(pending) = pending.filter(|pending| pending.user_id == user.id) else {
+        tracing::warn!(user = user.username, "refused identity link");
+        return Err(Forbidden::new("link_identity".to_string()).into());
+    };
+    let identity = Identity {
+        kind: pending.kind,
+        issuer: pending.issuer,
+        subject: pending.subject,
+        display_name: pending.display_name,
+    };
+    let mut conn = pool.acquire().await?;
+    identities::insert(&mut conn, user.id, &identity).await?;
+    audit::record(
+        &pool,
+        Some(user.id),
+        "identity_linked",
+        &format!(
+            "{} {} {}",
+            identity.kind.as_str(),
+            identity.issuer,
+            identity.subject
+        ),
+    )
+    .await?;
+    Ok(Redirect::to(pending.return_to.as_deref().unwrap_or("/")).into_response())
+}
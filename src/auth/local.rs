@@ -0,0 +1,389 @@
+//! Local accounts, for users without an account at any identity provider.
+//!
+//! Passwords are hashed with Argon2id. `LOCAL_ACCOUNTS` decides what is
+//! allowed: `open` (the default) lets anyone register, `closed` only lets
+//! existing users log in, and `disabled` turns local accounts off. A user
+//! provisioned through SAML may set a password as well, after which both
+//! ways of logging in lead to the same user.
+//!
+//! The password policy is configured with `PASSWORD_MIN_LENGTH` (default
+//! 12), `PASSWORD_MAX_LENGTH` (default 128) and `PASSWORD_MIN_CLASSES`, the
+//! number of character classes (lowercase and uppercase letters, digits and
+//! everything else) a password has to mix (default 1).
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{InternalError, InvalidUsername, ResourceNotFound, Unauthenticated, WeakPassword},
+    },
+    auth::{
+        session::{self, AuthMethod, Origin, OwnUser},
+        throttle::{Attempt, ClientAddr},
+        totp::{self, SecondFactor},
+    },
+    users::{self, Profile, Role, User},
+};
+
+use argon2::{
+    Argon2, PasswordHasher, PasswordVerifier,
+    password_hash::{PasswordHash, SaltString},
+};
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::Deserialize;
+use sqlx::{FromRow, PgPool};
+use std::sync::LazyLock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Open,
+    Closed,
+    Disabled,
+}
+
+impl Mode {
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("LOCAL_ACCOUNTS").as_deref() {
+            Err(_) | Ok("open") => Ok(Mode::Open),
+            Ok("closed") => Ok(Mode::Closed),
+            Ok("disabled") => Ok(Mode::Disabled),
+            Ok(mode) => Err(format!("unknown LOCAL_ACCOUNTS mode {mode}")),
+        }
+    }
+
+    /// Rejects the request as if the route did not exist unless `allowed`.
+    pub fn require(uri: &OriginalUri, allowed: impl Fn(Mode) -> bool) -> ApiResult<()> {
+        let mode = Mode::from_env()
+            .map_err(|err| ApiError::InternalError(InternalError::new(err.into())))?;
+        if allowed(mode) {
+            Ok(())
+        } else {
+            Err(ResourceNotFound::new(uri.to_string()).into())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub min_classes: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: 128,
+            min_classes: 1,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Result<Self, String> {
+        let mut policy = PasswordPolicy::default();
+        for (var, setting) in [
+            ("PASSWORD_MIN_LENGTH", &mut policy.min_length),
+            ("PASSWORD_MAX_LENGTH", &mut policy.max_length),
+            ("PASSWORD_MIN_CLASSES", &mut policy.min_classes),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                *setting = value
+                    .parse()
+                    .map_err(|_| format!("{var} is not a number: {value}"))?;
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Checks a new password of `username` against the policy.
+    pub fn check(&self, username: &str, password: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(format!(
+                "password must be at least {} characters long",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            return Err(format!(
+                "password must be at most {} characters long",
+                self.max_length
+            ));
+        }
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_numeric()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ];
+        if classes.iter().filter(|&&class| class).count() < self.min_classes {
+            return Err(format!(
+                "password must mix at least {} of lowercase letters, uppercase letters, digits and other characters",
+                self.min_classes
+            ));
+        }
+        if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+            return Err("password must not contain the username".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a username can be used for repositories in Forgejo as well.
+fn check_username(username: &str) -> Result<(), String> {
+    if username.is_empty() || username.len() > 40 {
+        return Err("username must be 1 to 40 characters long".to_string());
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err("username may only contain letters, digits, '-', '_' and '.'".to_string());
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("username must start with a letter or digit".to_string());
+    }
+    Ok(())
+}
+
+fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())?;
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+fn verify(password: &str, hash: &str) -> Result<bool, argon2::password_hash::Error> {
+    let hash = PasswordHash::new(hash)?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok())
+}
+
+/// Checked instead when there is no password to check against, so that
+/// unknown usernames take as long to reject as wrong passwords.
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash("no such password").expect("hashing a constant password"));
+
+/// Hashes off the async runtime; Argon2 is slow on purpose.
+pub async fn hash_password(password: String) -> ApiResult<String> {
+    Ok(tokio::task::spawn_blocking(move || hash(&password)).await??)
+}
+
+async fn verify_password(password: String, hash: Option<String>) -> ApiResult<bool> {
+    Ok(tokio::task::spawn_blocking(move || match hash {
+        Some(hash) => verify(&password, &hash),
+        None => verify(&password, &DUMMY_HASH).map(|_| false),
+    })
+    .await??)
+}
+
+#[derive(Debug, FromRow)]
+struct Credentials {
+    #[sqlx(flatten)]
+    user: User,
+    password_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Registration {
+    username: String,
+    password: String,
+    email: Option<String>,
+    display_name: Option<String>,
+}
+
+async fn register(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    ClientAddr(addr): ClientAddr,
+    payload: Result<Json<Registration>, JsonRejection>,
+) -> ApiResult<Response> {
+    Mode::require(&uri, |mode| mode == Mode::Open)?;
+    let Json(registration) = payload?;
+    check_username(&registration.username).map_err(InvalidUsername::new)?;
+    PasswordPolicy::from_env()
+        .map_err(|err| ApiError::InternalError(InternalError::new(err.into())))?
+        .check(&registration.username, &registration.password)
+        .map_err(WeakPassword::new)?;
+
+    let password_hash = hash_password(registration.password).await?;
+    let profile = Profile {
+        username: registration.username,
+        email: registration.email,
+        display_name: registration.display_name,
+        role: Role::Student,
+    };
+    let user = users::create_local(&pool, &profile, &password_hash).await?;
+    let origin = Origin {
+        method: AuthMethod::Password,
+        addr: &addr,
+    };
+    let cookie = session::create(&pool, &headers, user.id, origin, None).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(header::SET_COOKIE, cookie)],
+        Json(user),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+    /// Needed once the user has set up two-factor authentication.
+    #[serde(flatten)]
+    second_factor: SecondFactor,
+}
+
+async fn login(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    addr: ClientAddr,
+    payload: Result<Json<Login>, JsonRejection>,
+) -> ApiResult<Response> {
+    Mode::require(&uri, |mode| mode != Mode::Disabled)?;
+    let Json(login) = payload?;
+    let attempt = Attempt::begin(&pool, addr, Some(&login.username)).await?;
+    let credentials: Option<Credentials> = sqlx::query_as(
+        "SELECT id, username, email, display_name, role, password_hash
+         FROM users WHERE username = $1",
+    )
+    .bind(&login.username)
+    .fetch_optional(&pool)
+    .await?;
+
+    let (user, password_hash) = match credentials {
+        Some(credentials) => (Some(credentials.user), credentials.password_hash),
+        None => (None, None),
+    };
+    let valid = verify_password(login.password, password_hash).await?;
+    let user = match user.filter(|_| valid) {
+        Some(user) => Ok(user),
+        None => {
+            tracing::info!(username = login.username, "failed local login");
+            Err(Unauthenticated::new("invalid username or password".to_string()).into())
+        }
+    };
+    let user = attempt.record(&pool, user).await?;
+    let restricted = totp::check(&pool, &user, &login.second_factor).await;
+    let restricted = attempt.record(&pool, restricted).await?;
+    attempt.succeed(&pool, &user).await?;
+    let origin = Origin {
+        method: AuthMethod::Password,
+        addr: attempt.addr(),
+    };
+    let cookie = if restricted {
+        tracing::info!(user = user.username, "second factor has to be set up");
+        session::create_restricted(&pool, &headers, user.id, origin).await?
+    } else {
+        session::create(&pool, &headers, user.id, origin, None).await?
+    };
+    Ok(([(header::SET_COOKIE, cookie)], Json(user)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct PasswordChange {
+    /// Required unless the user has no password yet, like users who have
+    /// only logged in through SAML.
+    current_password: Option<String>,
+    new_password: String,
+}
+
+async fn change_password(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    OwnUser(user): OwnUser,
+    payload: Result<Json<PasswordChange>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    Mode::require(&uri, |mode| mode != Mode::Disabled)?;
+    let Json(change) = payload?;
+    let password_hash: Option<String> =
+        sqlx::query_scalar("SELECT password_hash FROM users WHERE id = $1")
+            .bind(user.id)
+            .fetch_one(&pool)
+            .await?;
+    if password_hash.is_some() {
+        let current = change.current_password.unwrap_or_default();
+        if !verify_password(current, password_hash).await? {
+            return Err(Unauthenticated::new("current password is incorrect".to_string()).into());
+        }
+    }
+    PasswordPolicy::from_env()
+        .map_err(|err| ApiError::InternalError(InternalError::new(err.into())))?
+        .check(&user.username, &change.new_password)
+        .map_err(WeakPassword::new)?;
+
+    let password_hash = hash_password(change.new_password).await?;
+    users::set_password_hash(&pool, user.id, &password_hash).await?;
+    // Whoever knew the old password loses access along with it.
+    session::end_others(&pool, user.id, session::from_headers(&headers)?).await?;
+    tracing::info!(user = user.username, "changed local password");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/register", post(register))
+        .route("/login", post(login))
+        .route("/password", post(change_password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_policy() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            policy.check("jdoe", "short").unwrap_err(),
+            "password must be at least 12 characters long"
+        );
+        assert_eq!(
+            policy.check("jdoe", &"a".repeat(129)).unwrap_err(),
+            "password must be at most 128 characters long"
+        );
+        assert_eq!(
+            policy.check("jdoe", "my name is JDoe!").unwrap_err(),
+            "password must not contain the username"
+        );
+        policy.check("jdoe", "correct horse battery").unwrap();
+
+        let strict = PasswordPolicy {
+            min_classes: 3,
+            ..PasswordPolicy::default()
+        };
+        assert!(strict.check("jdoe", "correct horse battery").is_err());
+        strict.check("jdoe", "Correct horse battery").unwrap();
+    }
+
+    #[test]
+    fn usernames() {
+        check_username("jane.doe-2").unwrap();
+        assert!(check_username("").is_err());
+        assert!(check_username(".hidden").is_err());
+        assert!(check_username("jane doe").is_err());
+        assert!(check_username(&"a".repeat(41)).is_err());
+    }
+
+    #[test]
+    fn argon2id_round_trip() {
+        let hash = hash("correct horse battery").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify("correct horse battery", &hash).unwrap());
+        assert!(!verify("Correct horse battery", &hash).unwrap());
+        assert_ne!(hash, self::hash("correct horse battery").unwrap());
+    }
+}
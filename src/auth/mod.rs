@@ -1,38 +1,28 @@
-use axum::{
-    Router,
-    response::{Html, IntoResponse},
-    routing::get,
-};
-use serde::Serialize;
+pub mod csrf;
+pub mod email;
+pub mod impersonation;
+pub mod job;
+pub mod link;
+pub mod local;
+pub mod oidc;
+pub mod rbac;
+pub mod saml;
+pub mod session;
+pub mod throttle;
+pub mod token;
+pub mod totp;
+pub mod webauthn;
 
-#[derive(Serialize, Debug)]
-#[serde(rename = "md:EntityDescriptor")]
-struct EntityDescriptor {
-    #[serde(rename = "@xmlns:md")]
-    xmlns_md: String,
-
-    #[serde(rename = "@xmlns:saml")]
-    xmlns_saml: String,
-
-    #[serde(rename = "@entityID")]
-    entity_id: String,
-}
-
-async fn acs() -> Html<&'static str> {
-    Html("ACS")
-}
-
-async fn metadata() -> impl IntoResponse {
-    let _enity_descriptor = EntityDescriptor {
-        xmlns_md: "urn:oasis:names:tc:SAML:2.0:metadata".to_string(),
-        xmlns_saml: "urn:oasis:names:tc:SAML:2.0:assertion".to_string(),
-        entity_id: "https://ece.gg/auth/saml/metadata".to_string(),
-    };
-    Html("Metadata")
-}
+use axum::{Router, routing::get};
 
 pub fn routes() -> Router {
     Router::new()
-        .route("/saml/acs", get(acs))
-        .route("/saml/metadata", get(metadata))
+        .nest("/email", email::routes())
+        .nest("/impersonation", impersonation::routes())
+        .route("/link", get(link::confirm))
+        .nest("/local", local::routes())
+        .nest("/oidc", oidc::routes())
+        .nest("/saml", saml::routes())
+        .nest("/totp", totp::routes())
+        .nest("/webauthn", webauthn::routes())
 }
@@ -0,0 +1,327 @@
+//! The redirection endpoint: code exchange and ID token validation.
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::{
+        link,
+        oidc::{
+            Provider, STATE_COOKIE,
+            discovery::Metadata,
+            id_token::{self, Expected},
+            invalid, redirect_uri,
+        },
+        session::{self, AuthMethod, Origin},
+        throttle::{Attempt, ClientAddr},
+    },
+    users::{self, Identity},
+};
+
+use axum::{
+    Extension,
+    extract::Query,
+    http::{HeaderMap, header},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+use sqlx::{FromRow, PgPool};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    state: Option<String>,
+    code: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// An authorization request awaiting its answer.
+#[derive(Debug, FromRow)]
+struct Pending {
+    provider: String,
+    nonce: String,
+    code_verifier: String,
+    return_to: Option<String>,
+    link_user_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// Redeems an authorization code at the token endpoint and returns the ID
+/// token.
+async fn exchange(
+    client: &reqwest::Client,
+    metadata: &Metadata,
+    provider: &Provider,
+    redirect_uri: &str,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, String> {
+    let response: TokenResponse = client
+        .post(&metadata.token_endpoint)
+        .basic_auth(&provider.client_id, Some(&provider.client_secret))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", code_verifier),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("code exchange failed: {err}"))?
+        .json()
+        .await
+        .map_err(|err| format!("malformed token response: {err}"))?;
+    response
+        .id_token
+        .ok_or_else(|| "token response has no ID token".to_string())
+}
+
+fn clear_state() -> String {
+    format!("{STATE_COOKIE}=; Path=/auth/oidc; Max-Age=0; HttpOnly; Secure; SameSite=Lax")
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    addr: ClientAddr,
+    Query(query): Query<CallbackQuery>,
+) -> ApiResult<Response> {
+    let attempt = Attempt::begin(&pool, addr, None).await?;
+    let verified = async {
+        let state = query
+            .state
+            .as_deref()
+            .ok_or_else(|| invalid("missing state"))?;
+        // Without this, a login started by someone else could be completed
+        // in this browser, logging its user into their account.
+        if session::cookie(&headers, STATE_COOKIE) != Some(state) {
+            return Err(invalid("state does not belong to this browser").into());
+        }
+        let pending: Pending = sqlx::query_as(
+            "DELETE FROM oidc_requests
+             WHERE state = $1 AND created_at > now() - interval '10 minutes'
+             RETURNING provider, nonce, code_verifier, return_to, link_user_id",
+        )
+        .bind(state)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| invalid("unknown or expired state"))?;
+        if let Some(error) = &query.error {
+            let reason = query.error_description.as_ref().unwrap_or(error);
+            return Err(invalid(format!("provider refused login: {reason}")).into());
+        }
+        let code = query.code.as_ref().ok_or_else(|| invalid("missing code"))?;
+
+        let provider = Provider::from_env(&pending.provider)?;
+        let client = reqwest::Client::new();
+        let metadata = Metadata::fetch(&client, &provider.issuer)
+            .await
+            .map_err(invalid)?;
+        let token = exchange(
+            &client,
+            &metadata,
+            &provider,
+            &redirect_uri()?,
+            code,
+            &pending.code_verifier,
+        )
+        .await
+        .map_err(invalid)?;
+        let jwks = metadata.jwks(&client).await.map_err(invalid)?;
+        let expected = Expected {
+            issuer: &metadata.issuer,
+            client_id: &provider.client_id,
+            nonce: &pending.nonce,
+        };
+        let token = id_token::validate(&token, &jwks, &expected, now()).map_err(invalid)?;
+        Ok::<_, ApiError>((pending, provider, metadata, token))
+    }
+    .await;
+    let (pending, provider, metadata, token) = attempt.record(&pool, verified).await?;
+    let profile = provider
+        .mapping
+        .profile(&id_token::attributes(&token.claims))
+        .map_err(invalid)?;
+
+    let identity = if provider.name == "forgejo" {
+        Identity::forgejo(&token.sub, Some(&profile.username))
+    } else {
+        Identity::oidc(&metadata.issuer, &token.sub, Some(&profile.username))
+    };
+    if let Some(user_id) = pending.link_user_id {
+        let location = link::start(&pool, user_id, &identity, pending.return_to.as_deref()).await?;
+        return Ok((
+            AppendHeaders([(header::SET_COOKIE, clear_state())]),
+            Redirect::to(&location),
+        )
+            .into_response());
+    }
+    let user = users::provision(&pool, &identity, &profile).await?;
+    attempt.succeed(&pool, &user).await?;
+    let cookie = session::create(
+        &pool,
+        &headers,
+        user.id,
+        Origin {
+            method: AuthMethod::Oidc,
+            addr: attempt.addr(),
+        },
+        None,
+    )
+    .await?;
+    Ok((
+        AppendHeaders([
+            (header::SET_COOKIE, cookie),
+            (header::SET_COOKIE, clear_state()),
+        ]),
+        Redirect::to(pending.return_to.as_deref().unwrap_or("/")),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::oidc::id_token::tests::{jwks, sign},
+        users::{Profile, Role},
+    };
+    use axum::{
+        Form, Json, Router,
+        http::StatusCode,
+        routing::{get, post},
+    };
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    /// Serves discovery, JWKS and token endpoints like Forgejo does, issuing
+    /// an ID token for code `good-code` redeemed with verifier `verifier`.
+    async fn mock_provider() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}/", listener.local_addr().unwrap());
+
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}login/oauth/authorize"),
+            "token_endpoint": format!("{issuer}login/oauth/access_token"),
+            "jwks_uri": format!("{issuer}login/oauth/keys"),
+        });
+        let claims = json!({
+            "iss": issuer,
+            "aud": "ceresforge",
+            "sub": "42",
+            "exp": now() + 300,
+            "iat": now(),
+            "nonce": "nonce",
+            "name": "Jane Doe",
+            "preferred_username": "jdoe",
+            "email": "jdoe@example.edu",
+            "email_verified": true,
+            "groups": ["cs101", "cs101:tas"],
+        });
+        let token = move |headers: HeaderMap, Form(form): Form<HashMap<String, String>>| {
+            let claims = claims.clone();
+            async move {
+                let authorized = headers
+                    .get(header::AUTHORIZATION)
+                    .is_some_and(|value| value.as_bytes().starts_with(b"Basic "));
+                let redeemed = form.get("grant_type").map(String::as_str)
+                    == Some("authorization_code")
+                    && form.get("code").map(String::as_str) == Some("good-code")
+                    && form.get("code_verifier").map(String::as_str) == Some("verifier");
+                if !authorized || !redeemed {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({"error": "invalid_grant"})),
+                    );
+                }
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "access_token": "access",
+                        "token_type": "Bearer",
+                        "id_token": sign(&claims),
+                    })),
+                )
+            }
+        };
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route("/login/oauth/keys", get(|| async { Json(jwks()) }))
+            .route("/login/oauth/access_token", post(token));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        issuer
+    }
+
+    #[tokio::test]
+    async fn mock_forgejo_login() {
+        let issuer = mock_provider().await;
+        let var = |name: &str| match name {
+            "FORGEJO_URL" => Some(issuer.clone()),
+            "OIDC_FORGEJO_CLIENT_ID" => Some("ceresforge".to_string()),
+            "OIDC_FORGEJO_CLIENT_SECRET" => Some("secret".to_string()),
+            "OIDC_FORGEJO_ROLE_MAP" => Some("cs101:tas=ta".to_string()),
+            _ => None,
+        };
+        let provider = Provider::configure("forgejo", var).unwrap();
+        let client = reqwest::Client::new();
+        let metadata = Metadata::fetch(&client, &provider.issuer).await.unwrap();
+        let redirect_uri = "https://ceresforge.example.edu/auth/oidc/callback";
+
+        let refused = exchange(
+            &client,
+            &metadata,
+            &provider,
+            redirect_uri,
+            "stolen-code",
+            "verifier",
+        )
+        .await;
+        assert!(refused.unwrap_err().starts_with("code exchange failed"));
+
+        let token = exchange(
+            &client,
+            &metadata,
+            &provider,
+            redirect_uri,
+            "good-code",
+            "verifier",
+        )
+        .await
+        .unwrap();
+        let jwks = metadata.jwks(&client).await.unwrap();
+        let expected = Expected {
+            issuer: &metadata.issuer,
+            client_id: &provider.client_id,
+            nonce: "nonce",
+        };
+        let token = id_token::validate(&token, &jwks, &expected, now()).unwrap();
+        assert_eq!(token.sub, "42");
+        assert_eq!(
+            provider
+                .mapping
+                .profile(&id_token::attributes(&token.claims))
+                .unwrap(),
+            Profile {
+                username: "jdoe".to_string(),
+                email: Some("jdoe@example.edu".to_string()),
+                display_name: Some("Jane Doe".to_string()),
+                role: Role::Ta,
+            }
+        );
+    }
+}
@@ -0,0 +1,53 @@
+//! Provider metadata from `/.well-known/openid-configuration`.
+
+use crate::auth::oidc::id_token::Jwks;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Metadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+impl Metadata {
+    /// Fetches the metadata of `issuer`, which must name itself as such.
+    pub async fn fetch(client: &reqwest::Client, issuer: &str) -> Result<Self, String> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let metadata: Metadata = client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("cannot fetch {url}: {err}"))?
+            .json()
+            .await
+            .map_err(|err| format!("malformed provider metadata: {err}"))?;
+        // Tolerate a configured issuer that differs only by a trailing slash;
+        // ID tokens are checked against the issuer exactly as published.
+        if metadata.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(format!(
+                "provider metadata names issuer {}",
+                metadata.issuer
+            ));
+        }
+        Ok(metadata)
+    }
+
+    pub async fn jwks(&self, client: &reqwest::Client) -> Result<Jwks, String> {
+        client
+            .get(&self.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("cannot fetch {}: {err}", self.jwks_uri))?
+            .json()
+            .await
+            .map_err(|err| format!("malformed JWKS: {err}"))
+    }
+}
@@ -0,0 +1,310 @@
+//! ID token validation. Only RS256 is accepted, which every OpenID provider
+//! has to support and Forgejo uses by default.
+
+use crate::auth::saml::attributes::Attributes;
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use rsa::{
+    BigUint, RsaPublicKey,
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::Verifier,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+
+/// Leeway for clocks differing between us and the provider, in seconds.
+const CLOCK_SKEW: i64 = 60;
+
+pub type Claims = serde_json::Map<String, Value>;
+
+#[derive(Debug, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    #[serde(rename = "use")]
+    usage: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+impl Jwk {
+    /// Returns the key if it is an RSA key usable for RS256 signatures.
+    fn rs256_key(&self) -> Option<RsaPublicKey> {
+        if self.kty != "RSA"
+            || self.usage.as_deref().is_some_and(|usage| usage != "sig")
+            || self.alg.as_deref().is_some_and(|alg| alg != "RS256")
+        {
+            return None;
+        }
+        let n = BASE64_URL_SAFE_NO_PAD.decode(self.n.as_ref()?).ok()?;
+        let e = BASE64_URL_SAFE_NO_PAD.decode(self.e.as_ref()?).ok()?;
+        RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e)).ok()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// What an ID token has to match.
+#[derive(Debug)]
+pub struct Expected<'a> {
+    pub issuer: &'a str,
+    pub client_id: &'a str,
+    pub nonce: &'a str,
+}
+
+#[derive(Debug)]
+pub struct IdToken {
+    pub sub: String,
+    pub claims: Claims,
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, String> {
+    let json = BASE64_URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| "ID token is not base64url".to_string())?;
+    serde_json::from_slice(&json).map_err(|err| format!("malformed ID token: {err}"))
+}
+
+fn number(claims: &Claims, name: &str) -> Result<i64, String> {
+    claims
+        .get(name)
+        .and_then(Value::as_f64)
+        .map(|value| value as i64)
+        .ok_or_else(|| format!("ID token has no {name}"))
+}
+
+/// Checks the signature and claims of an ID token at time `now`.
+pub fn validate(
+    token: &str,
+    jwks: &Jwks,
+    expected: &Expected,
+    now: i64,
+) -> Result<IdToken, String> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err("ID token is not a compact JWS".to_string());
+    };
+    // The signature covers the first two parts exactly as sent.
+    let signed = &token[..header.len() + 1 + payload.len()];
+    let header: Header = decode(header)?;
+    if header.alg != "RS256" {
+        return Err(format!("unsupported signature algorithm {}", header.alg));
+    }
+    let signature = BASE64_URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()
+        .and_then(|signature| Signature::try_from(signature.as_slice()).ok())
+        .ok_or("malformed ID token signature")?;
+    let verified = jwks
+        .keys
+        .iter()
+        .filter(|jwk| header.kid.is_none() || jwk.kid.is_none() || jwk.kid == header.kid)
+        .filter_map(Jwk::rs256_key)
+        .any(|key| {
+            VerifyingKey::<Sha256>::new(key)
+                .verify(signed.as_bytes(), &signature)
+                .is_ok()
+        });
+    if !verified {
+        return Err("signature mismatch".to_string());
+    }
+
+    let claims: Claims = decode(payload)?;
+    if claims.get("iss").and_then(Value::as_str) != Some(expected.issuer) {
+        return Err("ID token is from another issuer".to_string());
+    }
+    let audience = match claims.get("aud") {
+        Some(Value::String(aud)) => aud == expected.client_id,
+        Some(Value::Array(aud)) => aud.iter().any(|aud| aud == expected.client_id),
+        _ => false,
+    };
+    if !audience {
+        return Err("ID token is for another client".to_string());
+    }
+    if claims
+        .get("azp")
+        .is_some_and(|azp| azp != expected.client_id)
+    {
+        return Err("ID token is for another client".to_string());
+    }
+    if number(&claims, "exp")? + CLOCK_SKEW < now {
+        return Err("ID token has expired".to_string());
+    }
+    if number(&claims, "iat")? - CLOCK_SKEW > now {
+        return Err("ID token is issued in the future".to_string());
+    }
+    if claims.get("nonce").and_then(Value::as_str) != Some(expected.nonce) {
+        return Err("nonce mismatch".to_string());
+    }
+    let sub = claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .filter(|sub| !sub.is_empty())
+        .ok_or("ID token has no sub")?
+        .to_string();
+    Ok(IdToken { sub, claims })
+}
+
+/// Turns claims into attributes for an [`AttributeMapping`], leaving out
+/// email addresses the provider has not verified.
+///
+/// [`AttributeMapping`]: crate::auth::saml::attributes::AttributeMapping
+pub fn attributes(claims: &Claims) -> Attributes {
+    let unverified = claims.get("email_verified") == Some(&Value::Bool(false));
+    claims
+        .iter()
+        .filter(|(name, _)| !(unverified && *name == "email"))
+        .map(|(name, value)| {
+            let values = match value {
+                Value::String(value) => vec![value.clone()],
+                Value::Array(values) => values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(str::to_string))
+                    .collect(),
+                Value::Null | Value::Object(_) => Vec::new(),
+                value => vec![value.to_string()],
+            };
+            (name.clone(), values)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rsa::{
+        RsaPrivateKey,
+        pkcs1v15::SigningKey,
+        pkcs8::DecodePrivateKey,
+        signature::{SignatureEncoding, Signer},
+        traits::PublicKeyParts,
+    };
+    use serde_json::json;
+
+    const KEY: &str = include_str!("../../../tests/fixtures/saml/idp.key");
+    const OTHER_KEY: &str = include_str!("../../../tests/fixtures/saml/sp.key");
+    const NOW: i64 = 1792324800;
+
+    /// Signs `claims` as an RS256 ID token, like a provider would.
+    pub fn sign(claims: &Value) -> String {
+        sign_with(KEY, claims)
+    }
+
+    fn sign_with(pem: &str, claims: &Value) -> String {
+        let key = RsaPrivateKey::from_pkcs8_pem(pem).unwrap();
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","kid":"test"}"#);
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signed = format!("{header}.{payload}");
+        let signature = SigningKey::<Sha256>::new(key).sign(signed.as_bytes());
+        format!(
+            "{signed}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+
+    /// The JWKS publishing the key [`sign`] uses.
+    pub fn jwks() -> Value {
+        let key = RsaPrivateKey::from_pkcs8_pem(KEY).unwrap();
+        json!({"keys": [{
+            "kty": "RSA",
+            "kid": "test",
+            "use": "sig",
+            "n": BASE64_URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            "e": BASE64_URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        }]})
+    }
+
+    fn claims() -> Value {
+        json!({
+            "iss": "https://git.example.edu/",
+            "aud": "ceresforge",
+            "sub": "42",
+            "exp": NOW + 300,
+            "iat": NOW,
+            "nonce": "n-0S6_WzA2Mj",
+            "preferred_username": "jdoe",
+            "email": "jdoe@example.edu",
+            "email_verified": true,
+            "groups": ["cs101", "cs101:tas"],
+        })
+    }
+
+    const EXPECTED: Expected = Expected {
+        issuer: "https://git.example.edu/",
+        client_id: "ceresforge",
+        nonce: "n-0S6_WzA2Mj",
+    };
+
+    fn check(claims: &Value) -> Result<IdToken, String> {
+        let jwks: Jwks = serde_json::from_value(jwks()).unwrap();
+        validate(&sign(claims), &jwks, &EXPECTED, NOW)
+    }
+
+    #[test]
+    fn valid_token() {
+        let token = check(&claims()).unwrap();
+        assert_eq!(token.sub, "42");
+        let attributes = attributes(&token.claims);
+        assert_eq!(attributes["preferred_username"], ["jdoe"]);
+        assert_eq!(attributes["groups"], ["cs101", "cs101:tas"]);
+        assert_eq!(attributes["email_verified"], ["true"]);
+    }
+
+    #[test]
+    fn rejected_tokens() {
+        let with = |name: &str, value: Value| {
+            let mut claims = claims();
+            claims[name] = value;
+            check(&claims).unwrap_err()
+        };
+        assert_eq!(
+            with("iss", json!("https://evil.example")),
+            "ID token is from another issuer"
+        );
+        assert_eq!(
+            with("aud", json!(["other"])),
+            "ID token is for another client"
+        );
+        assert_eq!(with("exp", json!(NOW - 120)), "ID token has expired");
+        assert_eq!(
+            with("iat", json!(NOW + 120)),
+            "ID token is issued in the future"
+        );
+        assert_eq!(with("nonce", json!("replayed")), "nonce mismatch");
+
+        let jwks: Jwks = serde_json::from_value(jwks()).unwrap();
+        let forged = sign_with(OTHER_KEY, &claims());
+        assert_eq!(
+            validate(&forged, &jwks, &EXPECTED, NOW).unwrap_err(),
+            "signature mismatch"
+        );
+        let token = sign(&claims());
+        let (_, rest) = token.split_once('.').unwrap();
+        let none = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#);
+        assert_eq!(
+            validate(&format!("{none}.{rest}"), &jwks, &EXPECTED, NOW).unwrap_err(),
+            "unsupported signature algorithm none"
+        );
+    }
+
+    #[test]
+    fn unverified_email() {
+        let mut claims = claims();
+        claims["email_verified"] = json!(false);
+        let token = check(&claims).unwrap();
+        assert!(!attributes(&token.claims).contains_key("email"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Authorization requests.
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::{
+        oidc::{Provider, STATE_COOKIE, discovery::Metadata, invalid, redirect_uri},
+        saml::local_path,
+        session::{OwnUser, random_token},
+    },
+};
+
+use axum::{
+    Extension,
+    extract::Query,
+    http::header,
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use url::Url;
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    provider: String,
+    return_to: Option<String>,
+    /// Whether to link the identity to the logged in user rather than log
+    /// in with it.
+    #[serde(default)]
+    link: bool,
+}
+
+/// The S256 PKCE code challenge for `verifier`.
+fn code_challenge(verifier: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[derive(Debug)]
+struct Request {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+}
+
+fn authorization_url(
+    endpoint: &str,
+    provider: &Provider,
+    redirect_uri: &str,
+    request: &Request,
+) -> Result<String, url::ParseError> {
+    let mut url = Url::parse(endpoint)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &provider.scopes)
+        .append_pair("state", &request.state)
+        .append_pair("nonce", &request.nonce)
+        .append_pair("code_challenge", &code_challenge(&request.code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    Ok(url.into())
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    user: Result<OwnUser, ApiError>,
+    Query(query): Query<LoginQuery>,
+) -> ApiResult<Response> {
+    let link_user_id = if query.link { Some(user?.0.id) } else { None };
+    let provider = Provider::from_env(&query.provider)?;
+    let metadata = Metadata::fetch(&reqwest::Client::new(), &provider.issuer)
+        .await
+        .map_err(invalid)?;
+    let request = Request {
+        state: random_token(),
+        nonce: random_token(),
+        code_verifier: random_token(),
+    };
+    let url = authorization_url(
+        &metadata.authorization_endpoint,
+        &provider,
+        &redirect_uri()?,
+        &request,
+    )
+    .map_err(|err| invalid(format!("bad authorization endpoint: {err}")))?;
+
+    sqlx::query("DELETE FROM oidc_requests WHERE created_at < now() - interval '10 minutes'")
+        .execute(&pool)
+        .await?;
+    sqlx::query(
+        "INSERT INTO oidc_requests
+             (state, provider, nonce, code_verifier, return_to, link_user_id)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(&request.state)
+    .bind(&provider.name)
+    .bind(&request.nonce)
+    .bind(&request.code_verifier)
+    .bind(local_path(query.return_to.as_deref()))
+    .bind(link_user_id)
+    .execute(&pool)
+    .await?;
+
+    let cookie = format!(
+        "{STATE_COOKIE}={}; Path=/auth/oidc; Max-Age=600; HttpOnly; Secure; SameSite=Lax",
+        request.state
+    );
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, cookie)]),
+        Redirect::to(&url),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::attributes::AttributeMapping;
+
+    #[test]
+    fn pkce_challenge() {
+        // RFC 7636, appendix B.
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn authorization_request() {
+        let provider = Provider {
+            name: "forgejo".to_string(),
+            issuer: "https://git.example.edu/".to_string(),
+            client_id: "ceresforge".to_string(),
+            client_secret: "secret".to_string(),
+            scopes: "openid profile".to_string(),
+            mapping: AttributeMapping::default(),
+        };
+        let request = Request {
+            state: "state".to_string(),
+            nonce: "nonce".to_string(),
+            code_verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string(),
+        };
+        let url = authorization_url(
+            "https://git.example.edu/login/oauth/authorize",
+            &provider,
+            "https://ceresforge.example.edu/auth/oidc/callback",
+            &request,
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://git.example.edu/login/oauth/authorize?response_type=code\
+             &client_id=ceresforge\
+             &redirect_uri=https%3A%2F%2Fceresforge.example.edu%2Fauth%2Foidc%2Fcallback\
+             &scope=openid+profile&state=state&nonce=nonce\
+             &code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM\
+             &code_challenge_method=S256"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! OpenID Connect login, authorization code flow with PKCE.
+//!
+//! Providers are listed by name in `OIDC_PROVIDERS` and configured with
+//! variables prefixed `OIDC_<NAME>_`, the name upper-cased with `-` turned
+//! into `_`:
+//!
+//! - `ISSUER`, `CLIENT_ID` and `CLIENT_SECRET` identify the provider and us.
+//! - `SCOPES` defaults to `openid profile email`.
+//! - `USERNAME_CLAIM` defaults to `preferred_username`.
+//! - `ROLES_CLAIM`, `ROLE_MAP` and `DEFAULT_ROLE` decide the role like their
+//!   SAML counterparts do, from the `groups` claim by default.
+//!
+//! Forgejo needs no entry in `OIDC_PROVIDERS`: setting
+//! `OIDC_FORGEJO_CLIENT_ID` is enough, its issuer defaults to `FORGEJO_URL`
+//! and the `groups` scope is requested as well. Its subjects are Forgejo
+//! user IDs, the same IDs webhook payloads carry.
+
+mod callback;
+mod discovery;
+mod id_token;
+mod login;
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{InternalError, InvalidOidcResponse, UnknownOidcProvider},
+    },
+    auth::saml::attributes::{AttributeMapping, parse_role_map},
+};
+
+use axum::{Router, routing::get};
+
+/// Cookie binding an authorization request to the browser that started it.
+const STATE_COOKIE: &str = "ceresforge_oidc";
+
+#[derive(Debug)]
+pub struct Provider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: String,
+    pub mapping: AttributeMapping,
+}
+
+fn invalid(reason: impl Into<String>) -> InvalidOidcResponse {
+    InvalidOidcResponse::new(reason.into())
+}
+
+fn config_error(err: String) -> ApiError {
+    ApiError::InternalError(InternalError::new(err.into()))
+}
+
+impl Provider {
+    /// Names of the configured providers.
+    pub fn names(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut names: Vec<String> = var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        if var("OIDC_FORGEJO_CLIENT_ID").is_some() && !names.iter().any(|name| name == "forgejo") {
+            names.push("forgejo".to_string());
+        }
+        names
+    }
+
+    /// Reads the configuration of provider `name` through `var`, which looks
+    /// up environment variables.
+    pub fn configure(name: &str, var: impl Fn(&str) -> Option<String>) -> ApiResult<Self> {
+        if !Provider::names(&var).iter().any(|known| known == name) {
+            return Err(UnknownOidcProvider::new(name.to_string()).into());
+        }
+        let prefix = format!("OIDC_{}_", name.to_uppercase().replace('-', "_"));
+        let setting = |key: &str| var(&format!("{prefix}{key}"));
+        let required = |key: &str| {
+            setting(key).ok_or_else(|| config_error(format!("{prefix}{key} is not set")))
+        };
+        let forgejo = name == "forgejo";
+
+        let issuer = match setting("ISSUER") {
+            Some(issuer) => issuer,
+            None if forgejo => var("FORGEJO_URL")
+                .ok_or_else(|| config_error("FORGEJO_URL is not set".to_string()))?,
+            None => required("ISSUER")?,
+        };
+        let scopes = setting("SCOPES").unwrap_or_else(|| {
+            if forgejo {
+                "openid profile email groups".to_string()
+            } else {
+                "openid profile email".to_string()
+            }
+        });
+
+        let mut mapping = AttributeMapping {
+            username: "preferred_username".to_string(),
+            email: "email".to_string(),
+            display_name: "name".to_string(),
+            roles: vec!["groups".to_string()],
+            role_map: Vec::new(),
+            ..AttributeMapping::default()
+        };
+        if let Some(username) = setting("USERNAME_CLAIM") {
+            mapping.username = username;
+        }
+        if let Some(roles) = setting("ROLES_CLAIM") {
+            mapping.roles = roles.split_whitespace().map(str::to_string).collect();
+        }
+        if let Some(role_map) = setting("ROLE_MAP") {
+            mapping.role_map =
+                parse_role_map(&format!("{prefix}ROLE_MAP"), &role_map).map_err(config_error)?;
+        }
+        if let Some(role) = setting("DEFAULT_ROLE") {
+            mapping.default_role = role.parse().map_err(config_error)?;
+        }
+
+        Ok(Provider {
+            name: name.to_string(),
+            issuer,
+            client_id: required("CLIENT_ID")?,
+            client_secret: required("CLIENT_SECRET")?,
+            scopes,
+            mapping,
+        })
+    }
+
+    pub fn from_env(name: &str) -> ApiResult<Self> {
+        Provider::configure(name, |var| std::env::var(var).ok())
+    }
+}
+
+/// Our redirection endpoint, as registered with the providers.
+fn redirect_uri() -> ApiResult<String> {
+    let base_url = std::env::var("BASE_URL")?;
+    Ok(format!(
+        "{}/auth/oidc/callback",
+        base_url.trim_end_matches('/')
+    ))
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/callback", get(callback::handler))
+        .route("/login", get(login::handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::Role;
+    use std::collections::HashMap;
+
+    fn env(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn forgejo_defaults() {
+        let var = env(&[
+            ("FORGEJO_URL", "https://git.example.edu/"),
+            ("OIDC_FORGEJO_CLIENT_ID", "ceresforge"),
+            ("OIDC_FORGEJO_CLIENT_SECRET", "secret"),
+        ]);
+        assert_eq!(Provider::names(&var), ["forgejo"]);
+        let forgejo = Provider::configure("forgejo", &var).unwrap();
+        assert_eq!(forgejo.issuer, "https://git.example.edu/");
+        assert_eq!(forgejo.scopes, "openid profile email groups");
+        assert_eq!(forgejo.mapping.username, "preferred_username");
+    }
+
+    #[test]
+    fn generic_provider() {
+        let var = env(&[
+            ("OIDC_PROVIDERS", "campus-sso"),
+            ("OIDC_CAMPUS_SSO_ISSUER", "https://sso.example.edu"),
+            ("OIDC_CAMPUS_SSO_CLIENT_ID", "ceresforge"),
+            ("OIDC_CAMPUS_SSO_CLIENT_SECRET", "secret"),
+            ("OIDC_CAMPUS_SSO_ROLE_MAP", "staff=instructor"),
+            ("OIDC_CAMPUS_SSO_DEFAULT_ROLE", "ta"),
+        ]);
+        let provider = Provider::configure("campus-sso", &var).unwrap();
+        assert_eq!(provider.issuer, "https://sso.example.edu");
+        assert_eq!(
+            provider.mapping.role_map,
+            [("staff".to_string(), Role::Instructor)]
+        );
+        assert_eq!(provider.mapping.default_role, Role::Ta);
+
+        assert!(matches!(
+            Provider::configure("forgejo", &var),
+            Err(ApiError::UnknownOidcProvider(_))
+        ));
+    }
+}
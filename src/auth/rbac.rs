@@ -0,0 +1,271 @@
+//! Role-based access control.
+//!
+//! Whether a permission is granted depends only on the user's global role
+//! and, for permissions within a course, their role in that course; see
+//! [`allows`]. Handlers declare what they need with the [`Authorized`]
+//! extractor, which gathers those roles. Every decision is traced under the
+//! `audit` target and denials are written to the audit log.
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{Forbidden, InsufficientScope, InternalError, ResourceNotFound},
+    },
+    audit,
+    auth::{
+        session::CurrentUser,
+        token::{Grant, Scope},
+    },
+    courses::{self, Course, CourseRole},
+    users::{Role, User},
+};
+
+use axum::{
+    extract::{FromRequestParts, OriginalUri, RawPathParams},
+    http::request::Parts,
+};
+use sqlx::PgPool;
+use std::marker::PhantomData;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    CreateCourse,
+    ViewCourse,
+    Submit,
+    ViewGrades,
+    ManageCourse,
+    ManageUsers,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::CreateCourse => "create_course",
+            Permission::ViewCourse => "view_course",
+            Permission::Submit => "submit",
+            Permission::ViewGrades => "view_grades",
+            Permission::ManageCourse => "manage_course",
+            Permission::ManageUsers => "manage_users",
+        }
+    }
+
+    /// Whether the permission applies within a course.
+    pub fn in_course(&self) -> bool {
+        !matches!(self, Permission::CreateCourse | Permission::ManageUsers)
+    }
+
+    /// The scope a personal access token needs to be used for it.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Permission::ViewCourse | Permission::ViewGrades => Scope::ReadCourses,
+            Permission::Submit => Scope::WriteSubmissions,
+            Permission::CreateCourse | Permission::ManageCourse => Scope::ManageCourses,
+            Permission::ManageUsers => Scope::Admin,
+        }
+    }
+}
+
+/// Decides whether a user with global `role` and, for the course in
+/// question, `course_role` has `permission`. Admins have every permission.
+pub fn allows(role: Role, course_role: Option<CourseRole>, permission: Permission) -> bool {
+    if role == Role::Admin {
+        return true;
+    }
+    match permission {
+        Permission::CreateCourse => role >= Role::Instructor,
+        Permission::ViewCourse | Permission::Submit => course_role.is_some(),
+        Permission::ViewGrades => course_role >= Some(CourseRole::Ta),
+        Permission::ManageCourse => course_role == Some(CourseRole::Instructor),
+        Permission::ManageUsers => false,
+    }
+}
+
+/// Names a permission for [`Authorized`].
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+/// Permissions as types, for use as `Authorized<require::ViewGrades>`.
+pub mod require {
+    use super::{Permission, RequiredPermission};
+
+    #[derive(Debug)]
+    pub struct CreateCourse;
+    #[derive(Debug)]
+    pub struct ViewCourse;
+    #[derive(Debug)]
+    pub struct Submit;
+    #[derive(Debug)]
+    pub struct ViewGrades;
+    #[derive(Debug)]
+    pub struct ManageCourse;
+    #[derive(Debug)]
+    pub struct ManageUsers;
+
+    impl RequiredPermission for CreateCourse {
+        const PERMISSION: Permission = Permission::CreateCourse;
+    }
+    impl RequiredPermission for ViewCourse {
+        const PERMISSION: Permission = Permission::ViewCourse;
+    }
+    impl RequiredPermission for Submit {
+        const PERMISSION: Permission = Permission::Submit;
+    }
+    impl RequiredPermission for ViewGrades {
+        const PERMISSION: Permission = Permission::ViewGrades;
+    }
+    impl RequiredPermission for ManageCourse {
+        const PERMISSION: Permission = Permission::ManageCourse;
+    }
+    impl RequiredPermission for ManageUsers {
+        const PERMISSION: Permission = Permission::ManageUsers;
+    }
+}
+
+/// Finds the course a request is about from its path: a `{course}`
+/// parameter holds the course's slug, an `{owner}` parameter the Forgejo
+/// organization of its repositories.
+async fn course(pool: &PgPool, params: &RawPathParams) -> ApiResult<Option<Course>> {
+    for (name, value) in params {
+        match name {
+            "course" => return courses::find(pool, value).await,
+            "owner" => return courses::by_organization(pool, value).await,
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Extracts the current user if they have permission `P`, rejecting the
+/// request with [`Forbidden`] otherwise. For permissions within a course,
+/// the course is found from the request path.
+#[derive(Debug)]
+pub struct Authorized<P> {
+    pub user: User,
+    pub course: Option<Course>,
+    permission: PhantomData<P>,
+}
+
+impl<P, S> FromRequestParts<S> for Authorized<P>
+where
+    P: RequiredPermission,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let permission = P::PERMISSION;
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        if let Some(grant) = parts.extensions.get::<Grant>() {
+            if !grant.allows(permission.scope()) {
+                return Err(InsufficientScope::new(permission.scope().as_str().to_string()).into());
+            }
+        }
+        let pool = parts.extensions.get::<PgPool>().cloned().ok_or_else(|| {
+            ApiError::InternalError(InternalError::new("no database pool".into()))
+        })?;
+
+        let (course, course_role) = if permission.in_course() {
+            let uri = parts
+                .extensions
+                .get::<OriginalUri>()
+                .map_or(&parts.uri, |uri| &uri.0)
+                .clone();
+            let params = RawPathParams::from_request_parts(parts, state)
+                .await
+                .map_err(|err| ApiError::InternalError(InternalError::new(Box::new(err))))?;
+            let course = course(&pool, &params)
+                .await?
+                .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?;
+            let course_role = courses::role(&pool, course.id, user.id).await?;
+            (Some(course), course_role)
+        } else {
+            (None, None)
+        };
+
+        let granted = allows(user.role, course_role, permission);
+        let on = course
+            .as_ref()
+            .map(|course| format!(" on course {}", course.slug))
+            .unwrap_or_default();
+        tracing::debug!(
+            target: "audit",
+            user = user.username,
+            permission = permission.as_str(),
+            course = course.as_ref().map(|course| course.slug.as_str()),
+            granted,
+        );
+        if !granted {
+            audit::record(
+                &pool,
+                Some(user.id),
+                "permission_denied",
+                &format!("{}{on}", permission.as_str()),
+            )
+            .await?;
+            return Err(Forbidden::new(permission.as_str().to_string()).into());
+        }
+        Ok(Authorized {
+            user,
+            course,
+            permission: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn course_permissions() {
+        use CourseRole::*;
+        use Permission::*;
+
+        let cases = [
+            (None, ViewCourse, false),
+            (Some(Student), ViewCourse, true),
+            (None, Submit, false),
+            (Some(Student), Submit, true),
+            (Some(Student), ViewGrades, false),
+            (Some(Ta), ViewGrades, true),
+            (Some(Ta), ManageCourse, false),
+            (Some(Instructor), ViewGrades, true),
+            (Some(Instructor), ManageCourse, true),
+        ];
+        for (course_role, permission, granted) in cases {
+            assert_eq!(
+                allows(Role::Student, course_role, permission),
+                granted,
+                "{course_role:?} {permission:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn global_roles() {
+        // A global role does not reach into courses the user is not in.
+        assert!(!allows(Role::Instructor, None, Permission::ViewCourse));
+        assert!(allows(Role::Instructor, None, Permission::CreateCourse));
+        assert!(!allows(Role::Ta, None, Permission::CreateCourse));
+        assert!(!allows(Role::Instructor, None, Permission::ManageUsers));
+        for permission in [
+            Permission::CreateCourse,
+            Permission::ViewCourse,
+            Permission::Submit,
+            Permission::ViewGrades,
+            Permission::ManageCourse,
+            Permission::ManageUsers,
+        ] {
+            assert!(allows(Role::Admin, None, permission));
+        }
+    }
+
+    #[test]
+    fn token_scopes() {
+        assert_eq!(Permission::ViewGrades.scope(), Scope::ReadCourses);
+        assert_eq!(Permission::Submit.scope(), Scope::WriteSubmissions);
+        assert_eq!(Permission::ManageCourse.scope(), Scope::ManageCourses);
+        assert_eq!(Permission::ManageUsers.scope(), Scope::Admin);
+    }
+}
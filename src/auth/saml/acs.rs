@@ -0,0 +1,617 @@
+//! Assertion Consumer Service, HTTP-POST binding.
+
+use crate::{
+    api::{ApiError, ApiResult, error::InvalidSamlResponse},
+    auth::{
+        link,
+        saml::{
+            IdentityProviders, ServiceProvider,
+            attributes::{AttributeMapping, Attributes},
+            idp::IdentityProvider,
+            login::local_path,
+            signature,
+            time::{CLOCK_SKEW, now, parse_instant},
+            xml::{self, Element, SAML, SAMLP},
+        },
+        session::{self, AuthMethod, Origin, Subject},
+        throttle::{Attempt, ClientAddr},
+    },
+    users::{self, Identity},
+};
+
+use axum::{
+    Extension, Form,
+    http::{HeaderMap, header},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+
+#[derive(Debug, Deserialize)]
+pub struct AcsForm {
+    #[serde(rename = "SAMLResponse")]
+    saml_response: String,
+    #[serde(rename = "RelayState")]
+    relay_state: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Assertion {
+    pub id: String,
+    pub name_id: String,
+    pub name_id_format: Option<String>,
+    pub session_index: Option<String>,
+    /// The request the assertion answers; unsolicited ones are refused.
+    pub in_response_to: String,
+    pub not_on_or_after: i64,
+    pub attributes: Attributes,
+}
+
+fn invalid(reason: &str) -> InvalidSamlResponse {
+    InvalidSamlResponse::new(reason.to_string())
+}
+
+fn instant(element: &Element, name: &str) -> Result<Option<i64>, InvalidSamlResponse> {
+    match element.attribute(name) {
+        Some(value) => parse_instant(value)
+            .map(Some)
+            .ok_or_else(|| invalid(&format!("malformed {name}"))),
+        None => Ok(None),
+    }
+}
+
+fn check_issuer(element: &Element, idp: &IdentityProvider) -> Result<(), InvalidSamlResponse> {
+    match element.child(SAML, "Issuer") {
+        Some(issuer) if issuer.text().trim() != idp.entity_id => Err(invalid("unexpected issuer")),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the `InResponseTo` and `NotOnOrAfter` of the first bearer
+/// confirmation addressed to us that is still valid.
+fn confirm_subject(
+    subject: &Element,
+    sp: &ServiceProvider,
+    now: i64,
+) -> Result<(Option<String>, i64), InvalidSamlResponse> {
+    for confirmation in subject.children(SAML, "SubjectConfirmation") {
+        if confirmation.attribute("Method") != Some(BEARER) {
+            continue;
+        }
+        let Some(data) = confirmation.child(SAML, "SubjectConfirmationData") else {
+            continue;
+        };
+        if data.attribute("Recipient") != Some(sp.acs_url.as_str()) {
+            continue;
+        }
+        let Some(not_on_or_after) = instant(data, "NotOnOrAfter")? else {
+            continue;
+        };
+        if not_on_or_after + CLOCK_SKEW <= now {
+            continue;
+        }
+        if instant(data, "NotBefore")?.is_some_and(|t| t - CLOCK_SKEW > now) {
+            continue;
+        }
+        let in_response_to = data.attribute("InResponseTo").map(str::to_string);
+        return Ok((in_response_to, not_on_or_after));
+    }
+    Err(invalid("no valid bearer subject confirmation"))
+}
+
+fn check_conditions(
+    conditions: &Element,
+    sp: &ServiceProvider,
+    now: i64,
+) -> Result<Option<i64>, InvalidSamlResponse> {
+    if instant(conditions, "NotBefore")?.is_some_and(|t| t - CLOCK_SKEW > now) {
+        return Err(invalid("assertion is not yet valid"));
+    }
+    let not_on_or_after = instant(conditions, "NotOnOrAfter")?;
+    if not_on_or_after.is_some_and(|t| t + CLOCK_SKEW <= now) {
+        return Err(invalid("assertion has expired"));
+    }
+    for restriction in conditions.children(SAML, "AudienceRestriction") {
+        if !restriction
+            .children(SAML, "Audience")
+            .any(|audience| audience.text().trim() == sp.entity_id)
+        {
+            return Err(invalid("audience mismatch"));
+        }
+    }
+    Ok(not_on_or_after)
+}
+
+/// Collects the values of every attribute, under both its name and its
+/// friendly name.
+fn attributes(assertion: &Element) -> Attributes {
+    let mut attributes = Attributes::new();
+    let statements = assertion.children(SAML, "AttributeStatement");
+    for attribute in statements.flat_map(|s| s.children(SAML, "Attribute")) {
+        let values: Vec<String> = attribute
+            .children(SAML, "AttributeValue")
+            .map(|value| value.text())
+            .collect();
+        for name in [
+            attribute.attribute("Name"),
+            attribute.attribute("FriendlyName"),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            attributes
+                .entry(name.to_string())
+                .or_default()
+                .extend(values.iter().cloned());
+        }
+    }
+    attributes
+}
+
+/// Validates a decoded `Response` and returns the assertion it carries.
+pub fn validate(
+    document: &str,
+    sp: &ServiceProvider,
+    idp: &IdentityProvider,
+    now: i64,
+) -> Result<Assertion, InvalidSamlResponse> {
+    let response = xml::parse(document).map_err(InvalidSamlResponse::new)?;
+    if !response.is(SAMLP, "Response") || response.attribute("Version") != Some("2.0") {
+        return Err(invalid("not a SAML 2.0 response"));
+    }
+    if response
+        .attribute("Destination")
+        .is_some_and(|destination| destination != sp.acs_url)
+    {
+        return Err(invalid("unexpected destination"));
+    }
+    let status = response
+        .child(SAMLP, "Status")
+        .and_then(|status| status.child(SAMLP, "StatusCode"))
+        .and_then(|code| code.attribute("Value"));
+    if status != Some(SUCCESS) {
+        return Err(invalid("authentication failed at the identity provider"));
+    }
+    check_issuer(&response, idp)?;
+
+    if response.child(SAML, "EncryptedAssertion").is_some() {
+        return Err(invalid("encrypted assertions are not supported"));
+    }
+    let mut assertions = response.children(SAML, "Assertion");
+    let assertion = assertions
+        .next()
+        .ok_or_else(|| invalid("missing assertion"))?;
+    if assertions.next().is_some() {
+        return Err(invalid("multiple assertions"));
+    }
+    let id = assertion
+        .attribute("ID")
+        .ok_or_else(|| invalid("assertion has no ID"))?;
+    for element in [&response, assertion] {
+        if let Some(id) = element.attribute("ID") {
+            if response.count_id(id) != 1 {
+                return Err(invalid("duplicate ID"));
+            }
+        }
+    }
+
+    let mut signed = false;
+    for element in [&response, assertion] {
+        if element.child(xml::DS, "Signature").is_some() {
+            signature::verify(element, &idp.keys).map_err(|reason| invalid(&reason))?;
+            signed = true;
+        }
+    }
+    if !signed {
+        return Err(invalid("unsigned assertion"));
+    }
+
+    let issuer = assertion
+        .child(SAML, "Issuer")
+        .ok_or_else(|| invalid("assertion has no issuer"))?;
+    if issuer.text().trim() != idp.entity_id {
+        return Err(invalid("unexpected issuer"));
+    }
+
+    let subject = assertion
+        .child(SAML, "Subject")
+        .ok_or_else(|| invalid("assertion has no subject"))?;
+    let name_id_element = subject
+        .child(SAML, "NameID")
+        .ok_or_else(|| invalid("assertion has no NameID"))?;
+    let name_id = name_id_element.text().trim().to_string();
+    if name_id.is_empty() {
+        return Err(invalid("assertion has no NameID"));
+    }
+    let (in_response_to, mut not_on_or_after) = confirm_subject(subject, sp, now)?;
+    // IdP-initiated logins would let anyone log a browser in to their account.
+    let in_response_to = in_response_to.ok_or_else(|| invalid("unsolicited response"))?;
+    if let Some(expected) = response.attribute("InResponseTo") {
+        if in_response_to != expected {
+            return Err(invalid("InResponseTo mismatch"));
+        }
+    }
+
+    if let Some(conditions) = assertion.child(SAML, "Conditions") {
+        if let Some(t) = check_conditions(conditions, sp, now)? {
+            not_on_or_after = not_on_or_after.min(t);
+        }
+    }
+
+    let session_index = assertion
+        .child(SAML, "AuthnStatement")
+        .and_then(|statement| statement.attribute("SessionIndex"))
+        .map(str::to_string);
+
+    Ok(Assertion {
+        id: id.to_string(),
+        name_id,
+        name_id_format: name_id_element.attribute("Format").map(str::to_string),
+        session_index,
+        in_response_to,
+        not_on_or_after,
+        attributes: attributes(assertion),
+    })
+}
+
+/// Returns the issuer of a response, falling back to that of its assertion
+/// when the response itself does not name one.
+pub fn issuer(document: &str) -> Option<String> {
+    let response = xml::parse(document).ok()?;
+    let issuer = response.child(SAML, "Issuer").or_else(|| {
+        response
+            .child(SAML, "Assertion")
+            .and_then(|assertion| assertion.child(SAML, "Issuer"))
+    })?;
+    Some(issuer.text().trim().to_string())
+}
+
+/// Consumes the outstanding request to `idp` that a message answers, and
+/// returns the user who started it if it was for linking.
+pub async fn consume_request(
+    pool: &PgPool,
+    in_response_to: &str,
+    idp: &IdentityProvider,
+) -> ApiResult<Option<i64>> {
+    let consumed: Option<Option<i64>> = sqlx::query_scalar(
+        "DELETE FROM saml_requests
+         WHERE id = $1 AND idp = $2 AND created_at > now() - interval '10 minutes'
+         RETURNING link_user_id",
+    )
+    .bind(in_response_to)
+    .bind(&idp.entity_id)
+    .fetch_optional(pool)
+    .await?;
+    consumed.ok_or_else(|| invalid("unknown InResponseTo").into())
+}
+
+/// Remembers an assertion until it expires so it cannot be replayed.
+async fn check_replay(pool: &PgPool, assertion: &Assertion) -> ApiResult<()> {
+    sqlx::query("DELETE FROM saml_assertions WHERE expires_at < now()")
+        .execute(pool)
+        .await?;
+    let inserted = sqlx::query(
+        "INSERT INTO saml_assertions (id, expires_at)
+         VALUES ($1, to_timestamp($2))
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(&assertion.id)
+    .bind((assertion.not_on_or_after + CLOCK_SKEW) as f64)
+    .execute(pool)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(invalid("replayed assertion").into());
+    }
+    Ok(())
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(idps): Extension<IdentityProviders>,
+    headers: HeaderMap,
+    addr: ClientAddr,
+    Form(form): Form<AcsForm>,
+) -> ApiResult<Response> {
+    let sp = ServiceProvider::from_env()?;
+    let attempt = Attempt::begin(&pool, addr, None).await?;
+
+    let verified = async {
+        let document = BASE64_STANDARD
+            .decode(form.saml_response.trim())
+            .map_err(|_| invalid("SAMLResponse is not base64"))?;
+        let document =
+            String::from_utf8(document).map_err(|_| invalid("SAMLResponse is not UTF-8"))?;
+        let idp = issuer(&document)
+            .and_then(|issuer| idps.get(&issuer))
+            .ok_or_else(|| invalid("unknown issuer"))?;
+        let assertion = validate(&document, &sp, &idp, now())?;
+        let link_user_id = consume_request(&pool, &assertion.in_response_to, &idp).await?;
+        check_replay(&pool, &assertion).await?;
+        Ok::<_, ApiError>((idp, assertion, link_user_id))
+    }
+    .await;
+    let (idp, assertion, link_user_id) = attempt.record(&pool, verified).await?;
+    let profile = AttributeMapping::from_env(&idp)
+        .map_err(|err| invalid(&err))?
+        .profile(&assertion.attributes)
+        .map_err(|err| invalid(&err))?;
+    let identity = Identity::saml(&idp.entity_id, &assertion.name_id, Some(&profile.username));
+    if let Some(user_id) = link_user_id {
+        let location = link::start(&pool, user_id, &identity, form.relay_state.as_deref()).await?;
+        return Ok(Redirect::to(&location).into_response());
+    }
+    let user = users::provision(&pool, &identity, &profile).await?;
+    attempt.succeed(&pool, &user).await?;
+    let subject = Subject {
+        idp: Some(idp.entity_id.clone()),
+        name_id: assertion.name_id,
+        name_id_format: assertion.name_id_format,
+        session_index: assertion.session_index,
+    };
+    let cookie = session::create(
+        &pool,
+        &headers,
+        user.id,
+        Origin {
+            method: AuthMethod::Saml,
+            addr: attempt.addr(),
+        },
+        Some(&subject),
+    )
+    .await?;
+
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, cookie)]),
+        Redirect::to(local_path(form.relay_state.as_deref()).unwrap_or("/")),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::{
+        RsaPrivateKey,
+        pkcs1v15::SigningKey,
+        pkcs8::DecodePrivateKey,
+        signature::{SignatureEncoding, Signer},
+    };
+    use sha2::{Digest, Sha256};
+
+    const IDP_KEY: &str = include_str!("../../../tests/fixtures/saml/idp.key");
+    const IDP_CERTIFICATE: &str = include_str!("../../../tests/fixtures/saml/idp.crt");
+    const SP_KEY: &str = include_str!("../../../tests/fixtures/saml/sp.key");
+    const IDP: &str = "https://idp.example.edu/idp";
+
+    // 2026-10-18T12:00:00Z
+    const NOW: i64 = 1792324800;
+
+    fn idp() -> IdentityProvider {
+        let body: String = IDP_CERTIFICATE
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        IdentityProvider {
+            entity_id: IDP.to_string(),
+            name: "Example University".to_string(),
+            sso_url: "https://idp.example.edu/idp/sso".to_string(),
+            slo_url: None,
+            keys: vec![
+                crate::auth::saml::public_key(&BASE64_STANDARD.decode(body).unwrap()).unwrap(),
+            ],
+            scopes: Vec::new(),
+        }
+    }
+
+    fn sp() -> ServiceProvider {
+        ServiceProvider::new("https://ece.gg")
+    }
+
+    /// An assertion written in canonical form, so its digest can be computed
+    /// without a canonicalizer.
+    fn assertion(audience: &str, recipient: &str, not_on_or_after: &str) -> String {
+        format!(
+            "<saml:Assertion xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" ID=\"_assertion\" IssueInstant=\"2026-10-18T11:59:58Z\" Version=\"2.0\">\
+             <saml:Issuer>{IDP}</saml:Issuer>\
+             <saml:Subject>\
+             <saml:NameID Format=\"urn:oasis:names:tc:SAML:2.0:nameid-format:persistent\">student-42</saml:NameID>\
+             <saml:SubjectConfirmation Method=\"urn:oasis:names:tc:SAML:2.0:cm:bearer\">\
+             <saml:SubjectConfirmationData InResponseTo=\"_request\" NotOnOrAfter=\"{not_on_or_after}\" Recipient=\"{recipient}\"></saml:SubjectConfirmationData>\
+             </saml:SubjectConfirmation>\
+             </saml:Subject>\
+             <saml:Conditions NotBefore=\"2026-10-18T11:59:00Z\" NotOnOrAfter=\"{not_on_or_after}\">\
+             <saml:AudienceRestriction><saml:Audience>{audience}</saml:Audience></saml:AudienceRestriction>\
+             </saml:Conditions>\
+             <saml:AuthnStatement AuthnInstant=\"2026-10-18T11:59:58Z\" SessionIndex=\"_session\"></saml:AuthnStatement>\
+             <saml:AttributeStatement>\
+             <saml:Attribute Name=\"mail\"><saml:AttributeValue>student@example.edu</saml:AttributeValue></saml:Attribute>\
+             </saml:AttributeStatement>\
+             </saml:Assertion>"
+        )
+    }
+
+    fn valid_assertion() -> String {
+        assertion(
+            "https://ece.gg/auth/saml/metadata",
+            "https://ece.gg/auth/saml/acs",
+            "2026-10-18T12:05:00Z",
+        )
+    }
+
+    /// Inserts an enveloped signature made with `key` after the issuer.
+    fn sign_with(assertion: &str, key: &str) -> String {
+        let digest = BASE64_STANDARD.encode(Sha256::digest(assertion.as_bytes()));
+        let signed_info = format!(
+            "<ds:SignedInfo xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">\
+             <ds:CanonicalizationMethod Algorithm=\"http://www.w3.org/2001/10/xml-exc-c14n#\"></ds:CanonicalizationMethod>\
+             <ds:SignatureMethod Algorithm=\"http://www.w3.org/2001/04/xmldsig-more#rsa-sha256\"></ds:SignatureMethod>\
+             <ds:Reference URI=\"#_assertion\">\
+             <ds:Transforms>\
+             <ds:Transform Algorithm=\"http://www.w3.org/2000/09/xmldsig#enveloped-signature\"></ds:Transform>\
+             <ds:Transform Algorithm=\"http://www.w3.org/2001/10/xml-exc-c14n#\"></ds:Transform>\
+             </ds:Transforms>\
+             <ds:DigestMethod Algorithm=\"http://www.w3.org/2001/04/xmlenc#sha256\"></ds:DigestMethod>\
+             <ds:DigestValue>{digest}</ds:DigestValue>\
+             </ds:Reference>\
+             </ds:SignedInfo>"
+        );
+        let key = RsaPrivateKey::from_pkcs8_pem(key).unwrap();
+        let value = SigningKey::<Sha256>::new(key).sign(signed_info.as_bytes());
+        let signature = format!(
+            "<ds:Signature xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">{}<ds:SignatureValue>{}</ds:SignatureValue></ds:Signature>",
+            signed_info.replace(" xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\"", ""),
+            BASE64_STANDARD.encode(value.to_bytes())
+        );
+        let at = assertion.find("<saml:Subject>").unwrap();
+        format!("{}{signature}{}", &assertion[..at], &assertion[at..])
+    }
+
+    fn sign(assertion: &str) -> String {
+        sign_with(assertion, IDP_KEY)
+    }
+
+    fn response(assertion: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion"
+    ID="_response" Version="2.0" IssueInstant="2026-10-18T11:59:58Z"
+    Destination="https://ece.gg/auth/saml/acs" InResponseTo="_request">
+  <saml:Issuer>{IDP}</saml:Issuer>
+  <samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status>
+  {assertion}
+</samlp:Response>"#
+        )
+    }
+
+    fn reason(result: Result<Assertion, InvalidSamlResponse>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn valid_response() {
+        let document = response(&sign(&valid_assertion()));
+        let assertion = validate(&document, &sp(), &idp(), NOW).unwrap();
+        assert_eq!(assertion.id, "_assertion");
+        assert_eq!(assertion.name_id, "student-42");
+        assert_eq!(assertion.session_index.as_deref(), Some("_session"));
+        assert_eq!(assertion.in_response_to, "_request");
+        assert_eq!(assertion.not_on_or_after, NOW + 300);
+        assert_eq!(assertion.attributes["mail"], ["student@example.edu"]);
+    }
+
+    #[test]
+    fn unsolicited_response() {
+        let assertion = valid_assertion().replace(" InResponseTo=\"_request\"", "");
+        let document = response(&sign(&assertion)).replace(" InResponseTo=\"_request\"", "");
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "unsolicited response"
+        );
+    }
+
+    #[test]
+    fn canonicalizes_reformatted_assertion() {
+        // Inherited namespace, reordered attributes and self-closing tags all
+        // canonicalize to the signed form.
+        let signed = sign(&valid_assertion())
+            .replace(
+                " xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" ID=\"_assertion\" IssueInstant=\"2026-10-18T11:59:58Z\" Version=\"2.0\"",
+                " Version='2.0' IssueInstant=\"2026-10-18T11:59:58Z\" ID=\"_assertion\"",
+            )
+            .replace("></saml:SubjectConfirmationData>", "/>")
+            .replace("></saml:AuthnStatement>", " />");
+        let document = response(&signed);
+        assert!(validate(&document, &sp(), &idp(), NOW).is_ok());
+    }
+
+    #[test]
+    fn tampered_assertion() {
+        let document = response(&sign(&valid_assertion()).replace("student-42", "admin"));
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "digest mismatch"
+        );
+    }
+
+    #[test]
+    fn untrusted_key() {
+        let document = response(&sign_with(&valid_assertion(), SP_KEY));
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "signature mismatch"
+        );
+    }
+
+    #[test]
+    fn unsigned_assertion() {
+        let document = response(&valid_assertion());
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "unsigned assertion"
+        );
+    }
+
+    #[test]
+    fn wrapped_assertion() {
+        let evil = valid_assertion().replace("student-42", "admin");
+        let document = response(&format!(
+            "{evil}<samlp:Extensions>{}</samlp:Extensions>",
+            sign(&valid_assertion())
+        ));
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "duplicate ID"
+        );
+    }
+
+    #[test]
+    fn wrong_audience() {
+        let document = response(&sign(&assertion(
+            "https://other.example.org",
+            "https://ece.gg/auth/saml/acs",
+            "2026-10-18T12:05:00Z",
+        )));
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "audience mismatch"
+        );
+    }
+
+    #[test]
+    fn wrong_recipient() {
+        let document = response(&sign(&assertion(
+            "https://ece.gg/auth/saml/metadata",
+            "https://other.example.org/acs",
+            "2026-10-18T12:05:00Z",
+        )));
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "no valid bearer subject confirmation"
+        );
+    }
+
+    #[test]
+    fn expired_assertion() {
+        let document = response(&sign(&valid_assertion()));
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW + 3600)),
+            "no valid bearer subject confirmation"
+        );
+    }
+
+    #[test]
+    fn wrong_destination() {
+        let document = response(&sign(&valid_assertion())).replace(
+            "Destination=\"https://ece.gg/auth/saml/acs\"",
+            "Destination=\"https://other.example.org/acs\"",
+        );
+        assert_eq!(
+            reason(validate(&document, &sp(), &idp(), NOW)),
+            "unexpected destination"
+        );
+    }
+}
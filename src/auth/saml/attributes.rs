@@ -0,0 +1,350 @@
+//! Mapping of SAML attributes to CeresForge users.
+//!
+//! Attributes are matched by `Name` or `FriendlyName`. The defaults follow
+//! the eduPerson schema; each can be overridden for all identity providers:
+//!
+//! - `SAML_ATTRIBUTE_USERNAME`, `SAML_ATTRIBUTE_EMAIL` and
+//!   `SAML_ATTRIBUTE_DISPLAY_NAME` name the attribute supplying each field.
+//! - `SAML_ATTRIBUTE_ROLES` lists the attributes whose values decide the
+//!   role, e.g. `eduPersonAffiliation isMemberOf`.
+//! - `SAML_DEFAULT_ROLE` is the role of users matching no role map entry.
+//!
+//! Roles are only granted by identity providers named in `SAML_IDPS`, each
+//! configured with variables prefixed `SAML_IDP_<NAME>_`, the name
+//! upper-cased with `-` turned into `_`:
+//!
+//! - `ENTITY_ID` is the entity ID of the identity provider.
+//! - `ROLE_MAP` maps attribute values to roles as `value=role` pairs
+//!   separated by whitespace, e.g. `faculty=instructor cs-tas=ta`, and
+//!   defaults to `student=student faculty=instructor`. A user matching
+//!   several values gets the most privileged role.
+//! - `SCOPES` adds to the scopes the metadata grants the identity provider.
+//! - `ATTRIBUTE_USERNAME`, `ATTRIBUTE_EMAIL`, `ATTRIBUTE_DISPLAY_NAME`,
+//!   `ATTRIBUTE_ROLES` and `DEFAULT_ROLE` override their `SAML_` namesakes.
+//!
+//! Users of every other identity provider get the default role. Scoped
+//! values like `staff@example.edu`, in role attributes and usernames alike,
+//! only count if the identity provider may assert their scope; role map
+//! entries match them both in full and without scope.
+
+use crate::{
+    auth::saml::idp::IdentityProvider,
+    users::{Profile, Role},
+};
+
+use std::collections::BTreeMap;
+
+/// Attribute values by attribute name and friendly name.
+pub type Attributes = BTreeMap<String, Vec<String>>;
+
+/// Parses `value=role` pairs separated by whitespace, read from `var`.
+pub fn parse_role_map(var: &str, role_map: &str) -> Result<Vec<(String, Role)>, String> {
+    role_map
+        .split_whitespace()
+        .map(|pair| {
+            let (value, role) = pair
+                .rsplit_once('=')
+                .ok_or_else(|| format!("malformed {var} entry {pair}"))?;
+            Ok((value.to_string(), role.parse()?))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct AttributeMapping {
+    pub username: String,
+    pub email: String,
+    pub display_name: String,
+    pub roles: Vec<String>,
+    pub role_map: Vec<(String, Role)>,
+    pub default_role: Role,
+    /// Scopes values may carry, or `None` to accept any.
+    pub scopes: Option<Vec<String>>,
+}
+
+impl Default for AttributeMapping {
+    fn default() -> Self {
+        AttributeMapping {
+            username: "uid".to_string(),
+            email: "mail".to_string(),
+            display_name: "displayName".to_string(),
+            roles: vec!["eduPersonAffiliation".to_string()],
+            role_map: vec![
+                ("student".to_string(), Role::Student),
+                ("faculty".to_string(), Role::Instructor),
+            ],
+            default_role: Role::Student,
+            scopes: None,
+        }
+    }
+}
+
+impl AttributeMapping {
+    /// Reads the mapping for `idp` through `var`, which looks up environment
+    /// variables.
+    pub fn configure(
+        idp: &IdentityProvider,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, String> {
+        let prefix = var("SAML_IDPS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|name| format!("SAML_IDP_{}_", name.to_uppercase().replace('-', "_")))
+            .find(|prefix| var(&format!("{prefix}ENTITY_ID")).as_deref() == Some(&idp.entity_id));
+        let setting = |key: &str| {
+            prefix
+                .as_ref()
+                .and_then(|prefix| var(&format!("{prefix}{key}")))
+                .or_else(|| var(&format!("SAML_{key}")))
+        };
+
+        let mut mapping = AttributeMapping {
+            scopes: Some(idp.scopes.clone()),
+            ..AttributeMapping::default()
+        };
+        if let Some(username) = setting("ATTRIBUTE_USERNAME") {
+            mapping.username = username;
+        }
+        if let Some(email) = setting("ATTRIBUTE_EMAIL") {
+            mapping.email = email;
+        }
+        if let Some(display_name) = setting("ATTRIBUTE_DISPLAY_NAME") {
+            mapping.display_name = display_name;
+        }
+        if let Some(roles) = setting("ATTRIBUTE_ROLES") {
+            mapping.roles = roles.split_whitespace().map(str::to_string).collect();
+        }
+        if let Some(role) = setting("DEFAULT_ROLE") {
+            mapping.default_role = role.parse()?;
+        }
+        let Some(prefix) = prefix else {
+            mapping.role_map = Vec::new();
+            return Ok(mapping);
+        };
+        let role_map_var = format!("{prefix}ROLE_MAP");
+        if let Some(role_map) = var(&role_map_var) {
+            mapping.role_map = parse_role_map(&role_map_var, &role_map)?;
+        }
+        if let Some(scopes) = var(&format!("{prefix}SCOPES")) {
+            mapping
+                .scopes
+                .get_or_insert_with(Vec::new)
+                .extend(scopes.split_whitespace().map(str::to_string));
+        }
+        Ok(mapping)
+    }
+
+    pub fn from_env(idp: &IdentityProvider) -> Result<Self, String> {
+        AttributeMapping::configure(idp, |var| std::env::var(var).ok())
+    }
+
+    /// Splits a scoped value into the value and its scope, if the scope is
+    /// one the mapping accepts; unscoped values are returned as they are.
+    fn unscope<'a>(&self, value: &'a str) -> Option<(&'a str, Option<&'a str>)> {
+        let Some((unscoped, scope)) = value.rsplit_once('@') else {
+            return Some((value, None));
+        };
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|s| s.eq_ignore_ascii_case(scope)) => None,
+            _ => Some((unscoped, Some(scope))),
+        }
+    }
+
+    fn first<'a>(&self, attributes: &'a Attributes, name: &str) -> Option<&'a str> {
+        attributes
+            .get(name)
+            .and_then(|values| values.iter().map(|v| v.trim()).find(|v| !v.is_empty()))
+    }
+
+    fn role(&self, attributes: &Attributes) -> Role {
+        let values = self
+            .roles
+            .iter()
+            .filter_map(|name| attributes.get(name))
+            .flatten();
+        let mut role = None;
+        for value in values {
+            let Some((unscoped, scope)) = self.unscope(value) else {
+                continue;
+            };
+            for (mapped, mapped_role) in &self.role_map {
+                if mapped == value || (scope.is_some() && mapped == unscoped) {
+                    role = role.max(Some(*mapped_role));
+                }
+            }
+        }
+        role.unwrap_or(self.default_role)
+    }
+
+    /// Builds the profile of the user an assertion describes.
+    pub fn profile(&self, attributes: &Attributes) -> Result<Profile, String> {
+        let username = self
+            .first(attributes, &self.username)
+            .ok_or_else(|| format!("assertion has no {} attribute", self.username))?;
+        if self.unscope(username).is_none() {
+            return Err(format!("username {username} is out of the issuer's scope"));
+        }
+        Ok(Profile {
+            username: username.to_string(),
+            email: self.first(attributes, &self.email).map(str::to_string),
+            display_name: self
+                .first(attributes, &self.display_name)
+                .map(str::to_string),
+            role: self.role(attributes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(pairs: &[(&str, &[&str])]) -> Attributes {
+        pairs
+            .iter()
+            .map(|(name, values)| {
+                (
+                    name.to_string(),
+                    values.iter().map(|v| v.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_mapping() {
+        let profile = AttributeMapping::default()
+            .profile(&attributes(&[
+                ("uid", &["jdoe"]),
+                ("mail", &["jdoe@example.edu"]),
+                ("displayName", &["Jane Doe"]),
+                ("eduPersonAffiliation", &["member", "faculty", "student"]),
+            ]))
+            .unwrap();
+        assert_eq!(
+            profile,
+            Profile {
+                username: "jdoe".to_string(),
+                email: Some("jdoe@example.edu".to_string()),
+                display_name: Some("Jane Doe".to_string()),
+                role: Role::Instructor,
+            }
+        );
+    }
+
+    #[test]
+    fn groups_and_scoped_affiliations() {
+        let mapping = AttributeMapping {
+            roles: vec![
+                "eduPersonScopedAffiliation".to_string(),
+                "isMemberOf".to_string(),
+            ],
+            role_map: vec![
+                ("student".to_string(), Role::Student),
+                ("cs-tas".to_string(), Role::Ta),
+                ("cs-admins".to_string(), Role::Admin),
+            ],
+            ..AttributeMapping::default()
+        };
+        let student = attributes(&[
+            ("uid", &["jdoe"]),
+            ("eduPersonScopedAffiliation", &["student@example.edu"]),
+        ]);
+        assert_eq!(mapping.profile(&student).unwrap().role, Role::Student);
+
+        let ta = attributes(&[
+            ("uid", &["jdoe"]),
+            ("eduPersonScopedAffiliation", &["student@example.edu"]),
+            ("isMemberOf", &["cs-tas"]),
+        ]);
+        assert_eq!(mapping.profile(&ta).unwrap().role, Role::Ta);
+
+        let admin = attributes(&[("uid", &["root"]), ("isMemberOf", &["cs-tas", "cs-admins"])]);
+        assert_eq!(mapping.profile(&admin).unwrap().role, Role::Admin);
+    }
+
+    fn idp(entity_id: &str, scopes: &[&str]) -> IdentityProvider {
+        IdentityProvider {
+            entity_id: entity_id.to_string(),
+            name: entity_id.to_string(),
+            sso_url: format!("{entity_id}/sso"),
+            slo_url: None,
+            keys: Vec::new(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn env(name: &str) -> Option<String> {
+        let value = match name {
+            "SAML_IDPS" => "uni-a",
+            "SAML_IDP_UNI_A_ENTITY_ID" => "https://idp.uni-a.example",
+            "SAML_IDP_UNI_A_ROLE_MAP" => "faculty=instructor cs-admins=admin",
+            "SAML_ATTRIBUTE_ROLES" => "eduPersonScopedAffiliation isMemberOf",
+            _ => return None,
+        };
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn role_maps_are_per_identity_provider() {
+        let uni_a =
+            AttributeMapping::configure(&idp("https://idp.uni-a.example", &["uni-a.example"]), env)
+                .unwrap();
+        let uni_b =
+            AttributeMapping::configure(&idp("https://idp.uni-b.example", &["uni-b.example"]), env)
+                .unwrap();
+
+        let admin = attributes(&[("uid", &["root"]), ("isMemberOf", &["cs-admins"])]);
+        assert_eq!(uni_a.profile(&admin).unwrap().role, Role::Admin);
+        // University B asserting University A's group gets nothing for it.
+        assert_eq!(uni_b.profile(&admin).unwrap().role, Role::Student);
+
+        let faculty = |scope: &str| {
+            attributes(&[
+                ("uid", &["jdoe"]),
+                ("eduPersonScopedAffiliation", &[&format!("faculty@{scope}")]),
+            ])
+        };
+        assert_eq!(
+            uni_a.profile(&faculty("uni-a.example")).unwrap().role,
+            Role::Instructor
+        );
+        assert_eq!(
+            uni_a.profile(&faculty("uni-b.example")).unwrap().role,
+            Role::Student
+        );
+        assert_eq!(
+            uni_b.profile(&faculty("uni-b.example")).unwrap().role,
+            Role::Student
+        );
+
+        let scoped = |username: &str| attributes(&[("uid", &[username])]);
+        assert_eq!(
+            uni_a
+                .profile(&scoped("jdoe@uni-a.example"))
+                .unwrap()
+                .username,
+            "jdoe@uni-a.example"
+        );
+        assert_eq!(
+            uni_b.profile(&scoped("jdoe@uni-a.example")).unwrap_err(),
+            "username jdoe@uni-a.example is out of the issuer's scope"
+        );
+    }
+
+    #[test]
+    fn default_role_and_missing_username() {
+        let mapping = AttributeMapping {
+            default_role: Role::Ta,
+            ..AttributeMapping::default()
+        };
+        let staff = attributes(&[("uid", &["jdoe"]), ("eduPersonAffiliation", &["staff"])]);
+        assert_eq!(mapping.profile(&staff).unwrap().role, Role::Ta);
+        assert_eq!(
+            mapping
+                .profile(&attributes(&[("mail", &["jdoe@example.edu"])]))
+                .unwrap_err(),
+            "assertion has no uid attribute"
+        );
+    }
+}
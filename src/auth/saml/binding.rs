@@ -0,0 +1,191 @@
+//! HTTP-Redirect binding: deflated, base64 encoded messages in the query
+//! string, signed over the query parameters rather than the XML.
+
+use crate::auth::saml::signature;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use rsa::{
+    RsaPrivateKey, RsaPublicKey,
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::Verifier,
+};
+use sha2::Sha256;
+use url::form_urlencoded::{byte_serialize, parse};
+
+/// Largest inflated message we accept.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// A message received over the redirect binding. Its signature still has to
+/// be checked with [`Message::verify`] once the sender is known.
+#[derive(Debug)]
+pub struct Message {
+    /// `SAMLRequest` or `SAMLResponse`.
+    pub parameter: &'static str,
+    pub xml: String,
+    pub relay_state: Option<String>,
+    signed: String,
+    signature: Vec<u8>,
+}
+
+impl Message {
+    /// Verifies the signature over the query string against any of `keys`.
+    pub fn verify(&self, keys: &[RsaPublicKey]) -> Result<(), String> {
+        let signature =
+            Signature::try_from(self.signature.as_slice()).map_err(|err| err.to_string())?;
+        keys.iter()
+            .find(|key| {
+                VerifyingKey::<Sha256>::new((*key).clone())
+                    .verify(self.signed.as_bytes(), &signature)
+                    .is_ok()
+            })
+            .map(|_| ())
+            .ok_or_else(|| "signature mismatch".to_string())
+    }
+}
+
+fn encode(value: &str) -> String {
+    byte_serialize(value.as_bytes()).collect()
+}
+
+fn decode(value: &str) -> String {
+    parse(format!("v={value}").as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns the URL that delivers `xml` to `destination` as `parameter`,
+/// signed with `key`.
+pub fn redirect_url(
+    destination: &str,
+    parameter: &str,
+    xml: &str,
+    relay_state: Option<&str>,
+    key: &RsaPrivateKey,
+) -> String {
+    let deflated = miniz_oxide::deflate::compress_to_vec(xml.as_bytes(), 6);
+
+    // The signature covers the query string exactly as sent, in this order.
+    let mut query = format!("{parameter}={}", encode(&BASE64_STANDARD.encode(deflated)));
+    if let Some(relay_state) = relay_state {
+        query.push_str(&format!("&RelayState={}", encode(relay_state)));
+    }
+    query.push_str(&format!("&SigAlg={}", encode(signature::RSA_SHA256)));
+    let signature = signature::sign(key, query.as_bytes());
+
+    let separator = if destination.contains('?') { '&' } else { '?' };
+    format!(
+        "{destination}{separator}{query}&Signature={}",
+        encode(&signature)
+    )
+}
+
+/// Decodes the message carried by a redirect binding query string.
+pub fn receive(query: &str) -> Result<Message, String> {
+    let mut message = None;
+    let mut relay_state = None;
+    let mut sig_alg = None;
+    let mut signature = None;
+    // The signature is over the raw parameter values, so they are kept as
+    // they appear in the query string.
+    for pair in query.split('&') {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let slot = match name {
+            "SAMLRequest" | "SAMLResponse" if message.is_some() => {
+                return Err("multiple SAML messages".to_string());
+            }
+            "SAMLRequest" => {
+                message = Some(("SAMLRequest", value));
+                continue;
+            }
+            "SAMLResponse" => {
+                message = Some(("SAMLResponse", value));
+                continue;
+            }
+            "RelayState" => &mut relay_state,
+            "SigAlg" => &mut sig_alg,
+            "Signature" => &mut signature,
+            _ => continue,
+        };
+        if slot.replace(value).is_some() {
+            return Err(format!("duplicate {name}"));
+        }
+    }
+
+    let (parameter, raw) = message.ok_or("missing SAML message")?;
+    let sig_alg = sig_alg.ok_or("unsigned message")?;
+    let signature = signature.ok_or("unsigned message")?;
+    if decode(sig_alg) != signature::RSA_SHA256 {
+        return Err("unsupported signature algorithm".to_string());
+    }
+
+    let mut signed = format!("{parameter}={raw}");
+    if let Some(relay_state) = relay_state {
+        signed.push_str(&format!("&RelayState={relay_state}"));
+    }
+    signed.push_str(&format!("&SigAlg={sig_alg}"));
+    let signature = BASE64_STANDARD
+        .decode(decode(signature))
+        .map_err(|err| err.to_string())?;
+
+    let deflated = BASE64_STANDARD
+        .decode(decode(raw))
+        .map_err(|err| err.to_string())?;
+    let xml = miniz_oxide::inflate::decompress_to_vec_with_limit(&deflated, MAX_MESSAGE)
+        .map_err(|_| "message does not inflate".to_string())?;
+    Ok(Message {
+        parameter,
+        xml: String::from_utf8(xml).map_err(|err| err.to_string())?,
+        relay_state: relay_state.map(decode),
+        signed,
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::public_key;
+    use rsa::pkcs8::DecodePrivateKey;
+
+    const SP_KEY: &str = include_str!("../../../tests/fixtures/saml/sp.key");
+    const SP_CERT: &str = include_str!("../../../tests/fixtures/saml/sp.crt");
+    const IDP_CERT: &str = include_str!("../../../tests/fixtures/saml/idp.crt");
+
+    fn key(pem: &str) -> RsaPublicKey {
+        let body: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+        public_key(&BASE64_STANDARD.decode(body).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let url = redirect_url(
+            "https://idp.example.edu/idp/slo",
+            "SAMLRequest",
+            "<samlp:LogoutRequest/>",
+            Some("/courses?id=1"),
+            &RsaPrivateKey::from_pkcs8_pem(SP_KEY).unwrap(),
+        );
+        let (_, query) = url.split_once('?').unwrap();
+        let message = receive(query).unwrap();
+        message.verify(&[key(SP_CERT)]).unwrap();
+        assert_eq!(message.parameter, "SAMLRequest");
+        assert_eq!(message.xml, "<samlp:LogoutRequest/>");
+        assert_eq!(message.relay_state.as_deref(), Some("/courses?id=1"));
+
+        assert_eq!(
+            message.verify(&[key(IDP_CERT)]).unwrap_err(),
+            "signature mismatch"
+        );
+        let tampered = query.replace("RelayState=%2F", "RelayState=%2F%2F");
+        assert_eq!(
+            receive(&tampered)
+                .unwrap()
+                .verify(&[key(SP_CERT)])
+                .unwrap_err(),
+            "signature mismatch"
+        );
+        let (unsigned, _) = query.split_once("&SigAlg=").unwrap();
+        assert_eq!(receive(unsigned).unwrap_err(), "unsigned message");
+    }
+}
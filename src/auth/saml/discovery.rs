@@ -0,0 +1,76 @@
+//! Discovery page letting users pick the identity provider of their
+//! institution.
+
+use crate::auth::saml::{IdentityProviders, login::local_path};
+
+use axum::{Extension, extract::Query, response::Html};
+use quick_xml::escape::escape;
+use serde::Deserialize;
+use url::form_urlencoded::Serializer;
+
+#[derive(Debug, Deserialize)]
+pub struct DiscoveryQuery {
+    return_to: Option<String>,
+    #[serde(default)]
+    link: bool,
+}
+
+fn render(idps: &IdentityProviders, return_to: Option<&str>, link: bool) -> String {
+    let providers: String = idps
+        .all()
+        .iter()
+        .map(|idp| {
+            let mut query = Serializer::new(String::new());
+            query.append_pair("idp", &idp.entity_id);
+            if let Some(return_to) = return_to {
+                query.append_pair("return_to", return_to);
+            }
+            if link {
+                query.append_pair("link", "true");
+            }
+            format!(
+                "      <li><a href=\"/auth/saml/login?{}\">{}</a></li>\n",
+                escape(query.finish()),
+                escape(&idp.name)
+            )
+        })
+        .collect();
+    include_str!("../../../frontend/saml-discovery.html").replace("{providers}\n", &providers)
+}
+
+pub async fn handler(
+    Extension(idps): Extension<IdentityProviders>,
+    Query(query): Query<DiscoveryQuery>,
+) -> Html<String> {
+    Html(render(
+        &idps,
+        local_path(query.return_to.as_deref()),
+        query.link,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::idp::IdentityProvider;
+
+    #[test]
+    fn links_each_provider() {
+        let idps = IdentityProviders::default();
+        idps.insert(
+            "federation.xml",
+            vec![IdentityProvider {
+                entity_id: "https://idp.uni-a.example/idp".to_string(),
+                name: "Arts & Sciences".to_string(),
+                sso_url: "https://idp.uni-a.example/sso".to_string(),
+                slo_url: None,
+                keys: Vec::new(),
+                scopes: Vec::new(),
+            }],
+        );
+        let page = render(&idps, Some("/courses"), false);
+        assert!(page.contains(
+            "<li><a href=\"/auth/saml/login?idp=https%3A%2F%2Fidp.uni-a.example%2Fidp&amp;return_to=%2Fcourses\">Arts &amp; Sciences</a></li>"
+        ));
+    }
+}
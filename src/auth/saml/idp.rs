@@ -0,0 +1,526 @@
+//! Identity providers, loaded from SAML metadata.
+//!
+//! `SAML_IDP_METADATA` lists metadata sources separated by whitespace: HTTPS
+//! URLs or local file paths. A source may describe a single identity
+//! provider or be a federation aggregate describing many. Sources are
+//! reloaded every [`REFRESH_INTERVAL`]; a source that fails to load keeps
+//! the identity providers it last described.
+//!
+//! Every identity provider a source describes is trusted to log users in,
+//! so a source may be followed by `|` and the path of a PEM certificate, as
+//! in `https://fed.example/metadata.xml|/etc/ceresforge/fed.pem`, whose key
+//! must have signed its metadata. Sources fetched over HTTPS need one;
+//! local files are trusted as they are. An entity that more than one source
+//! describes is refused, as there is no telling which is right.
+
+use crate::auth::saml::{
+    public_key, signature,
+    time::{now, parse_instant},
+    xml::{self, Element, SAMLP},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use rsa::RsaPublicKey;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+const MD: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const MDUI: &str = "urn:oasis:names:tc:SAML:metadata:ui";
+const SHIBMD: &str = "urn:mace:shibboleth:metadata:1.0";
+const HTTP_REDIRECT: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub struct IdentityProvider {
+    pub entity_id: String,
+    /// Human readable name shown on the discovery page.
+    pub name: String,
+    pub sso_url: String,
+    pub slo_url: Option<String>,
+    /// Signing keys; more than one during a key rollover.
+    pub keys: Vec<RsaPublicKey>,
+    /// Scopes of the values it may assert, like `example.edu` for
+    /// `staff@example.edu`.
+    pub scopes: Vec<String>,
+}
+
+/// The configured identity providers, keyed by metadata source.
+#[derive(Clone, Debug, Default)]
+pub struct IdentityProviders(Arc<RwLock<BTreeMap<String, Vec<Arc<IdentityProvider>>>>>);
+
+impl IdentityProviders {
+    /// Returns the identity provider `entity_id`, unless no source or more
+    /// than one describes it.
+    pub fn get(&self, entity_id: &str) -> Option<Arc<IdentityProvider>> {
+        let sources = self.0.read().unwrap();
+        let mut found = sources
+            .values()
+            .flatten()
+            .filter(|idp| idp.entity_id == entity_id);
+        let idp = found.next()?;
+        if found.next().is_some() {
+            tracing::warn!(entity_id, "identity provider described more than once");
+            return None;
+        }
+        Some(idp.clone())
+    }
+
+    /// Returns every identity provider described exactly once, ordered by
+    /// name.
+    pub fn all(&self) -> Vec<Arc<IdentityProvider>> {
+        let sources = self.0.read().unwrap();
+        let mut counts = BTreeMap::new();
+        for idp in sources.values().flatten() {
+            *counts.entry(idp.entity_id.as_str()).or_insert(0) += 1;
+        }
+        let mut idps: Vec<_> = sources
+            .values()
+            .flatten()
+            .filter(|idp| counts[idp.entity_id.as_str()] == 1)
+            .cloned()
+            .collect();
+        idps.sort_by(|a, b| a.name.cmp(&b.name));
+        idps
+    }
+
+    pub(super) fn insert(&self, source: &str, idps: Vec<IdentityProvider>) {
+        let idps = idps.into_iter().map(Arc::new).collect();
+        self.0.write().unwrap().insert(source.to_string(), idps);
+    }
+
+    /// Reloads every metadata source.
+    pub async fn refresh(&self) {
+        let sources = std::env::var("SAML_IDP_METADATA").unwrap_or_default();
+        for source in sources.split_whitespace() {
+            match load(source).await {
+                Ok(idps) => {
+                    tracing::debug!(source, count = idps.len(), "loaded SAML metadata");
+                    self.insert(source, idps);
+                }
+                Err(err) => tracing::warn!(source, err, "could not load SAML metadata"),
+            }
+        }
+    }
+}
+
+/// Refreshes `idps` forever. The caller is expected to have loaded them once.
+pub async fn schedule(idps: IdentityProviders) {
+    loop {
+        tokio::time::sleep(REFRESH_INTERVAL).await;
+        idps.refresh().await;
+    }
+}
+
+/// Reads the keys of the PEM certificate at `path` that metadata is signed
+/// with.
+fn signer(path: &str) -> Result<Vec<RsaPublicKey>, String> {
+    let pem = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = BASE64_STANDARD
+        .decode(body.trim())
+        .map_err(|err| format!("{path}: {err}"))?;
+    Ok(vec![
+        public_key(&der).map_err(|err| format!("{path}: {err}"))?,
+    ])
+}
+
+async fn load(source: &str) -> Result<Vec<IdentityProvider>, String> {
+    let (location, signer) = match source.split_once('|') {
+        Some((location, certificate)) => (location, Some(signer(certificate)?)),
+        None => (source, None),
+    };
+    if location.starts_with("https://") && signer.is_none() {
+        return Err("metadata fetched over HTTPS needs a signing certificate".to_string());
+    }
+    let document = if location.starts_with("https://") {
+        reqwest::get(location)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?
+            .text()
+            .await
+            .map_err(|err| err.to_string())?
+    } else if location.starts_with("http://") {
+        return Err("metadata must be fetched over HTTPS".to_string());
+    } else {
+        std::fs::read_to_string(location).map_err(|err| err.to_string())?
+    };
+    parse(&document, signer.as_deref(), now())
+}
+
+/// Returns whether `element` is past its `validUntil`.
+fn expired(element: &Element, now: i64) -> bool {
+    element
+        .attribute("validUntil")
+        .is_some_and(|until| parse_instant(until).is_none_or(|until| until <= now))
+}
+
+/// Collects the EntityDescriptors of a metadata document, descending into
+/// EntitiesDescriptors.
+fn entities<'a>(element: &'a Element, now: i64, out: &mut Vec<&'a Element>) {
+    if expired(element, now) {
+        return;
+    }
+    if element.is(MD, "EntityDescriptor") {
+        out.push(element);
+    } else if element.is(MD, "EntitiesDescriptor") {
+        for child in element.elements() {
+            entities(child, now, out);
+        }
+    }
+}
+
+/// Extracts the identity providers from a metadata document, which must be
+/// signed with one of `signer` if given. Entities that are not usable
+/// identity providers are skipped.
+pub fn parse(
+    document: &str,
+    signer: Option<&[RsaPublicKey]>,
+    now: i64,
+) -> Result<Vec<IdentityProvider>, String> {
+    let root = xml::parse(document)?;
+    if !root.is(MD, "EntityDescriptor") && !root.is(MD, "EntitiesDescriptor") {
+        return Err("not a SAML metadata document".to_string());
+    }
+    if let Some(keys) = signer {
+        signature::verify(&root, keys).map_err(|err| format!("metadata signature: {err}"))?;
+    }
+    if expired(&root, now) {
+        return Err("metadata has expired".to_string());
+    }
+
+    let mut found = Vec::new();
+    entities(&root, now, &mut found);
+    let mut idps = Vec::new();
+    for entity in found {
+        match identity_provider(entity) {
+            Ok(Some(idp)) => idps.push(idp),
+            Ok(None) => {}
+            Err(err) => {
+                let entity_id = entity.attribute("entityID");
+                tracing::warn!(entity_id, err, "skipping identity provider");
+            }
+        }
+    }
+    if idps.is_empty() {
+        return Err("metadata describes no usable identity provider".to_string());
+    }
+    Ok(idps)
+}
+
+fn location<'a>(descriptor: &'a Element, service: &'a str) -> Option<&'a str> {
+    descriptor
+        .children(MD, service)
+        .find(|endpoint| endpoint.attribute("Binding") == Some(HTTP_REDIRECT))
+        .and_then(|endpoint| endpoint.attribute("Location"))
+}
+
+fn signing_keys(descriptor: &Element) -> Result<Vec<RsaPublicKey>, String> {
+    let mut keys = Vec::new();
+    for key_descriptor in descriptor.children(MD, "KeyDescriptor") {
+        if key_descriptor
+            .attribute("use")
+            .is_some_and(|key_use| key_use != "signing")
+        {
+            continue;
+        }
+        let certificates = key_descriptor
+            .children(xml::DS, "KeyInfo")
+            .flat_map(|info| info.children(xml::DS, "X509Data"))
+            .flat_map(|data| data.children(xml::DS, "X509Certificate"));
+        for certificate in certificates {
+            let der: String = certificate
+                .text()
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            let der = BASE64_STANDARD.decode(der).map_err(|err| err.to_string())?;
+            keys.push(public_key(&der).map_err(|err| err.to_string())?);
+        }
+    }
+    Ok(keys)
+}
+
+fn display_name(entity: &Element, descriptor: &Element) -> Option<String> {
+    let ui_name = descriptor
+        .child(MD, "Extensions")
+        .and_then(|extensions| extensions.child(MDUI, "UIInfo"))
+        .and_then(|info| info.child(MDUI, "DisplayName"));
+    let organization_name = entity
+        .child(MD, "Organization")
+        .and_then(|organization| organization.child(MD, "OrganizationDisplayName"));
+    ui_name
+        .or(organization_name)
+        .map(|name| name.text().trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Returns the literal `shibmd:Scope`s of an IdPSSODescriptor. Regular
+/// expression scopes are ignored.
+fn scopes(descriptor: &Element) -> Vec<String> {
+    descriptor
+        .child(MD, "Extensions")
+        .into_iter()
+        .flat_map(|extensions| extensions.children(SHIBMD, "Scope"))
+        .filter(|scope| scope.attribute("regexp") != Some("true"))
+        .map(|scope| scope.text().trim().to_string())
+        .filter(|scope| !scope.is_empty())
+        .collect()
+}
+
+fn identity_provider(entity: &Element) -> Result<Option<IdentityProvider>, String> {
+    let Some(descriptor) = entity.children(MD, "IDPSSODescriptor").find(|descriptor| {
+        descriptor
+            .attribute("protocolSupportEnumeration")
+            .is_some_and(|protocols| protocols.split_whitespace().any(|p| p == SAMLP))
+    }) else {
+        return Ok(None);
+    };
+    let entity_id = entity
+        .attribute("entityID")
+        .ok_or("entity has no entityID")?;
+    let sso_url = location(descriptor, "SingleSignOnService")
+        .ok_or("no HTTP-Redirect SingleSignOnService")?;
+    let keys = signing_keys(descriptor)?;
+    if keys.is_empty() {
+        return Err("no signing certificate".to_string());
+    }
+
+    Ok(Some(IdentityProvider {
+        entity_id: entity_id.to_string(),
+        name: display_name(entity, descriptor).unwrap_or_else(|| entity_id.to_string()),
+        sso_url: sso_url.to_string(),
+        slo_url: location(descriptor, "SingleLogoutService").map(str::to_string),
+        keys,
+        scopes: scopes(descriptor),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::signature::{ENVELOPED, EXC_C14N, RSA_SHA256, SHA256};
+    use rsa::{RsaPrivateKey, pkcs8::DecodePrivateKey};
+    use sha2::{Digest, Sha256};
+
+    const IDP_CERTIFICATE: &str = include_str!("../../../tests/fixtures/saml/idp.crt");
+    const IDP_KEY: &str = include_str!("../../../tests/fixtures/saml/idp.key");
+    const SP_KEY: &str = include_str!("../../../tests/fixtures/saml/sp.key");
+
+    // 2026-10-18T12:00:00Z
+    const NOW: i64 = 1792324800;
+
+    fn certificate() -> String {
+        IDP_CERTIFICATE
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn entity(entity_id: &str, name: &str) -> String {
+        let scope = entity_id.trim_start_matches("https://idp.");
+        format!(
+            r#"<md:EntityDescriptor entityID="{entity_id}">
+    <md:IDPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol">
+      <md:Extensions>
+        <shibmd:Scope regexp="false">{scope}</shibmd:Scope>
+        <shibmd:Scope regexp="true">^.+\.example$</shibmd:Scope>
+        <mdui:UIInfo><mdui:DisplayName xml:lang="en">{name}</mdui:DisplayName></mdui:UIInfo>
+      </md:Extensions>
+      <md:KeyDescriptor use="signing">
+        <ds:KeyInfo><ds:X509Data><ds:X509Certificate>
+{}
+        </ds:X509Certificate></ds:X509Data></ds:KeyInfo>
+      </md:KeyDescriptor>
+      <md:KeyDescriptor use="encryption">
+        <ds:KeyInfo><ds:X509Data><ds:X509Certificate>not a certificate</ds:X509Certificate></ds:X509Data></ds:KeyInfo>
+      </md:KeyDescriptor>
+      <md:SingleLogoutService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect" Location="{entity_id}/slo"/>
+      <md:SingleSignOnService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST" Location="{entity_id}/sso-post"/>
+      <md:SingleSignOnService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect" Location="{entity_id}/sso"/>
+    </md:IDPSSODescriptor>
+  </md:EntityDescriptor>"#,
+            certificate()
+        )
+    }
+
+    fn aggregate(valid_until: &str, entities: &[String]) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+<md:EntitiesDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata"
+    xmlns:mdui="urn:oasis:names:tc:SAML:metadata:ui"
+    xmlns:shibmd="urn:mace:shibboleth:metadata:1.0"
+    xmlns:ds="http://www.w3.org/2000/09/xmldsig#" validUntil="{valid_until}">
+  {}
+  <md:EntityDescriptor entityID="https://sp.example.edu">
+    <md:SPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol"/>
+  </md:EntityDescriptor>
+</md:EntitiesDescriptor>"#,
+            entities.join("\n")
+        )
+    }
+
+    /// Signs an aggregate with `key` the way a federation would.
+    fn sign_aggregate(document: &str, key: &str) -> String {
+        let document = document.replacen(
+            "<md:EntitiesDescriptor ",
+            "<md:EntitiesDescriptor ID=\"_metadata\" ",
+            1,
+        );
+        let root = xml::parse(&document).unwrap();
+        let digest =
+            BASE64_STANDARD.encode(Sha256::digest(root.canonicalize(None, &[]).as_bytes()));
+        let signed_info = format!(
+            "<ds:SignedInfo xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">\
+             <ds:CanonicalizationMethod Algorithm=\"{EXC_C14N}\"></ds:CanonicalizationMethod>\
+             <ds:SignatureMethod Algorithm=\"{RSA_SHA256}\"></ds:SignatureMethod>\
+             <ds:Reference URI=\"#_metadata\">\
+             <ds:Transforms>\
+             <ds:Transform Algorithm=\"{ENVELOPED}\"></ds:Transform>\
+             <ds:Transform Algorithm=\"{EXC_C14N}\"></ds:Transform>\
+             </ds:Transforms>\
+             <ds:DigestMethod Algorithm=\"{SHA256}\"></ds:DigestMethod>\
+             <ds:DigestValue>{digest}</ds:DigestValue>\
+             </ds:Reference>\
+             </ds:SignedInfo>"
+        );
+        let key = RsaPrivateKey::from_pkcs8_pem(key).unwrap();
+        let value = signature::sign(&key, signed_info.as_bytes());
+        let signature = format!(
+            "<ds:Signature xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">{}<ds:SignatureValue>{value}</ds:SignatureValue></ds:Signature>",
+            signed_info.replace(" xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\"", "")
+        );
+        let start = document.find("<md:EntitiesDescriptor").unwrap();
+        let at = start + document[start..].find('>').unwrap() + 1;
+        format!("{}{signature}{}", &document[..at], &document[at..])
+    }
+
+    fn federation_keys() -> Vec<RsaPublicKey> {
+        let der = BASE64_STANDARD
+            .decode(certificate().replace('\n', ""))
+            .unwrap();
+        vec![public_key(&der).unwrap()]
+    }
+
+    #[test]
+    fn signed_aggregate() {
+        let document = aggregate(
+            "2026-11-01T00:00:00Z",
+            &[
+                entity("https://idp.uni-a.example", "University A"),
+                entity("https://idp.uni-b.example", "University B"),
+            ],
+        );
+        let keys = federation_keys();
+        let signed = sign_aggregate(&document, IDP_KEY);
+        assert_eq!(parse(&signed, Some(&keys), NOW).unwrap().len(), 2);
+
+        let tampered = signed.replace("University B", "University C");
+        assert_eq!(
+            parse(&tampered, Some(&keys), NOW).unwrap_err(),
+            "metadata signature: digest mismatch"
+        );
+        let smuggled = signed.replace(
+            "</md:EntitiesDescriptor>",
+            &format!(
+                "{}</md:EntitiesDescriptor>",
+                entity("https://idp.evil.example", "Evil")
+            ),
+        );
+        assert!(parse(&smuggled, Some(&keys), NOW).is_err());
+        assert_eq!(
+            parse(&sign_aggregate(&document, SP_KEY), Some(&keys), NOW).unwrap_err(),
+            "metadata signature: signature mismatch"
+        );
+        assert_eq!(
+            parse(&document, Some(&keys), NOW).unwrap_err(),
+            "metadata signature: missing signature"
+        );
+    }
+
+    #[test]
+    fn federation_aggregate() {
+        let document = aggregate(
+            "2026-11-01T00:00:00Z",
+            &[
+                entity("https://idp.uni-a.example", "University A"),
+                entity("https://idp.uni-b.example", "University B"),
+            ],
+        );
+        let idps = parse(&document, None, NOW).unwrap();
+        assert_eq!(idps.len(), 2);
+        assert_eq!(idps[0].entity_id, "https://idp.uni-a.example");
+        assert_eq!(idps[0].name, "University A");
+        assert_eq!(idps[0].sso_url, "https://idp.uni-a.example/sso");
+        assert_eq!(
+            idps[0].slo_url.as_deref(),
+            Some("https://idp.uni-a.example/slo")
+        );
+        assert_eq!(idps[0].keys.len(), 1);
+        assert_eq!(idps[0].scopes, ["uni-a.example"]);
+        assert_eq!(idps[1].name, "University B");
+    }
+
+    #[test]
+    fn expired_metadata() {
+        let document = aggregate(
+            "2026-10-01T00:00:00Z",
+            &[entity("https://idp.uni-a.example", "University A")],
+        );
+        assert_eq!(
+            parse(&document, None, NOW).unwrap_err(),
+            "metadata has expired"
+        );
+    }
+
+    #[test]
+    fn unusable_entities_are_skipped() {
+        let broken = entity("https://idp.uni-b.example", "University B").replace(
+            "bindings:HTTP-Redirect\" Location=\"https://idp.uni-b.example/sso",
+            "bindings:SOAP\" Location=\"https://idp.uni-b.example/sso",
+        );
+        let document = aggregate(
+            "2026-11-01T00:00:00Z",
+            &[entity("https://idp.uni-a.example", "University A"), broken],
+        );
+        let idps = parse(&document, None, NOW).unwrap();
+        assert_eq!(idps.len(), 1);
+        assert_eq!(idps[0].entity_id, "https://idp.uni-a.example");
+    }
+
+    #[test]
+    fn registry() {
+        let idps = IdentityProviders::default();
+        let document = aggregate(
+            "2026-11-01T00:00:00Z",
+            &[
+                entity("https://idp.uni-b.example", "University B"),
+                entity("https://idp.uni-a.example", "University A"),
+            ],
+        );
+        idps.insert("federation.xml", parse(&document, None, NOW).unwrap());
+        let names: Vec<_> = idps.all().iter().map(|idp| idp.name.clone()).collect();
+        assert_eq!(names, ["University A", "University B"]);
+        assert!(idps.get("https://idp.uni-b.example").is_some());
+        assert!(idps.get("https://idp.uni-c.example").is_none());
+
+        // Another source describing University B makes it ambiguous.
+        let document = aggregate(
+            "2026-11-01T00:00:00Z",
+            &[entity("https://idp.uni-b.example", "University B (local)")],
+        );
+        idps.insert("uni-b.xml", parse(&document, None, NOW).unwrap());
+        assert!(idps.get("https://idp.uni-b.example").is_none());
+        assert!(idps.get("https://idp.uni-a.example").is_some());
+        let names: Vec<_> = idps.all().iter().map(|idp| idp.name.clone()).collect();
+        assert_eq!(names, ["University A"]);
+    }
+}
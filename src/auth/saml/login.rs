@@ -0,0 +1,305 @@
+//! SP-initiated login, HTTP-Redirect binding.
+
+use crate::{
+    api::{ApiError, ApiResult, error::UnknownIdentityProvider},
+    auth::{
+        saml::{
+            IdentityProviders, ServiceProvider, binding,
+            idp::IdentityProvider,
+            private_key,
+            time::{format_instant, now},
+        },
+        session::{OwnUser, random_token},
+    },
+};
+
+use axum::{
+    Extension,
+    extract::Query,
+    response::{IntoResponse, Redirect},
+};
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use url::form_urlencoded::Serializer;
+
+const HTTP_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const PERSISTENT: &str = "urn:oasis:names:tc:SAML:2.0:nameid-format:persistent";
+
+/// Longest RelayState we send; SAML bindings only guarantee 80 bytes.
+const MAX_RELAY_STATE: usize = 80;
+
+#[derive(Serialize, Debug)]
+#[serde(rename = "samlp:AuthnRequest")]
+struct AuthnRequest {
+    #[serde(rename = "@xmlns:samlp")]
+    xmlns_samlp: String,
+
+    #[serde(rename = "@xmlns:saml")]
+    xmlns_saml: String,
+
+    #[serde(rename = "@ID")]
+    id: String,
+
+    #[serde(rename = "@Version")]
+    version: String,
+
+    #[serde(rename = "@IssueInstant")]
+    issue_instant: String,
+
+    #[serde(rename = "@Destination")]
+    destination: String,
+
+    #[serde(rename = "@AssertionConsumerServiceURL")]
+    assertion_consumer_service_url: String,
+
+    #[serde(rename = "@ProtocolBinding")]
+    protocol_binding: String,
+
+    #[serde(rename = "saml:Issuer")]
+    issuer: String,
+
+    #[serde(rename = "samlp:NameIDPolicy")]
+    name_id_policy: NameIdPolicy,
+}
+
+#[derive(Serialize, Debug)]
+struct NameIdPolicy {
+    #[serde(rename = "@Format")]
+    format: String,
+
+    #[serde(rename = "@AllowCreate")]
+    allow_create: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    /// Entity ID of the identity provider to log in with.
+    idp: Option<String>,
+    return_to: Option<String>,
+    /// Whether to link the identity to the logged in user rather than log
+    /// in with it.
+    #[serde(default)]
+    link: bool,
+}
+
+/// Returns `path` if it is safe to redirect to after login, i.e. a path on
+/// this site rather than an absolute or protocol-relative URL. Browsers drop
+/// tabs and newlines from `Location`, and other characters outside printable
+/// ASCII are no valid header value, so neither may appear.
+pub fn local_path(path: Option<&str>) -> Option<&str> {
+    path.filter(|path| {
+        path.starts_with('/')
+            && !path.starts_with("//")
+            && !path.contains('\\')
+            && path.chars().all(|c| c.is_ascii_graphic())
+            && path.len() <= MAX_RELAY_STATE
+    })
+}
+
+fn discovery_url(return_to: Option<&str>, link: bool) -> String {
+    let mut query = Serializer::new(String::new());
+    if let Some(return_to) = return_to {
+        query.append_pair("return_to", return_to);
+    }
+    if link {
+        query.append_pair("link", "true");
+    }
+    match query.finish() {
+        query if query.is_empty() => "/auth/saml/discovery".to_string(),
+        query => format!("/auth/saml/discovery?{query}"),
+    }
+}
+
+/// Builds the redirect URL carrying a signed AuthnRequest with ID `id`.
+fn redirect_url(
+    sp: &ServiceProvider,
+    idp: &IdentityProvider,
+    key: &RsaPrivateKey,
+    id: &str,
+    issue_instant: i64,
+    relay_state: Option<&str>,
+) -> ApiResult<String> {
+    let request = AuthnRequest {
+        xmlns_samlp: "urn:oasis:names:tc:SAML:2.0:protocol".to_string(),
+        xmlns_saml: "urn:oasis:names:tc:SAML:2.0:assertion".to_string(),
+        id: id.to_string(),
+        version: "2.0".to_string(),
+        issue_instant: format_instant(issue_instant),
+        destination: idp.sso_url.clone(),
+        assertion_consumer_service_url: sp.acs_url.clone(),
+        protocol_binding: HTTP_POST.to_string(),
+        issuer: sp.entity_id.clone(),
+        name_id_policy: NameIdPolicy {
+            format: PERSISTENT.to_string(),
+            allow_create: true,
+        },
+    };
+    let xml = quick_xml::se::to_string(&request)?;
+    Ok(binding::redirect_url(
+        &idp.sso_url,
+        "SAMLRequest",
+        &xml,
+        relay_state,
+        key,
+    ))
+}
+
+/// Records an outstanding request so its answer can be matched by
+/// `InResponseTo`, along with the user starting it if it is for linking.
+pub async fn remember_request(
+    pool: &PgPool,
+    id: &str,
+    idp: &IdentityProvider,
+    link_user_id: Option<i64>,
+) -> ApiResult<()> {
+    sqlx::query("DELETE FROM saml_requests WHERE created_at < now() - interval '10 minutes'")
+        .execute(pool)
+        .await?;
+    sqlx::query("INSERT INTO saml_requests (id, idp, link_user_id) VALUES ($1, $2, $3)")
+        .bind(id)
+        .bind(&idp.entity_id)
+        .bind(link_user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(idps): Extension<IdentityProviders>,
+    user: Result<OwnUser, ApiError>,
+    Query(query): Query<LoginQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let return_to = local_path(query.return_to.as_deref());
+    let link_user_id = if query.link { Some(user?.0.id) } else { None };
+    let idp = match &query.idp {
+        Some(entity_id) => idps
+            .get(entity_id)
+            .ok_or_else(|| UnknownIdentityProvider::new(entity_id.clone()))?,
+        None => match idps.all().as_slice() {
+            [idp] => idp.clone(),
+            _ => return Ok(Redirect::to(&discovery_url(return_to, query.link))),
+        },
+    };
+    let sp = ServiceProvider::from_env()?;
+    let key = private_key()?;
+
+    let id = format!("_{}", random_token());
+    let url = redirect_url(&sp, &idp, &key, &id, now(), return_to)?;
+
+    remember_request(&pool, &id, &idp, link_user_id).await?;
+
+    Ok(Redirect::to(&url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::{
+        public_key,
+        signature::RSA_SHA256,
+        xml::{self, SAML, SAMLP},
+    };
+    use base64::{Engine, prelude::BASE64_STANDARD};
+    use rsa::{
+        pkcs1v15::{Signature, VerifyingKey},
+        pkcs8::DecodePrivateKey,
+        signature::Verifier,
+    };
+    use sha2::Sha256;
+
+    const SP_KEY: &str = include_str!("../../../tests/fixtures/saml/sp.key");
+    const SP_CERT: &str = include_str!("../../../tests/fixtures/saml/sp.crt");
+    const IDP_CERT: &str = include_str!("../../../tests/fixtures/saml/idp.crt");
+
+    fn der(pem: &str) -> Vec<u8> {
+        let body: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+        BASE64_STANDARD.decode(body).unwrap()
+    }
+
+    fn parameter(query: &str, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    #[test]
+    fn signed_redirect() {
+        let sp = ServiceProvider::new("https://ceresforge.example.edu");
+        let idp = IdentityProvider {
+            entity_id: "https://idp.example.edu/idp".to_string(),
+            name: "Example University".to_string(),
+            sso_url: "https://idp.example.edu/idp/sso".to_string(),
+            slo_url: None,
+            keys: vec![public_key(&der(IDP_CERT)).unwrap()],
+            scopes: Vec::new(),
+        };
+        let key = RsaPrivateKey::from_pkcs8_pem(SP_KEY).unwrap();
+
+        let url = redirect_url(&sp, &idp, &key, "_abc", 1792324800, Some("/courses/1")).unwrap();
+        let (base, query) = url.split_once('?').unwrap();
+        assert_eq!(base, idp.sso_url);
+        assert_eq!(parameter(query, "RelayState").unwrap(), "/courses/1");
+        assert_eq!(parameter(query, "SigAlg").unwrap(), RSA_SHA256);
+
+        let (signed, _) = query.split_once("&Signature=").unwrap();
+        let signature = BASE64_STANDARD
+            .decode(parameter(query, "Signature").unwrap())
+            .unwrap();
+        let verifying_key = VerifyingKey::<Sha256>::new(public_key(&der(SP_CERT)).unwrap());
+        verifying_key
+            .verify(
+                signed.as_bytes(),
+                &Signature::try_from(signature.as_slice()).unwrap(),
+            )
+            .unwrap();
+
+        let deflated = BASE64_STANDARD
+            .decode(parameter(query, "SAMLRequest").unwrap())
+            .unwrap();
+        let inflated = miniz_oxide::inflate::decompress_to_vec(&deflated).unwrap();
+        let request = xml::parse(std::str::from_utf8(&inflated).unwrap()).unwrap();
+        assert!(request.is(SAMLP, "AuthnRequest"));
+        assert_eq!(request.attribute("ID"), Some("_abc"));
+        assert_eq!(
+            request.attribute("IssueInstant"),
+            Some("2026-10-18T12:00:00Z")
+        );
+        assert_eq!(
+            request.attribute("Destination"),
+            Some("https://idp.example.edu/idp/sso")
+        );
+        assert_eq!(
+            request.attribute("AssertionConsumerServiceURL"),
+            Some(sp.acs_url.as_str())
+        );
+        assert_eq!(request.child(SAML, "Issuer").unwrap().text(), sp.entity_id);
+    }
+
+    #[test]
+    fn local_paths_only() {
+        assert_eq!(local_path(Some("/courses")), Some("/courses"));
+        assert_eq!(local_path(Some("https://evil.example")), None);
+        assert_eq!(local_path(Some("//evil.example")), None);
+        assert_eq!(local_path(Some("/\\evil.example")), None);
+        assert_eq!(local_path(Some("/\t/evil.example")), None);
+        assert_eq!(local_path(Some("/\n/evil.example")), None);
+        assert_eq!(local_path(Some("/\r\nSet-Cookie: a=b")), None);
+        assert_eq!(local_path(Some("/caf\u{e9}")), None);
+        assert_eq!(
+            local_path(Some("/courses/algo?tab=grades#top")),
+            Some("/courses/algo?tab=grades#top")
+        );
+        assert_eq!(local_path(None), None);
+    }
+
+    #[test]
+    fn discovery_keeps_parameters() {
+        assert_eq!(discovery_url(None, false), "/auth/saml/discovery");
+        assert_eq!(
+            discovery_url(Some("/courses/1"), true),
+            "/auth/saml/discovery?return_to=%2Fcourses%2F1&link=true"
+        );
+    }
+}
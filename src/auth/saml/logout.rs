@@ -0,0 +1,535 @@
+//! Single Logout, HTTP-Redirect binding.
+//!
+//! Logging out of CeresForge ends the local session and sends a
+//! `LogoutRequest` to the identity provider. Logouts started elsewhere arrive
+//! as a `LogoutRequest` from the identity provider and end every matching
+//! session before we answer with a `LogoutResponse`.
+
+use crate::{
+    api::{
+        ApiResult,
+        error::{InvalidSamlRequest, InvalidSamlResponse},
+    },
+    auth::{
+        saml::{
+            IdentityProviders, ServiceProvider,
+            acs::consume_request,
+            binding,
+            idp::IdentityProvider,
+            login::remember_request,
+            private_key,
+            time::{CLOCK_SKEW, format_instant, now, parse_instant},
+            xml::{self, Element, SAML, SAMLP},
+        },
+        session::{self, CLEAR_COOKIE, Subject, random_token},
+    },
+};
+
+use axum::{
+    Extension,
+    extract::RawQuery,
+    http::{HeaderMap, header},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+};
+use serde::Serialize;
+use sqlx::PgPool;
+
+const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+
+/// How old an identity provider's `LogoutRequest` may be, in seconds.
+const MAX_REQUEST_AGE: i64 = 300;
+
+#[derive(Serialize, Debug)]
+#[serde(rename = "samlp:LogoutRequest")]
+struct LogoutRequestMessage {
+    #[serde(rename = "@xmlns:samlp")]
+    xmlns_samlp: String,
+
+    #[serde(rename = "@xmlns:saml")]
+    xmlns_saml: String,
+
+    #[serde(rename = "@ID")]
+    id: String,
+
+    #[serde(rename = "@Version")]
+    version: String,
+
+    #[serde(rename = "@IssueInstant")]
+    issue_instant: String,
+
+    #[serde(rename = "@Destination")]
+    destination: String,
+
+    #[serde(rename = "saml:Issuer")]
+    issuer: String,
+
+    #[serde(rename = "saml:NameID")]
+    name_id: NameId,
+
+    #[serde(rename = "samlp:SessionIndex", skip_serializing_if = "Option::is_none")]
+    session_index: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct NameId {
+    #[serde(rename = "@Format", skip_serializing_if = "Option::is_none")]
+    format: Option<String>,
+
+    #[serde(rename = "$text")]
+    value: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename = "samlp:LogoutResponse")]
+struct LogoutResponseMessage {
+    #[serde(rename = "@xmlns:samlp")]
+    xmlns_samlp: String,
+
+    #[serde(rename = "@xmlns:saml")]
+    xmlns_saml: String,
+
+    #[serde(rename = "@ID")]
+    id: String,
+
+    #[serde(rename = "@Version")]
+    version: String,
+
+    #[serde(rename = "@IssueInstant")]
+    issue_instant: String,
+
+    #[serde(rename = "@Destination")]
+    destination: String,
+
+    #[serde(rename = "@InResponseTo")]
+    in_response_to: String,
+
+    #[serde(rename = "saml:Issuer")]
+    issuer: String,
+
+    #[serde(rename = "samlp:Status")]
+    status: Status,
+}
+
+#[derive(Serialize, Debug)]
+struct Status {
+    #[serde(rename = "samlp:StatusCode")]
+    status_code: StatusCode,
+}
+
+#[derive(Serialize, Debug)]
+struct StatusCode {
+    #[serde(rename = "@Value")]
+    value: String,
+}
+
+/// A validated `LogoutRequest` from the identity provider.
+#[derive(Debug)]
+pub struct LogoutRequest {
+    pub id: String,
+    pub name_id: String,
+    pub session_indexes: Vec<String>,
+}
+
+fn invalid_request(reason: &str) -> InvalidSamlRequest {
+    InvalidSamlRequest::new(reason.to_string())
+}
+
+fn invalid_response(reason: &str) -> InvalidSamlResponse {
+    InvalidSamlResponse::new(reason.to_string())
+}
+
+fn issued_by(element: &Element, idp: &IdentityProvider) -> bool {
+    element
+        .child(SAML, "Issuer")
+        .is_some_and(|issuer| issuer.text().trim() == idp.entity_id)
+}
+
+/// Returns the issuer of a logout message, before it has been verified.
+fn issuer(document: &str) -> Option<String> {
+    let message = xml::parse(document).ok()?;
+    Some(message.child(SAML, "Issuer")?.text().trim().to_string())
+}
+
+fn addressed_to(element: &Element, sp: &ServiceProvider) -> bool {
+    element
+        .attribute("Destination")
+        .is_none_or(|destination| destination == sp.slo_url)
+}
+
+/// Validates a `LogoutRequest` whose binding signature has been checked.
+pub fn validate_request(
+    document: &str,
+    sp: &ServiceProvider,
+    idp: &IdentityProvider,
+    now: i64,
+) -> Result<LogoutRequest, InvalidSamlRequest> {
+    let request = xml::parse(document).map_err(InvalidSamlRequest::new)?;
+    if !request.is(SAMLP, "LogoutRequest") || request.attribute("Version") != Some("2.0") {
+        return Err(invalid_request("not a SAML 2.0 logout request"));
+    }
+    let id = request
+        .attribute("ID")
+        .ok_or_else(|| invalid_request("logout request has no ID"))?;
+    if !addressed_to(&request, sp) {
+        return Err(invalid_request("unexpected destination"));
+    }
+    if !issued_by(&request, idp) {
+        return Err(invalid_request("unexpected issuer"));
+    }
+
+    let issue_instant = request
+        .attribute("IssueInstant")
+        .and_then(parse_instant)
+        .ok_or_else(|| invalid_request("malformed IssueInstant"))?;
+    if issue_instant - CLOCK_SKEW > now || issue_instant + MAX_REQUEST_AGE + CLOCK_SKEW < now {
+        return Err(invalid_request("stale logout request"));
+    }
+    if let Some(not_on_or_after) = request.attribute("NotOnOrAfter") {
+        let not_on_or_after = parse_instant(not_on_or_after)
+            .ok_or_else(|| invalid_request("malformed NotOnOrAfter"))?;
+        if not_on_or_after + CLOCK_SKEW <= now {
+            return Err(invalid_request("logout request has expired"));
+        }
+    }
+
+    if request.child(SAML, "EncryptedID").is_some() {
+        return Err(invalid_request("encrypted NameIDs are not supported"));
+    }
+    let name_id = request
+        .child(SAML, "NameID")
+        .map(|name_id| name_id.text().trim().to_string())
+        .filter(|name_id| !name_id.is_empty())
+        .ok_or_else(|| invalid_request("logout request has no NameID"))?;
+    let session_indexes = request
+        .children(SAMLP, "SessionIndex")
+        .map(|index| index.text().trim().to_string())
+        .collect();
+
+    Ok(LogoutRequest {
+        id: id.to_string(),
+        name_id,
+        session_indexes,
+    })
+}
+
+/// Validates a `LogoutResponse` whose binding signature has been checked and
+/// returns the ID of the request it answers.
+pub fn validate_response(
+    document: &str,
+    sp: &ServiceProvider,
+    idp: &IdentityProvider,
+) -> Result<String, InvalidSamlResponse> {
+    let response = xml::parse(document).map_err(InvalidSamlResponse::new)?;
+    if !response.is(SAMLP, "LogoutResponse") || response.attribute("Version") != Some("2.0") {
+        return Err(invalid_response("not a SAML 2.0 logout response"));
+    }
+    if !addressed_to(&response, sp) {
+        return Err(invalid_response("unexpected destination"));
+    }
+    if !issued_by(&response, idp) {
+        return Err(invalid_response("unexpected issuer"));
+    }
+    let status = response
+        .child(SAMLP, "Status")
+        .and_then(|status| status.child(SAMLP, "StatusCode"))
+        .and_then(|code| code.attribute("Value"));
+    if status != Some(SUCCESS) {
+        // Our own session is gone either way, so this is only worth noting.
+        tracing::warn!(?status, "identity provider did not complete logout");
+    }
+    response
+        .attribute("InResponseTo")
+        .map(str::to_string)
+        .ok_or_else(|| invalid_response("logout response has no InResponseTo"))
+}
+
+/// Serializes the `LogoutRequest` sent on behalf of `subject`.
+fn logout_request(
+    sp: &ServiceProvider,
+    destination: &str,
+    id: &str,
+    issue_instant: i64,
+    subject: Subject,
+) -> ApiResult<String> {
+    Ok(quick_xml::se::to_string(&LogoutRequestMessage {
+        xmlns_samlp: SAMLP.to_string(),
+        xmlns_saml: SAML.to_string(),
+        id: id.to_string(),
+        version: "2.0".to_string(),
+        issue_instant: format_instant(issue_instant),
+        destination: destination.to_string(),
+        issuer: sp.entity_id.clone(),
+        name_id: NameId {
+            format: subject.name_id_format,
+            value: subject.name_id,
+        },
+        session_index: subject.session_index,
+    })?)
+}
+
+/// Serializes a successful `LogoutResponse` to `in_response_to`.
+fn logout_response(
+    sp: &ServiceProvider,
+    destination: &str,
+    id: &str,
+    issue_instant: i64,
+    in_response_to: &str,
+) -> ApiResult<String> {
+    Ok(quick_xml::se::to_string(&LogoutResponseMessage {
+        xmlns_samlp: SAMLP.to_string(),
+        xmlns_saml: SAML.to_string(),
+        id: id.to_string(),
+        version: "2.0".to_string(),
+        issue_instant: format_instant(issue_instant),
+        destination: destination.to_string(),
+        in_response_to: in_response_to.to_string(),
+        issuer: sp.entity_id.clone(),
+        status: Status {
+            status_code: StatusCode {
+                value: SUCCESS.to_string(),
+            },
+        },
+    })?)
+}
+
+/// Ends the current session and, if the identity provider supports it,
+/// continues the logout there.
+pub async fn logout(
+    Extension(pool): Extension<PgPool>,
+    Extension(idps): Extension<IdentityProviders>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let subject = match session::from_headers(&headers)? {
+        Some(id) => session::end(&pool, id).await?,
+        None => None,
+    };
+
+    let mut location = "/".to_string();
+    if let Some(subject) = subject {
+        let idp = subject.idp.as_deref().and_then(|idp| idps.get(idp));
+        if let Some((idp, slo_url)) = idp
+            .as_ref()
+            .and_then(|idp| Some((idp, idp.slo_url.as_deref()?)))
+        {
+            let sp = ServiceProvider::from_env()?;
+            let id = format!("_{}", random_token());
+            let request = logout_request(&sp, slo_url, &id, now(), subject)?;
+            remember_request(&pool, &id, idp, None).await?;
+            location =
+                binding::redirect_url(slo_url, "SAMLRequest", &request, None, &private_key()?);
+        }
+    }
+
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, CLEAR_COOKIE)]),
+        Redirect::to(&location),
+    )
+        .into_response())
+}
+
+/// SingleLogoutService endpoint for both logout requests from the identity
+/// provider and its responses to ours.
+pub async fn slo(
+    Extension(pool): Extension<PgPool>,
+    Extension(idps): Extension<IdentityProviders>,
+    RawQuery(query): RawQuery,
+) -> ApiResult<Response> {
+    let sp = ServiceProvider::from_env()?;
+    let query = query.ok_or_else(|| invalid_request("missing SAML message"))?;
+    let message = binding::receive(&query).map_err(InvalidSamlRequest::new)?;
+    let idp = issuer(&message.xml)
+        .and_then(|issuer| idps.get(&issuer))
+        .ok_or_else(|| invalid_request("unknown issuer"))?;
+    message.verify(&idp.keys).map_err(InvalidSamlRequest::new)?;
+
+    if message.parameter == "SAMLResponse" {
+        let in_response_to = validate_response(&message.xml, &sp, &idp)?;
+        consume_request(&pool, &in_response_to, &idp).await?;
+        return Ok((
+            AppendHeaders([(header::SET_COOKIE, CLEAR_COOKIE)]),
+            Redirect::to("/"),
+        )
+            .into_response());
+    }
+
+    let request = validate_request(&message.xml, &sp, &idp, now())?;
+    let ended = session::end_subject(
+        &pool,
+        &idp.entity_id,
+        &request.name_id,
+        &request.session_indexes,
+    )
+    .await?;
+    tracing::info!(name_id = request.name_id, ended, "SAML single logout");
+
+    let slo_url = idp
+        .slo_url
+        .as_deref()
+        .ok_or_else(|| invalid_request("identity provider has no SingleLogoutService"))?;
+    let id = format!("_{}", random_token());
+    let response = logout_response(&sp, slo_url, &id, now(), &request.id)?;
+    let location = binding::redirect_url(
+        slo_url,
+        "SAMLResponse",
+        &response,
+        message.relay_state.as_deref(),
+        &private_key()?,
+    );
+
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, CLEAR_COOKIE)]),
+        Redirect::to(&location),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::public_key;
+    use base64::{Engine, prelude::BASE64_STANDARD};
+
+    const IDP_CERTIFICATE: &str = include_str!("../../../tests/fixtures/saml/idp.crt");
+    const IDP: &str = "https://idp.example.edu/idp";
+
+    // 2026-10-18T12:00:00Z
+    const NOW: i64 = 1792324800;
+
+    fn idp() -> IdentityProvider {
+        let body: String = IDP_CERTIFICATE
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        IdentityProvider {
+            entity_id: IDP.to_string(),
+            name: "Example University".to_string(),
+            sso_url: "https://idp.example.edu/idp/sso".to_string(),
+            slo_url: Some("https://idp.example.edu/idp/slo".to_string()),
+            keys: vec![public_key(&BASE64_STANDARD.decode(body).unwrap()).unwrap()],
+            scopes: Vec::new(),
+        }
+    }
+
+    fn sp() -> ServiceProvider {
+        ServiceProvider::new("https://ece.gg")
+    }
+
+    fn request(issuer: &str, issue_instant: &str) -> String {
+        format!(
+            r#"<samlp:LogoutRequest xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion"
+    ID="_logout" Version="2.0" IssueInstant="{issue_instant}" Destination="https://ece.gg/auth/saml/slo">
+  <saml:Issuer>{issuer}</saml:Issuer>
+  <saml:NameID Format="urn:oasis:names:tc:SAML:2.0:nameid-format:persistent">student-42</saml:NameID>
+  <samlp:SessionIndex>_session1</samlp:SessionIndex>
+  <samlp:SessionIndex>_session2</samlp:SessionIndex>
+</samlp:LogoutRequest>"#
+        )
+    }
+
+    #[test]
+    fn valid_request() {
+        let request =
+            validate_request(&request(IDP, "2026-10-18T11:59:58Z"), &sp(), &idp(), NOW).unwrap();
+        assert_eq!(request.id, "_logout");
+        assert_eq!(request.name_id, "student-42");
+        assert_eq!(request.session_indexes, ["_session1", "_session2"]);
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let reason = |document: &str| {
+            validate_request(document, &sp(), &idp(), NOW)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            reason(&request("https://evil.example", "2026-10-18T11:59:58Z")),
+            "unexpected issuer"
+        );
+        assert_eq!(
+            reason(&request(IDP, "2026-10-18T11:50:00Z")),
+            "stale logout request"
+        );
+        assert_eq!(
+            reason(
+                &request(IDP, "2026-10-18T11:59:58Z")
+                    .replace("https://ece.gg/auth/saml/slo", "https://evil.example/slo")
+            ),
+            "unexpected destination"
+        );
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let response = logout_response(
+            &sp(),
+            "https://idp.example.edu/idp/slo",
+            "_response",
+            NOW,
+            "_logout",
+        )
+        .unwrap();
+        let parsed = xml::parse(&response).unwrap();
+        assert!(parsed.is(SAMLP, "LogoutResponse"));
+        assert_eq!(parsed.attribute("InResponseTo"), Some("_logout"));
+        assert_eq!(
+            parsed.attribute("IssueInstant"),
+            Some("2026-10-18T12:00:00Z")
+        );
+        assert_eq!(
+            parsed.child(SAML, "Issuer").unwrap().text(),
+            "https://ece.gg/auth/saml/metadata"
+        );
+
+        // An answer from the identity provider looks the same, apart from
+        // who issued it and where it is going.
+        let answer = response
+            .replace("https://ece.gg/auth/saml/metadata", IDP)
+            .replace(
+                "https://idp.example.edu/idp/slo",
+                "https://ece.gg/auth/saml/slo",
+            );
+        assert_eq!(
+            validate_response(&answer, &sp(), &idp()).unwrap(),
+            "_logout"
+        );
+        assert_eq!(
+            validate_response(&response, &sp(), &idp())
+                .unwrap_err()
+                .to_string(),
+            "unexpected destination"
+        );
+    }
+
+    #[test]
+    fn request_names_subject() {
+        let subject = Subject {
+            idp: Some(IDP.to_string()),
+            name_id: "student-42".to_string(),
+            name_id_format: Some(
+                "urn:oasis:names:tc:SAML:2.0:nameid-format:persistent".to_string(),
+            ),
+            session_index: Some("_session1".to_string()),
+        };
+        let request = logout_request(
+            &sp(),
+            "https://idp.example.edu/idp/slo",
+            "_logout",
+            NOW,
+            subject,
+        )
+        .unwrap();
+        let parsed = xml::parse(&request).unwrap();
+        assert!(parsed.is(SAMLP, "LogoutRequest"));
+        let name_id = parsed.child(SAML, "NameID").unwrap();
+        assert_eq!(name_id.text(), "student-42");
+        assert_eq!(
+            name_id.attribute("Format"),
+            Some("urn:oasis:names:tc:SAML:2.0:nameid-format:persistent")
+        );
+        assert_eq!(
+            parsed.child(SAMLP, "SessionIndex").unwrap().text(),
+            "_session1"
+        );
+    }
+}
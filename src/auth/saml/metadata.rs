@@ -0,0 +1,211 @@
+use crate::{
+    api::ApiResult,
+    auth::saml::{ServiceProvider, pem_body},
+};
+
+use axum::{http::header, response::IntoResponse};
+use serde::Serialize;
+
+const HTTP_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const HTTP_REDIRECT: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+
+#[derive(Serialize, Debug)]
+#[serde(rename = "md:EntityDescriptor")]
+struct EntityDescriptor {
+    #[serde(rename = "@xmlns:md")]
+    xmlns_md: String,
+
+    #[serde(rename = "@xmlns:saml")]
+    xmlns_saml: String,
+
+    #[serde(rename = "@xmlns:ds")]
+    xmlns_ds: String,
+
+    #[serde(rename = "@entityID")]
+    entity_id: String,
+
+    #[serde(rename = "md:SPSSODescriptor")]
+    sp_sso_descriptor: SpSsoDescriptor,
+}
+
+#[derive(Serialize, Debug)]
+struct SpSsoDescriptor {
+    #[serde(rename = "@AuthnRequestsSigned")]
+    authn_requests_signed: bool,
+
+    #[serde(rename = "@WantAssertionsSigned")]
+    want_assertions_signed: bool,
+
+    #[serde(rename = "@protocolSupportEnumeration")]
+    protocol_support_enumeration: String,
+
+    #[serde(rename = "md:KeyDescriptor")]
+    key_descriptors: Vec<KeyDescriptor>,
+
+    #[serde(rename = "md:SingleLogoutService")]
+    single_logout_service: Endpoint,
+
+    #[serde(rename = "md:NameIDFormat")]
+    name_id_format: String,
+
+    #[serde(rename = "md:AssertionConsumerService")]
+    assertion_consumer_service: IndexedEndpoint,
+}
+
+#[derive(Serialize, Debug)]
+struct KeyDescriptor {
+    #[serde(rename = "@use")]
+    key_use: String,
+
+    #[serde(rename = "ds:KeyInfo")]
+    key_info: KeyInfo,
+}
+
+#[derive(Serialize, Debug)]
+struct KeyInfo {
+    #[serde(rename = "ds:X509Data")]
+    x509_data: X509Data,
+}
+
+#[derive(Serialize, Debug)]
+struct X509Data {
+    #[serde(rename = "ds:X509Certificate")]
+    x509_certificate: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Endpoint {
+    #[serde(rename = "@Binding")]
+    binding: String,
+
+    #[serde(rename = "@Location")]
+    location: String,
+}
+
+#[derive(Serialize, Debug)]
+struct IndexedEndpoint {
+    #[serde(rename = "@Binding")]
+    binding: String,
+
+    #[serde(rename = "@Location")]
+    location: String,
+
+    #[serde(rename = "@index")]
+    index: u32,
+
+    #[serde(rename = "@isDefault")]
+    is_default: bool,
+}
+
+impl KeyDescriptor {
+    fn new(key_use: &str, certificate: &str) -> Self {
+        KeyDescriptor {
+            key_use: key_use.to_string(),
+            key_info: KeyInfo {
+                x509_data: X509Data {
+                    x509_certificate: certificate.to_string(),
+                },
+            },
+        }
+    }
+}
+
+/// Renders the metadata of `sp` with the base64 encoded `certificate`.
+fn render(sp: ServiceProvider, certificate: &str) -> ApiResult<String> {
+    let entity_descriptor = EntityDescriptor {
+        xmlns_md: "urn:oasis:names:tc:SAML:2.0:metadata".to_string(),
+        xmlns_saml: "urn:oasis:names:tc:SAML:2.0:assertion".to_string(),
+        xmlns_ds: "http://www.w3.org/2000/09/xmldsig#".to_string(),
+        entity_id: sp.entity_id,
+        sp_sso_descriptor: SpSsoDescriptor {
+            authn_requests_signed: true,
+            want_assertions_signed: true,
+            protocol_support_enumeration: "urn:oasis:names:tc:SAML:2.0:protocol".to_string(),
+            key_descriptors: vec![
+                KeyDescriptor::new("signing", certificate),
+                KeyDescriptor::new("encryption", certificate),
+            ],
+            single_logout_service: Endpoint {
+                binding: HTTP_REDIRECT.to_string(),
+                location: sp.slo_url,
+            },
+            name_id_format: "urn:oasis:names:tc:SAML:2.0:nameid-format:persistent".to_string(),
+            assertion_consumer_service: IndexedEndpoint {
+                binding: HTTP_POST.to_string(),
+                location: sp.acs_url,
+                index: 0,
+                is_default: true,
+            },
+        },
+    };
+    let xml = quick_xml::se::to_string(&entity_descriptor)?;
+    Ok(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{xml}"))
+}
+
+pub async fn handler() -> ApiResult<impl IntoResponse> {
+    let certificate = pem_body("SAML_SP_CERTIFICATE")?;
+    let sp = ServiceProvider::from_env()?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        render(sp, &certificate)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::saml::{
+        public_key,
+        xml::{self, DS},
+    };
+    use base64::{Engine, prelude::BASE64_STANDARD};
+
+    const MD: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+    const SP_CERTIFICATE: &str = include_str!("../../../tests/fixtures/saml/sp.crt");
+
+    #[test]
+    fn describes_service_provider() {
+        let certificate: String = SP_CERTIFICATE
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let sp = ServiceProvider::new("https://ceresforge.example");
+        let root = xml::parse(&render(sp, &certificate).unwrap()).unwrap();
+
+        assert!(root.is(MD, "EntityDescriptor"));
+        assert_eq!(
+            root.attribute("entityID"),
+            Some("https://ceresforge.example/auth/saml/metadata")
+        );
+        let descriptor = root.child(MD, "SPSSODescriptor").unwrap();
+        assert_eq!(descriptor.attribute("AuthnRequestsSigned"), Some("true"));
+        assert_eq!(descriptor.attribute("WantAssertionsSigned"), Some("true"));
+
+        let acs = descriptor.child(MD, "AssertionConsumerService").unwrap();
+        assert_eq!(acs.attribute("Binding"), Some(HTTP_POST));
+        assert_eq!(
+            acs.attribute("Location"),
+            Some("https://ceresforge.example/auth/saml/acs")
+        );
+        let slo = descriptor.child(MD, "SingleLogoutService").unwrap();
+        assert_eq!(slo.attribute("Binding"), Some(HTTP_REDIRECT));
+        assert_eq!(
+            slo.attribute("Location"),
+            Some("https://ceresforge.example/auth/saml/slo")
+        );
+
+        let signing = descriptor
+            .children(MD, "KeyDescriptor")
+            .find(|key| key.attribute("use") == Some("signing"))
+            .unwrap();
+        let embedded = signing
+            .child(DS, "KeyInfo")
+            .and_then(|info| info.child(DS, "X509Data"))
+            .and_then(|data| data.child(DS, "X509Certificate"))
+            .unwrap()
+            .text();
+        assert_eq!(embedded, certificate);
+        let der = BASE64_STANDARD.decode(&embedded).unwrap();
+        public_key(&der).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+mod acs;
+pub mod attributes;
+mod binding;
+mod discovery;
+mod idp;
+mod login;
+mod logout;
+mod metadata;
+mod signature;
+mod time;
+mod xml;
+
+pub use idp::{IdentityProviders, schedule};
+pub use login::local_path;
+
+use crate::api::ApiResult;
+
+use axum::{
+    Router,
+    routing::{get, post},
+};
+use rsa::{
+    RsaPrivateKey, RsaPublicKey,
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+};
+use x509_cert::{
+    Certificate,
+    der::{Decode, Encode},
+};
+
+/// Our own endpoints, as registered with the identity providers.
+#[derive(Debug)]
+pub struct ServiceProvider {
+    pub entity_id: String,
+    pub acs_url: String,
+    pub slo_url: String,
+}
+
+impl ServiceProvider {
+    pub fn new(base_url: &str) -> Self {
+        ServiceProvider {
+            entity_id: format!("{base_url}/auth/saml/metadata"),
+            acs_url: format!("{base_url}/auth/saml/acs"),
+            slo_url: format!("{base_url}/auth/saml/slo"),
+        }
+    }
+
+    /// Reads the public URL of this deployment from `BASE_URL` and our entity
+    /// ID from `SAML_ENTITY_ID`, which defaults to the metadata URL.
+    pub fn from_env() -> ApiResult<Self> {
+        let base_url = std::env::var("BASE_URL")?;
+        let mut sp = ServiceProvider::new(base_url.trim_end_matches('/'));
+        if let Ok(entity_id) = std::env::var("SAML_ENTITY_ID") {
+            sp.entity_id = entity_id;
+        }
+        Ok(sp)
+    }
+}
+
+/// Extracts the RSA public key from a DER encoded X.509 certificate.
+pub fn public_key(certificate: &[u8]) -> ApiResult<RsaPublicKey> {
+    let certificate = Certificate::from_der(certificate)?;
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()?;
+    Ok(RsaPublicKey::from_public_key_der(&spki)?)
+}
+
+/// Reads our PKCS#8 signing key from the PEM file at `SAML_SP_PRIVATE_KEY`.
+fn private_key() -> ApiResult<RsaPrivateKey> {
+    let pem = std::fs::read_to_string(std::env::var("SAML_SP_PRIVATE_KEY")?)?;
+    Ok(RsaPrivateKey::from_pkcs8_pem(&pem)?)
+}
+
+/// Reads the PEM file named by the environment variable `var` and returns its
+/// base64 encoded body.
+fn pem_body(var: &str) -> ApiResult<String> {
+    let pem = std::fs::read_to_string(std::env::var(var)?)?;
+    Ok(pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect())
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/acs", post(acs::handler))
+        .route("/discovery", get(discovery::handler))
+        .route("/login", get(login::handler))
+        .route("/logout", post(logout::logout))
+        .route("/metadata", get(metadata::handler))
+        .route("/slo", get(logout::slo))
+}
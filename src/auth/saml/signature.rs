@@ -0,0 +1,121 @@
+//! Enveloped XML signatures.
+//!
+//! Only the profile SAML deployments use in practice is accepted: one
+//! reference to the signed element's own `ID`, the enveloped-signature and
+//! Exclusive Canonicalization transforms, SHA-256 digests and RSA-SHA256
+//! signatures. The signature must be a direct child of the element it signs,
+//! which rules out signature wrapping.
+
+use crate::auth::saml::xml::{DS, Element};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use rsa::{
+    RsaPrivateKey, RsaPublicKey,
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    signature::{SignatureEncoding, Signer, Verifier},
+};
+use sha2::{Digest, Sha256};
+
+pub const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+pub const ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+pub const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+pub const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    BASE64_STANDARD.decode(value).map_err(|err| err.to_string())
+}
+
+fn algorithm(element: &Element) -> Option<&str> {
+    element.attribute("Algorithm")
+}
+
+/// Returns the InclusiveNamespaces PrefixList of a canonicalization element.
+fn inclusive_prefixes(element: &Element) -> Vec<String> {
+    element
+        .child(EXC_C14N, "InclusiveNamespaces")
+        .and_then(|e| e.attribute("PrefixList"))
+        .map(|list| list.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Verifies the enveloped signature of `element` against any of `keys`.
+pub fn verify(element: &Element, keys: &[RsaPublicKey]) -> Result<(), String> {
+    let signature = element.child(DS, "Signature").ok_or("missing signature")?;
+    let signed_info = signature
+        .child(DS, "SignedInfo")
+        .ok_or("missing SignedInfo")?;
+
+    let c14n = signed_info
+        .child(DS, "CanonicalizationMethod")
+        .ok_or("missing CanonicalizationMethod")?;
+    if algorithm(c14n) != Some(EXC_C14N) {
+        return Err("unsupported canonicalization method".to_string());
+    }
+    let method = signed_info
+        .child(DS, "SignatureMethod")
+        .ok_or("missing SignatureMethod")?;
+    if algorithm(method) != Some(RSA_SHA256) {
+        return Err("unsupported signature method".to_string());
+    }
+
+    let mut references = signed_info.children(DS, "Reference");
+    let reference = references.next().ok_or("missing Reference")?;
+    if references.next().is_some() {
+        return Err("multiple references".to_string());
+    }
+    let id = element.attribute("ID").ok_or("signed element has no ID")?;
+    if reference.attribute("URI") != Some(&format!("#{id}")) {
+        return Err("reference does not point at the signed element".to_string());
+    }
+
+    let mut inclusive = Vec::new();
+    if let Some(transforms) = reference.child(DS, "Transforms") {
+        for transform in transforms.children(DS, "Transform") {
+            match algorithm(transform) {
+                Some(ENVELOPED) => (),
+                Some(EXC_C14N) => inclusive = inclusive_prefixes(transform),
+                _ => return Err("unsupported transform".to_string()),
+            }
+        }
+    }
+    let digest_method = reference
+        .child(DS, "DigestMethod")
+        .ok_or("missing DigestMethod")?;
+    if algorithm(digest_method) != Some(SHA256) {
+        return Err("unsupported digest method".to_string());
+    }
+    let expected = decode(
+        &reference
+            .child(DS, "DigestValue")
+            .ok_or("missing DigestValue")?
+            .text(),
+    )?;
+    let digest = Sha256::digest(element.canonicalize(Some(signature), &inclusive).as_bytes());
+    if digest.as_slice() != expected {
+        return Err("digest mismatch".to_string());
+    }
+
+    let value = decode(
+        &signature
+            .child(DS, "SignatureValue")
+            .ok_or("missing SignatureValue")?
+            .text(),
+    )?;
+    let value = Signature::try_from(value.as_slice()).map_err(|err| err.to_string())?;
+    let signed = signed_info.canonicalize(None, &inclusive_prefixes(c14n));
+    keys.iter()
+        .find(|key| {
+            VerifyingKey::<Sha256>::new((*key).clone())
+                .verify(signed.as_bytes(), &value)
+                .is_ok()
+        })
+        .map(|_| ())
+        .ok_or_else(|| "signature mismatch".to_string())
+}
+
+/// Signs `bytes` with RSA-SHA256 and returns the base64 encoded signature.
+pub fn sign(key: &RsaPrivateKey, bytes: &[u8]) -> String {
+    let signature = SigningKey::<Sha256>::new(key.clone()).sign(bytes);
+    BASE64_STANDARD.encode(signature.to_bytes())
+}
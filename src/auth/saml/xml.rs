@@ -0,0 +1,349 @@
+//! A small namespace aware XML tree for SAML messages.
+//!
+//! quick-xml only offers a streaming reader, but verifying an XML signature
+//! needs to canonicalize arbitrary subtrees. Documents are read into an
+//! [`Element`] tree in which every element carries its in-scope namespace
+//! bindings, which is all Exclusive XML Canonicalization needs.
+
+use quick_xml::{
+    Reader,
+    events::{BytesStart, Event},
+};
+
+pub const SAMLP: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+pub const SAML: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+pub const DS: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+type Bindings = Vec<(Option<String>, String)>;
+
+#[derive(Debug)]
+pub struct Attribute {
+    prefix: Option<String>,
+    local: String,
+    namespace: Option<String>,
+    value: String,
+}
+
+#[derive(Debug)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Debug)]
+pub struct Element {
+    prefix: Option<String>,
+    local: String,
+    namespace: Option<String>,
+    attributes: Vec<Attribute>,
+    scope: Bindings,
+    children: Vec<Node>,
+}
+
+fn lookup<'a>(bindings: &'a Bindings, prefix: Option<&str>) -> Option<&'a str> {
+    bindings
+        .iter()
+        .rev()
+        .find(|(p, _)| p.as_deref() == prefix)
+        .map(|(_, uri)| uri.as_str())
+        .filter(|uri| !uri.is_empty())
+}
+
+fn split_name(name: &str) -> (Option<String>, String) {
+    match name.split_once(':') {
+        Some((prefix, local)) => (Some(prefix.to_string()), local.to_string()),
+        None => (None, name.to_string()),
+    }
+}
+
+/// Normalizes literal whitespace in an attribute value as an XML processor
+/// must before resolving character references.
+fn attribute_value(raw: &str) -> Result<String, String> {
+    let normalized = raw.replace("\r\n", " ").replace(['\t', '\n', '\r'], " ");
+    quick_xml::escape::unescape(&normalized)
+        .map(|value| value.into_owned())
+        .map_err(|err| err.to_string())
+}
+
+fn text_value(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn start_element(start: &BytesStart, parent: &Bindings) -> Result<Element, String> {
+    let mut scope = parent.clone();
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|err| err.to_string())?;
+        let key = std::str::from_utf8(attribute.key.as_ref()).map_err(|err| err.to_string())?;
+        let raw = std::str::from_utf8(&attribute.value).map_err(|err| err.to_string())?;
+        let value = attribute_value(raw)?;
+        if key == "xmlns" {
+            scope.push((None, value));
+        } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+            scope.push((Some(prefix.to_string()), value));
+        } else {
+            let (prefix, local) = split_name(key);
+            attributes.push((prefix, local, value));
+        }
+    }
+
+    let name = std::str::from_utf8(start.name().as_ref())
+        .map_err(|err| err.to_string())?
+        .to_string();
+    let (prefix, local) = split_name(&name);
+    let namespace = lookup(&scope, prefix.as_deref()).map(str::to_string);
+    if prefix.is_some() && namespace.is_none() {
+        return Err(format!("unbound prefix on {name}"));
+    }
+    let attributes = attributes
+        .into_iter()
+        .map(|(prefix, local, value)| {
+            let namespace = match &prefix {
+                Some(prefix) if prefix == "xml" => {
+                    Some("http://www.w3.org/XML/1998/namespace".to_string())
+                }
+                Some(prefix) => Some(
+                    lookup(&scope, Some(prefix))
+                        .ok_or_else(|| format!("unbound prefix {prefix}"))?
+                        .to_string(),
+                ),
+                None => None,
+            };
+            Ok(Attribute {
+                prefix,
+                local,
+                namespace,
+                value,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(Element {
+        prefix,
+        local,
+        namespace,
+        attributes,
+        scope,
+        children: Vec::new(),
+    })
+}
+
+/// Parses a document and returns its root element. Documents with a DTD are
+/// rejected outright.
+pub fn parse(xml: &str) -> Result<Element, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+
+    loop {
+        let event = reader.read_event().map_err(|err| err.to_string())?;
+        let element = match event {
+            Event::Start(start) => {
+                let parent = stack.last().map(|e| e.scope.clone()).unwrap_or_default();
+                stack.push(start_element(&start, &parent)?);
+                continue;
+            }
+            Event::Empty(start) => {
+                let parent = stack.last().map(|e| e.scope.clone()).unwrap_or_default();
+                start_element(&start, &parent)?
+            }
+            Event::End(_) => stack.pop().ok_or("unbalanced end tag")?,
+            Event::Text(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    let text = text.unescape().map_err(|err| err.to_string())?;
+                    parent.children.push(Node::Text(text_value(&text)));
+                }
+                continue;
+            }
+            Event::CData(data) => {
+                if let Some(parent) = stack.last_mut() {
+                    let text = std::str::from_utf8(&data).map_err(|err| err.to_string())?;
+                    parent.children.push(Node::Text(text_value(text)));
+                }
+                continue;
+            }
+            Event::DocType(_) => return Err("DTDs are not allowed".to_string()),
+            Event::Eof => break,
+            Event::Decl(_) | Event::PI(_) | Event::Comment(_) => continue,
+        };
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(Node::Element(element)),
+            None if root.is_none() => root = Some(element),
+            None => return Err("multiple root elements".to_string()),
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err("unclosed element".to_string());
+    }
+    root.ok_or_else(|| "empty document".to_string())
+}
+
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+impl Element {
+    pub fn is(&self, namespace: &str, local: &str) -> bool {
+        self.namespace.as_deref() == Some(namespace) && self.local == local
+    }
+
+    /// Returns the value of an unqualified attribute.
+    pub fn attribute(&self, local: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.namespace.is_none() && a.local == local)
+            .map(|a| a.value.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn children<'a>(
+        &'a self,
+        namespace: &'a str,
+        local: &'a str,
+    ) -> impl Iterator<Item = &'a Element> {
+        self.elements().filter(move |e| e.is(namespace, local))
+    }
+
+    pub fn child(&self, namespace: &str, local: &str) -> Option<&Element> {
+        self.elements().find(|e| e.is(namespace, local))
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            match node {
+                Node::Text(t) => text.push_str(t),
+                Node::Element(e) => text.push_str(&e.text()),
+            }
+        }
+        text
+    }
+
+    /// Counts the elements in this subtree whose `ID` attribute is `id`.
+    pub fn count_id(&self, id: &str) -> usize {
+        let own = usize::from(self.attribute("ID") == Some(id));
+        own + self.elements().map(|e| e.count_id(id)).sum::<usize>()
+    }
+
+    /// Renders this subtree in Exclusive XML Canonicalization (without
+    /// comments), leaving out `exclude` and treating the prefixes in
+    /// `inclusive` as in the InclusiveNamespaces PrefixList.
+    pub fn canonicalize(&self, exclude: Option<&Element>, inclusive: &[String]) -> String {
+        let mut out = String::new();
+        self.write_canonical(&mut out, &Vec::new(), exclude, inclusive);
+        out
+    }
+
+    fn qname(&self) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{prefix}:{}", self.local),
+            None => self.local.clone(),
+        }
+    }
+
+    fn write_canonical(
+        &self,
+        out: &mut String,
+        rendered: &Bindings,
+        exclude: Option<&Element>,
+        inclusive: &[String],
+    ) {
+        let mut prefixes: Vec<Option<String>> = vec![self.prefix.clone()];
+        for attribute in &self.attributes {
+            if attribute.prefix.as_deref().is_some_and(|p| p != "xml") {
+                prefixes.push(attribute.prefix.clone());
+            }
+        }
+        for prefix in inclusive {
+            let prefix = (prefix != "#default").then(|| prefix.clone());
+            if lookup(&self.scope, prefix.as_deref()).is_some() {
+                prefixes.push(prefix);
+            }
+        }
+        prefixes.sort();
+        prefixes.dedup();
+
+        let mut declarations = Vec::new();
+        for prefix in prefixes {
+            let uri = lookup(&self.scope, prefix.as_deref()).unwrap_or("");
+            if lookup(rendered, prefix.as_deref()).unwrap_or("") != uri {
+                declarations.push((prefix, uri.to_string()));
+            }
+        }
+
+        let mut attributes: Vec<&Attribute> = self.attributes.iter().collect();
+        attributes.sort_by(|a, b| {
+            (a.namespace.as_deref().unwrap_or(""), &a.local)
+                .cmp(&(b.namespace.as_deref().unwrap_or(""), &b.local))
+        });
+
+        let qname = self.qname();
+        out.push('<');
+        out.push_str(&qname);
+        for (prefix, uri) in &declarations {
+            match prefix {
+                Some(prefix) => out.push_str(&format!(" xmlns:{prefix}=\"")),
+                None => out.push_str(" xmlns=\""),
+            }
+            escape_attribute(uri, out);
+            out.push('"');
+        }
+        for attribute in attributes {
+            out.push(' ');
+            if let Some(prefix) = &attribute.prefix {
+                out.push_str(prefix);
+                out.push(':');
+            }
+            out.push_str(&attribute.local);
+            out.push_str("=\"");
+            escape_attribute(&attribute.value, out);
+            out.push('"');
+        }
+        out.push('>');
+
+        let mut rendered = rendered.clone();
+        rendered.extend(declarations);
+        for node in &self.children {
+            match node {
+                Node::Text(text) => escape_text(text, out),
+                Node::Element(element) if exclude.is_some_and(|e| std::ptr::eq(e, element)) => {}
+                Node::Element(element) => {
+                    element.write_canonical(out, &rendered, exclude, inclusive)
+                }
+            }
+        }
+
+        out.push_str("</");
+        out.push_str(&qname);
+        out.push('>');
+    }
+}
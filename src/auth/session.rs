@@ -0,0 +1,448 @@
+//! Database-backed sessions.
+//!
+//! The session cookie carries the session ID followed by an HMAC-SHA256 of
+//! it under `SESSION_SECRET`, so IDs that were never handed out are rejected
+//! without a database round trip. Sessions end after [`IDLE_TIMEOUT`]
+//! without requests or [`ABSOLUTE_TIMEOUT`] after login, whichever comes
+//! first.
+//!
+//! While an admin or instructor impersonates someone, their session stands
+//! for that user; see [`crate::auth::impersonation`].
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{Impersonating, InternalError, SecondFactorRequired, Unauthenticated},
+    },
+    auth::{
+        impersonation::{self, Impersonator},
+        token::Grant,
+    },
+    users::User,
+};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, header, request::Parts},
+};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+
+pub const COOKIE: &str = "ceresforge_session";
+
+/// `Set-Cookie` value that removes the session cookie.
+pub const CLEAR_COOKIE: &str =
+    "ceresforge_session=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax";
+
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+pub const ABSOLUTE_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How often `last_seen_at` is written back, to spare the database a write
+/// on every request.
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The SAML subject a session was started for.
+#[derive(Debug)]
+pub struct Subject {
+    pub idp: Option<String>,
+    pub name_id: String,
+    pub name_id_format: Option<String>,
+    pub session_index: Option<String>,
+}
+
+/// How a user logged in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "auth_method", rename_all = "lowercase")]
+pub enum AuthMethod {
+    Password,
+    Passkey,
+    Saml,
+    Oidc,
+}
+
+/// How and from where a session was started, for users to tell their
+/// sessions apart.
+#[derive(Clone, Copy, Debug)]
+pub struct Origin<'a> {
+    pub method: AuthMethod,
+    pub addr: &'a str,
+}
+
+/// An ended session, which may not have been a SAML one.
+#[derive(Debug, FromRow)]
+struct Ended {
+    idp: Option<String>,
+    name_id: Option<String>,
+    name_id_format: Option<String>,
+    session_index: Option<String>,
+}
+
+/// Returns 32 random bytes, hex encoded.
+pub fn random_token() -> String {
+    rand::random::<[u8; 32]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn secret() -> ApiResult<String> {
+    Ok(std::env::var("SESSION_SECRET")?)
+}
+
+/// Returns the cookie value for session `id`.
+fn sign(secret: &str, id: &str) -> ApiResult<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(id.as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(format!("{id}.{signature}"))
+}
+
+/// Returns the session ID in a cookie value if its signature is valid.
+fn verify<'a>(secret: &str, value: &'a str) -> ApiResult<Option<&'a str>> {
+    let Some((id, signature)) = value.split_once('.') else {
+        return Ok(None);
+    };
+    let Some(signature) = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+    else {
+        return Ok(None);
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(id.as_bytes());
+    Ok(mac.verify_slice(&signature).is_ok().then_some(id))
+}
+
+/// Returns the value of the cookie `name` the request carries.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
+
+/// Returns the ID of the session whose validly signed cookie the request
+/// carries.
+pub fn from_headers(headers: &HeaderMap) -> ApiResult<Option<&str>> {
+    match cookie(headers, COOKIE) {
+        Some(value) => verify(&secret()?, value),
+        None => Ok(None),
+    }
+}
+
+/// Starts a session for `user_id` and returns its `Set-Cookie` value. Any
+/// session the request already carries is ended, so a session ID planted
+/// before login is useless afterwards.
+pub async fn create(
+    pool: &PgPool,
+    headers: &HeaderMap,
+    user_id: i64,
+    origin: Origin<'_>,
+    subject: Option<&Subject>,
+) -> ApiResult<String> {
+    start(pool, headers, user_id, origin, subject, false).await
+}
+
+/// Like [`create`], but the session is only good for setting up two-factor
+/// authentication until [`lift_restriction`] is called.
+pub async fn create_restricted(
+    pool: &PgPool,
+    headers: &HeaderMap,
+    user_id: i64,
+    origin: Origin<'_>,
+) -> ApiResult<String> {
+    start(pool, headers, user_id, origin, None, true).await
+}
+
+async fn start(
+    pool: &PgPool,
+    headers: &HeaderMap,
+    user_id: i64,
+    origin: Origin<'_>,
+    subject: Option<&Subject>,
+    restricted: bool,
+) -> ApiResult<String> {
+    if let Some(previous) = from_headers(headers)? {
+        end(pool, previous).await?;
+    }
+    let active: bool = sqlx::query_scalar("SELECT active FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    if !active {
+        return Err(Unauthenticated::new("account is deactivated".to_string()).into());
+    }
+    sqlx::query(
+        "DELETE FROM sessions
+         WHERE last_seen_at < now() - $1 OR created_at < now() - $2",
+    )
+    .bind(IDLE_TIMEOUT)
+    .bind(ABSOLUTE_TIMEOUT)
+    .execute(pool)
+    .await?;
+
+    let id = random_token();
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    sqlx::query(
+        "INSERT INTO sessions
+             (id, user_id, idp, name_id, name_id_format, session_index, restricted,
+              auth_method, addr, user_agent)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+    )
+    .bind(&id)
+    .bind(user_id)
+    .bind(subject.and_then(|s| s.idp.as_deref()))
+    .bind(subject.map(|s| s.name_id.as_str()))
+    .bind(subject.and_then(|s| s.name_id_format.as_deref()))
+    .bind(subject.and_then(|s| s.session_index.as_deref()))
+    .bind(restricted)
+    .bind(origin.method)
+    .bind(origin.addr)
+    .bind(user_agent)
+    .execute(pool)
+    .await?;
+    Ok(format!(
+        "{COOKIE}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        sign(&secret()?, &id)?,
+        ABSOLUTE_TIMEOUT.as_secs()
+    ))
+}
+
+/// Ends a session and returns the SAML subject it belonged to, if any.
+pub async fn end(pool: &PgPool, id: &str) -> ApiResult<Option<Subject>> {
+    Ok(sqlx::query_as(
+        "DELETE FROM sessions WHERE id = $1
+         RETURNING idp, name_id, name_id_format, session_index",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .and_then(|ended: Ended| {
+        Some(Subject {
+            idp: ended.idp,
+            name_id: ended.name_id?,
+            name_id_format: ended.name_id_format,
+            session_index: ended.session_index,
+        })
+    }))
+}
+
+/// Ends every session of a SAML subject at `idp`, or only those with one of
+/// `session_indexes` if any are given. Returns how many were ended.
+pub async fn end_subject(
+    pool: &PgPool,
+    idp: &str,
+    name_id: &str,
+    session_indexes: &[String],
+) -> ApiResult<u64> {
+    let ended = sqlx::query(
+        "DELETE FROM sessions
+         WHERE idp = $1 AND name_id = $2
+           AND (cardinality($3::TEXT[]) = 0 OR session_index = ANY($3))",
+    )
+    .bind(idp)
+    .bind(name_id)
+    .bind(session_indexes)
+    .execute(pool)
+    .await?;
+    Ok(ended.rows_affected())
+}
+
+/// Ends every session of a user except `keep`. Returns how many were ended.
+pub async fn end_others(pool: &PgPool, user_id: i64, keep: Option<&str>) -> ApiResult<u64> {
+    let ended = sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND id IS DISTINCT FROM $2")
+        .bind(user_id)
+        .bind(keep)
+        .execute(pool)
+        .await?;
+    Ok(ended.rows_affected())
+}
+
+/// Lets a restricted session be used for everything.
+pub async fn lift_restriction(pool: &PgPool, id: &str) -> ApiResult<()> {
+    sqlx::query("UPDATE sessions SET restricted = false WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, FromRow)]
+struct Live {
+    #[sqlx(flatten)]
+    user: User,
+    restricted: bool,
+    impersonator_id: Option<i64>,
+    impersonator_username: Option<String>,
+}
+
+impl Live {
+    fn impersonator(&self) -> Option<Impersonator> {
+        Some(Impersonator {
+            id: self.impersonator_id?,
+            username: self.impersonator_username.clone()?,
+        })
+    }
+}
+
+/// Returns the user of a live session, which is the impersonated one while
+/// impersonating, and whether the session is restricted, and marks the
+/// session as used.
+async fn authenticate(pool: &PgPool, id: &str) -> ApiResult<Option<Live>> {
+    let live = sqlx::query_as(
+        "SELECT users.id, users.username, users.email, users.display_name, users.role,
+                sessions.restricted,
+                impersonators.id AS impersonator_id,
+                impersonators.username AS impersonator_username
+         FROM sessions
+         JOIN users ON users.id = coalesce(sessions.impersonating_id, sessions.user_id)
+         LEFT JOIN users impersonators
+             ON impersonators.id = sessions.user_id AND sessions.impersonating_id IS NOT NULL
+         WHERE sessions.id = $1 AND users.active
+           AND sessions.last_seen_at > now() - $2
+           AND sessions.created_at > now() - $3",
+    )
+    .bind(id)
+    .bind(IDLE_TIMEOUT)
+    .bind(ABSOLUTE_TIMEOUT)
+    .fetch_optional(pool)
+    .await?;
+    if live.is_some() {
+        sqlx::query(
+            "UPDATE sessions SET last_seen_at = now()
+             WHERE id = $1 AND last_seen_at < now() - $2",
+        )
+        .bind(id)
+        .bind(TOUCH_INTERVAL)
+        .execute(pool)
+        .await?;
+    }
+    Ok(live)
+}
+
+/// Returns the session of a request, whether restricted or not. Requests
+/// of an impersonated session are audited and get its [`Impersonator`].
+async fn live(parts: &mut Parts) -> ApiResult<Live> {
+    let pool =
+        parts.extensions.get::<PgPool>().cloned().ok_or_else(|| {
+            ApiError::InternalError(InternalError::new("no database pool".into()))
+        })?;
+    let id = from_headers(&parts.headers)?
+        .ok_or_else(|| Unauthenticated::new("not logged in".to_string()))?;
+    let live = authenticate(&pool, id)
+        .await?
+        .ok_or_else(|| Unauthenticated::new("session has expired".to_string()))?;
+    if let Some(impersonator) = live.impersonator() {
+        if parts.extensions.get::<Impersonator>().is_none() {
+            impersonation::audit(&pool, parts, &impersonator, &live.user).await?;
+            parts.extensions.insert(impersonator);
+        }
+    }
+    Ok(live)
+}
+
+/// Extracts the user of the request's session, or of the personal access
+/// token it was made with, rejecting the request with [`Unauthenticated`] if
+/// there is neither. Restricted sessions are rejected with
+/// [`SecondFactorRequired`].
+#[derive(Debug)]
+pub struct CurrentUser(pub User);
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(grant) = parts.extensions.get::<Grant>() {
+            return Ok(CurrentUser(grant.user.clone()));
+        }
+        let live = live(parts).await?;
+        if live.restricted {
+            return Err(SecondFactorRequired::new(
+                "two-factor authentication has to be set up first".to_string(),
+            )
+            .into());
+        }
+        Ok(CurrentUser(live.user))
+    }
+}
+
+/// Rejects requests of an impersonated session with [`Impersonating`].
+fn forbid_impersonation(parts: &Parts) -> ApiResult<()> {
+    match parts.extensions.get::<Impersonator>() {
+        Some(impersonator) => Err(Impersonating::new(impersonator.username.clone()).into()),
+        None => Ok(()),
+    }
+}
+
+/// Extracts the current user like [`CurrentUser`], but only if they act as
+/// themselves, rejecting impersonated sessions with [`Impersonating`]. For
+/// sensitive actions like changing credentials or linking identities.
+#[derive(Debug)]
+pub struct OwnUser(pub User);
+
+impl<S> FromRequestParts<S> for OwnUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        forbid_impersonation(parts)?;
+        Ok(OwnUser(user))
+    }
+}
+
+/// Extracts the user of the request's session like [`OwnUser`], but
+/// accepts restricted sessions, for the endpoints setting up two-factor
+/// authentication.
+#[derive(Debug)]
+pub struct EnrollingUser(pub User);
+
+impl<S> FromRequestParts<S> for EnrollingUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let live = live(parts).await?;
+        forbid_impersonation(parts)?;
+        Ok(EnrollingUser(live.user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_cookie() {
+        let value = sign("secret", "abc").unwrap();
+        assert_eq!(verify("secret", &value).unwrap(), Some("abc"));
+        assert_eq!(verify("other secret", &value).unwrap(), None);
+        assert_eq!(verify("secret", "abc").unwrap(), None);
+        let forged = value.replacen("abc", "abd", 1);
+        assert_eq!(verify("secret", &forged).unwrap(), None);
+        assert_eq!(verify("secret", "abc.zz").unwrap(), None);
+    }
+}
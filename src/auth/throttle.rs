@@ -0,0 +1,343 @@
+//! Throttling of login attempts.
+//!
+//! Failed logins are counted per username and per source address. Past a
+//! few free failures, each one makes the next attempt wait twice as long,
+//! up to [`MAX_DELAY`]; past more, the account or address is locked out for
+//! [`LOCKOUT`]. Failures are forgotten after [`WINDOW`] without any, and a
+//! successful login clears those of its account. Every login flow goes
+//! through an [`Attempt`], so a locked account cannot log in by SAML, OpenID
+//! or passkey either. Admins can lift lockouts early.
+//!
+//! The source address is the peer's, or with `TRUST_FORWARDED_FOR=true`
+//! the last one in `X-Forwarded-For`, as appended by a reverse proxy.
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{LoginThrottled, ResourceNotFound},
+    },
+    audit,
+    auth::{
+        email,
+        rbac::{Authorized, require},
+    },
+    users::User,
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{ConnectInfo, FromRequestParts, OriginalUri, Path},
+    http::{StatusCode, request::Parts},
+    routing::{delete, get},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::{net::SocketAddr, time::Duration};
+
+pub const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+pub const WINDOW: Duration = Duration::from_secs(60 * 60);
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "throttle_kind", rename_all = "lowercase")]
+pub enum ThrottleKind {
+    Account,
+    Ip,
+}
+
+impl ThrottleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleKind::Account => "account",
+            ThrottleKind::Ip => "ip",
+        }
+    }
+
+    /// Failures allowed without delay.
+    fn free(&self) -> u32 {
+        match self {
+            ThrottleKind::Account => 3,
+            // Many people may share an address, like a campus NAT.
+            ThrottleKind::Ip => 20,
+        }
+    }
+
+    /// Failures after which the key is locked out.
+    fn lockout_after(&self) -> u32 {
+        match self {
+            ThrottleKind::Account => 10,
+            ThrottleKind::Ip => 100,
+        }
+    }
+}
+
+/// Returns the delay before the attempt after `failures` failures.
+fn delay(kind: ThrottleKind, failures: u32) -> Duration {
+    match failures.checked_sub(kind.free()) {
+        Some(excess) => Duration::from_secs(1 << excess.min(16)).min(MAX_DELAY),
+        None => Duration::ZERO,
+    }
+}
+
+/// Returns how many seconds to wait before the next attempt, given the
+/// failures so far, seconds since the last of them and seconds left of a
+/// lockout.
+fn retry_after(kind: ThrottleKind, failures: u32, since_last: u64, locked_for: u64) -> u64 {
+    let delay = if since_last < WINDOW.as_secs() {
+        delay(kind, failures).as_secs().saturating_sub(since_last)
+    } else {
+        0
+    };
+    delay.max(locked_for)
+}
+
+/// The address a request comes from.
+#[derive(Clone, Debug)]
+pub struct ClientAddr(pub String);
+
+impl<S> FromRequestParts<S> for ClientAddr
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let trust_forwarded_for =
+            std::env::var("TRUST_FORWARDED_FOR").is_ok_and(|trust| trust == "true");
+        let forwarded_for = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .next_back()
+            .map(|addr| addr.trim().to_string());
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        let addr = match (trust_forwarded_for, forwarded_for) {
+            (true, Some(addr)) => addr,
+            _ => peer.unwrap_or_else(|| "unknown".to_string()),
+        };
+        Ok(ClientAddr(addr))
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct Throttle {
+    failures: i32,
+    since_last: i64,
+    locked_for: i64,
+}
+
+async fn check(pool: &PgPool, kind: ThrottleKind, key: &str) -> ApiResult<()> {
+    let throttle: Option<Throttle> = sqlx::query_as(
+        "SELECT failures,
+                extract(epoch FROM now() - last_failure_at)::BIGINT AS since_last,
+                coalesce(extract(epoch FROM locked_until - now())::BIGINT, 0) AS locked_for
+         FROM login_throttles WHERE kind = $1 AND key = $2",
+    )
+    .bind(kind)
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+    let Some(throttle) = throttle else {
+        return Ok(());
+    };
+    let retry_after = retry_after(
+        kind,
+        throttle.failures.try_into().unwrap_or_default(),
+        throttle.since_last.try_into().unwrap_or_default(),
+        throttle.locked_for.try_into().unwrap_or_default(),
+    );
+    if retry_after > 0 {
+        tracing::info!(kind = kind.as_str(), key, retry_after, "throttled login");
+        return Err(LoginThrottled::new(retry_after).into());
+    }
+    Ok(())
+}
+
+async fn fail(pool: &PgPool, kind: ThrottleKind, key: &str) -> ApiResult<()> {
+    let failures: i32 = sqlx::query_scalar(
+        "INSERT INTO login_throttles (kind, key, failures) VALUES ($1, $2, 1)
+         ON CONFLICT (kind, key) DO UPDATE
+         SET failures = CASE WHEN login_throttles.last_failure_at < now() - $3 THEN 1
+                             ELSE login_throttles.failures + 1 END,
+             last_failure_at = now()
+         RETURNING failures",
+    )
+    .bind(kind)
+    .bind(key)
+    .bind(WINDOW)
+    .fetch_one(pool)
+    .await?;
+    if u32::try_from(failures).unwrap_or_default() >= kind.lockout_after() {
+        sqlx::query(
+            "UPDATE login_throttles SET locked_until = now() + $3
+             WHERE kind = $1 AND key = $2",
+        )
+        .bind(kind)
+        .bind(key)
+        .bind(LOCKOUT)
+        .execute(pool)
+        .await?;
+        audit::record(
+            pool,
+            None,
+            "login_locked",
+            &format!("{} {key}", kind.as_str()),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// A login attempt, which may go ahead only if neither its source address
+/// nor, where known up front, its account is throttled.
+#[derive(Debug)]
+pub struct Attempt {
+    addr: String,
+    username: Option<String>,
+}
+
+impl Attempt {
+    pub async fn begin(
+        pool: &PgPool,
+        ClientAddr(addr): ClientAddr,
+        username: Option<&str>,
+    ) -> ApiResult<Self> {
+        check(pool, ThrottleKind::Ip, &addr).await?;
+        if let Some(username) = username {
+            check(pool, ThrottleKind::Account, username).await?;
+        }
+        Ok(Attempt {
+            addr,
+            username: username.map(str::to_string),
+        })
+    }
+
+    /// The address the attempt comes from.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Passes on the outcome of checking credentials, counting a failure
+    /// against the attempt if they were not good. Being asked for a second
+    /// factor is no failure.
+    pub async fn record<T>(&self, pool: &PgPool, outcome: ApiResult<T>) -> ApiResult<T> {
+        if matches!(&outcome, Err(err) if !matches!(err, ApiError::SecondFactorRequired(_))) {
+            fail(pool, ThrottleKind::Ip, &self.addr).await?;
+            if let Some(username) = &self.username {
+                fail(pool, ThrottleKind::Account, username).await?;
+            }
+        }
+        outcome
+    }
+
+    /// Lets `user` in unless their account is locked out, clearing its
+    /// failures and telling them of the login by mail.
+    pub async fn succeed(&self, pool: &PgPool, user: &User) -> ApiResult<()> {
+        check(pool, ThrottleKind::Account, &user.username).await?;
+        sqlx::query("DELETE FROM login_throttles WHERE kind = 'account' AND key = $1")
+            .bind(&user.username)
+            .execute(pool)
+            .await?;
+        email::notify_login(pool, user, &self.addr).await
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Listed {
+    kind: ThrottleKind,
+    key: String,
+    failures: i32,
+    last_failure_at: i64,
+    locked_until: Option<i64>,
+}
+
+async fn list(
+    Extension(pool): Extension<PgPool>,
+    _: Authorized<require::ManageUsers>,
+) -> ApiResult<Json<Vec<Listed>>> {
+    let throttles = sqlx::query_as(
+        "SELECT kind, key, failures,
+                extract(epoch FROM last_failure_at)::BIGINT AS last_failure_at,
+                extract(epoch FROM locked_until)::BIGINT AS locked_until
+         FROM login_throttles
+         WHERE last_failure_at > now() - $1 OR locked_until > now()
+         ORDER BY last_failure_at DESC",
+    )
+    .bind(WINDOW)
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(throttles))
+}
+
+async fn unlock(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageUsers>,
+    Path((kind, key)): Path<(ThrottleKind, String)>,
+) -> ApiResult<StatusCode> {
+    let unlocked = sqlx::query("DELETE FROM login_throttles WHERE kind = $1 AND key = $2")
+        .bind(kind)
+        .bind(&key)
+        .execute(&pool)
+        .await?;
+    if unlocked.rows_affected() == 0 {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "login_unlocked",
+        &format!("{} {key}", kind.as_str()),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list))
+        .route("/{kind}/{key}", delete(unlock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progressive_delays() {
+        let account = ThrottleKind::Account;
+        assert_eq!(delay(account, 0), Duration::ZERO);
+        assert_eq!(delay(account, 2), Duration::ZERO);
+        assert_eq!(delay(account, 3), Duration::from_secs(1));
+        assert_eq!(delay(account, 5), Duration::from_secs(4));
+        assert_eq!(delay(account, 9), MAX_DELAY);
+        assert_eq!(delay(account, u32::MAX), MAX_DELAY);
+        assert_eq!(delay(ThrottleKind::Ip, 5), Duration::ZERO);
+    }
+
+    #[test]
+    fn waiting() {
+        let account = ThrottleKind::Account;
+        assert_eq!(retry_after(account, 5, 1, 0), 3);
+        assert_eq!(retry_after(account, 5, 10, 0), 0);
+        assert_eq!(retry_after(account, 10, 10, 600), 600);
+        // Failures are forgotten after a while, lockouts are not.
+        assert_eq!(retry_after(account, 9, WINDOW.as_secs(), 0), 0);
+        assert_eq!(retry_after(account, 9, WINDOW.as_secs(), 5), 5);
+    }
+
+    #[test]
+    fn retry_after_header() {
+        use axum::response::IntoResponse;
+
+        let response = ApiError::from(LoginThrottled::new(30)).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+    }
+}
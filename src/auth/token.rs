@@ -0,0 +1,408 @@
+//! Personal access tokens for scripting the API.
+//!
+//! Tokens are accepted as `Authorization: Bearer` on everything under
+//! `/api` and are stored as SHA-256 hashes; being 32 random bytes, they need
+//! no slow password hash. Each token carries scopes limiting what it may be
+//! used for, which handlers demand with the [`Scoped`] extractor. Requests
+//! authenticated by session cookie are not limited by scopes.
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{InsufficientScope, InternalError, ResourceNotFound, Unauthenticated},
+    },
+    auth::session::{OwnUser, random_token},
+    users::{Role, User},
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{FromRequestParts, OriginalUri, Path, Request, rejection::JsonRejection},
+    http::{StatusCode, header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::{marker::PhantomData, time::Duration};
+
+/// Prefix telling our tokens apart from other bearer tokens, and making
+/// leaked ones easy to search for.
+pub const PREFIX: &str = "cfpat_";
+
+/// How often `last_used_at` is written back.
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "read:courses")]
+    ReadCourses,
+    #[serde(rename = "write:submissions")]
+    WriteSubmissions,
+    /// Creating courses and managing those the user teaches.
+    #[serde(rename = "manage:courses")]
+    ManageCourses,
+    /// Everything the token's user may do.
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadCourses => "read:courses",
+            Scope::WriteSubmissions => "write:submissions",
+            Scope::ManageCourses => "manage:courses",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Whether a user with global `role` may create tokens with this scope.
+    pub fn mintable_by(&self, role: Role) -> bool {
+        match self {
+            Scope::ReadCourses | Scope::WriteSubmissions => true,
+            Scope::ManageCourses => role >= Role::Instructor,
+            Scope::Admin => role == Role::Admin,
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "read:courses" => Ok(Scope::ReadCourses),
+            "write:submissions" => Ok(Scope::WriteSubmissions),
+            "manage:courses" => Ok(Scope::ManageCourses),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("unknown scope {scope}")),
+        }
+    }
+}
+
+/// The token a request was authenticated with, put into the request
+/// extensions by [`bearer`].
+#[derive(Clone, Debug)]
+pub struct Grant {
+    pub token_id: i64,
+    pub user: User,
+    pub scopes: Vec<Scope>,
+}
+
+impl Grant {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+/// Returns the hex-encoded SHA-256 hash of a random secret, like a token.
+pub fn hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Returns the personal access token in an `Authorization` header value.
+fn parse(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && token.starts_with(PREFIX)).then_some(token)
+}
+
+#[derive(Debug, FromRow)]
+struct Authenticated {
+    token_id: i64,
+    scopes: Vec<String>,
+    #[sqlx(flatten)]
+    user: User,
+}
+
+async fn authenticate(pool: &PgPool, token: &str) -> ApiResult<Option<Grant>> {
+    let authenticated: Option<Authenticated> = sqlx::query_as(
+        "SELECT api_tokens.id AS token_id, api_tokens.scopes,
+                users.id, users.username, users.email, users.display_name, users.role
+         FROM api_tokens JOIN users ON users.id = api_tokens.user_id
+         WHERE api_tokens.token_hash = $1 AND users.active
+           AND api_tokens.revoked_at IS NULL
+           AND (api_tokens.expires_at IS NULL OR api_tokens.expires_at > now())",
+    )
+    .bind(hash(token))
+    .fetch_optional(pool)
+    .await?;
+    let Some(authenticated) = authenticated else {
+        return Ok(None);
+    };
+    sqlx::query(
+        "UPDATE api_tokens SET last_used_at = now()
+         WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - $2)",
+    )
+    .bind(authenticated.token_id)
+    .bind(TOUCH_INTERVAL)
+    .execute(pool)
+    .await?;
+    Ok(Some(Grant {
+        token_id: authenticated.token_id,
+        user: authenticated.user,
+        scopes: authenticated
+            .scopes
+            .iter()
+            .filter_map(|scope| scope.parse().ok())
+            .collect(),
+    }))
+}
+
+/// Middleware authenticating requests that carry a personal access token.
+/// Other requests, including those with other kinds of bearer tokens, pass
+/// through untouched.
+pub async fn bearer(mut request: Request, next: Next) -> Result<Response, ApiError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse)
+        .map(str::to_string);
+    if let Some(token) = token {
+        let pool = request
+            .extensions()
+            .get::<PgPool>()
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::InternalError(InternalError::new("no database pool".into()))
+            })?;
+        let grant = authenticate(&pool, &token)
+            .await?
+            .ok_or_else(|| Unauthenticated::new("invalid token".to_string()))?;
+        request.extensions_mut().insert(grant);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Names a scope for [`Scoped`].
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+/// Scopes as types, for use as `Scoped<require::Admin>`. Permissions
+/// checked with [`Authorized`] bring their scope along instead.
+///
+/// [`Authorized`]: crate::auth::rbac::Authorized
+pub mod require {
+    use super::{RequiredScope, Scope};
+
+    #[derive(Debug)]
+    pub struct ReadCourses;
+
+    impl RequiredScope for ReadCourses {
+        const SCOPE: Scope = Scope::ReadCourses;
+    }
+
+    #[derive(Debug)]
+    pub struct Admin;
+
+    impl RequiredScope for Admin {
+        const SCOPE: Scope = Scope::Admin;
+    }
+}
+
+/// Extracts the current user like [`OwnUser`], so not while impersonating,
+/// additionally rejecting requests made with a token lacking scope `S`.
+#[derive(Debug)]
+pub struct Scoped<S>(pub User, pub PhantomData<S>);
+
+impl<S, T> FromRequestParts<T> for Scoped<S>
+where
+    S: RequiredScope,
+    T: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &T) -> Result<Self, Self::Rejection> {
+        if let Some(grant) = parts.extensions.get::<Grant>() {
+            if !grant.allows(S::SCOPE) {
+                tracing::info!(
+                    user = grant.user.username,
+                    token = grant.token_id,
+                    scope = S::SCOPE.as_str(),
+                    "token lacks scope"
+                );
+                return Err(InsufficientScope::new(S::SCOPE.as_str().to_string()).into());
+            }
+        }
+        let OwnUser(user) = OwnUser::from_request_parts(parts, state).await?;
+        Ok(Scoped(user, PhantomData))
+    }
+}
+
+/// A token as listed to its user. Its timestamps, like all the API returns,
+/// are seconds since the Unix epoch.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiToken {
+    id: i64,
+    name: String,
+    scopes: Vec<String>,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+const COLUMNS: &str = "id, name, scopes,
+    extract(epoch FROM created_at)::BIGINT AS created_at,
+    extract(epoch FROM expires_at)::BIGINT AS expires_at,
+    extract(epoch FROM last_used_at)::BIGINT AS last_used_at";
+
+/// Returns the tokens of a user that have not been revoked.
+pub async fn of_user(pool: &PgPool, user_id: i64) -> ApiResult<Vec<ApiToken>> {
+    Ok(sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM api_tokens
+         WHERE user_id = $1 AND revoked_at IS NULL
+         ORDER BY id"
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Revokes token `id` of a user. Returns whether there was such a token.
+pub async fn revoke_of_user(pool: &PgPool, user_id: i64, id: i64) -> ApiResult<bool> {
+    let revoked = sqlx::query(
+        "UPDATE api_tokens SET revoked_at = now()
+         WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(revoked.rows_affected() > 0)
+}
+
+async fn list(
+    Extension(pool): Extension<PgPool>,
+    Scoped(user, _): Scoped<require::Admin>,
+) -> ApiResult<Json<Vec<ApiToken>>> {
+    Ok(Json(of_user(&pool, user.id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewToken {
+    name: String,
+    scopes: Vec<Scope>,
+    /// Days until the token expires; it never does if left out.
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct Created {
+    /// The token itself, shown only this once.
+    token: String,
+    #[serde(flatten)]
+    details: ApiToken,
+}
+
+async fn create(
+    Extension(pool): Extension<PgPool>,
+    Scoped(user, _): Scoped<require::Admin>,
+    payload: Result<Json<NewToken>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(new) = payload?;
+    if let Some(scope) = new
+        .scopes
+        .iter()
+        .find(|scope| !scope.mintable_by(user.role))
+    {
+        return Err(InsufficientScope::new(scope.as_str().to_string()).into());
+    }
+    let token = format!("{PREFIX}{}", random_token());
+    let scopes: Vec<&str> = new.scopes.iter().map(Scope::as_str).collect();
+    let expires_in = new
+        .expires_in_days
+        .map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60));
+    let details: ApiToken = sqlx::query_as(&format!(
+        "INSERT INTO api_tokens (user_id, name, token_hash, scopes, expires_at)
+         VALUES ($1, $2, $3, $4, now() + $5)
+         RETURNING {COLUMNS}"
+    ))
+    .bind(user.id)
+    .bind(&new.name)
+    .bind(hash(&token))
+    .bind(&scopes)
+    .bind(expires_in)
+    .fetch_one(&pool)
+    .await?;
+    tracing::info!(
+        user = user.username,
+        token = details.id,
+        "created API token"
+    );
+    Ok((StatusCode::CREATED, Json(Created { token, details })).into_response())
+}
+
+async fn revoke(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    Scoped(user, _): Scoped<require::Admin>,
+    Path(id): Path<i64>,
+) -> ApiResult<StatusCode> {
+    if !revoke_of_user(&pool, user.id, id).await? {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    tracing::info!(user = user.username, token = id, "revoked API token");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list).post(create))
+        .route("/{id}", delete(revoke))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bearer_tokens() {
+        let token = format!("{PREFIX}{}", random_token());
+        assert_eq!(parse(&format!("Bearer {token}")), Some(token.as_str()));
+        assert_eq!(parse(&format!("bearer  {token}")), Some(token.as_str()));
+        assert_eq!(parse("Bearer some-job-token"), None);
+        assert_eq!(parse(&format!("Basic {token}")), None);
+        assert_eq!(hash(&token).len(), 64);
+        assert_ne!(hash(&token), token);
+    }
+
+    #[test]
+    fn scopes() {
+        let scopes: Vec<Scope> =
+            serde_json::from_str(r#"["read:courses", "write:submissions"]"#).unwrap();
+        assert_eq!(scopes, [Scope::ReadCourses, Scope::WriteSubmissions]);
+        assert!(serde_json::from_str::<Scope>(r#""write:courses""#).is_err());
+
+        let user = User {
+            id: 1,
+            username: "jdoe".to_string(),
+            email: None,
+            display_name: None,
+            role: Role::Instructor,
+        };
+        let grant = Grant {
+            token_id: 1,
+            user: user.clone(),
+            scopes: vec![Scope::ReadCourses],
+        };
+        assert!(grant.allows(Scope::ReadCourses));
+        assert!(!grant.allows(Scope::WriteSubmissions));
+        let admin = Grant {
+            scopes: vec![Scope::Admin],
+            ..grant
+        };
+        assert!(admin.allows(Scope::WriteSubmissions));
+
+        assert!(Scope::ManageCourses.mintable_by(Role::Instructor));
+        assert!(!Scope::ManageCourses.mintable_by(Role::Ta));
+        assert!(!Scope::Admin.mintable_by(Role::Instructor));
+    }
+}
@@ -0,0 +1,428 @@
+//! Time-based one-time passwords (RFC 6238) as a second factor for local
+//! accounts.
+//!
+//! Users enrol by loading the provisioning URI, usually shown as a QR code,
+//! into an authenticator app and confirming with a first code, which hands
+//! them ten recovery codes. Those are stored as SHA-256 hashes and each works
+//! once in place of a code. Codes are HMAC-SHA1 based, six digits long and
+//! change every 30 seconds, which is what authenticator apps assume; one step
+//! of clock drift either way is tolerated and no code is accepted twice.
+//!
+//! Users with a passkey (see [`webauthn`]) may use it as their second factor
+//! instead, and have to once they have one. `TOTP_REQUIRED_ROLE` makes a
+//! second factor, either kind, mandatory for users with at least the given
+//! role, e.g. `instructor`. Such users without one get a restricted session
+//! on local login, good only for enrolling.
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{
+            Forbidden, InternalError, ResourceNotFound, SecondFactorEnabled, SecondFactorRequired,
+            Unauthenticated,
+        },
+    },
+    audit,
+    auth::{
+        session::{self, EnrollingUser, OwnUser},
+        token,
+        webauthn::{self, AssertionCredential},
+    },
+    users::{Role, User},
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sqlx::{FromRow, PgPool};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const ISSUER: &str = "CeresForge";
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+
+/// Returns the HOTP value (RFC 4226) of `secret` for `counter`.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let value = u32::from_be_bytes([
+        digest[offset],
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]) & 0x7fff_ffff;
+    value % 10u32.pow(DIGITS)
+}
+
+/// Returns the time step of code `code` for `secret` at Unix time `now`,
+/// if it is valid and later than `last_used_step`.
+fn verify(secret: &[u8], code: &str, now: u64, last_used_step: u64) -> Option<u64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = now / STEP;
+    (current.saturating_sub(1)..=current + 1)
+        .filter(|step| *step > last_used_step)
+        .find(|step| hotp(secret, *step) == code)
+}
+
+/// Encodes bytes in unpadded base32 (RFC 4648), as authenticator apps
+/// expect secrets.
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    encoded
+}
+
+/// Returns the `otpauth://` URI to enrol `secret` in an authenticator app.
+fn provisioning_uri(username: &str, secret: &[u8]) -> String {
+    let mut uri = Url::parse("otpauth://totp/").expect("URI is valid");
+    uri.set_path(&format!("{ISSUER}:{username}"));
+    uri.query_pairs_mut()
+        .append_pair("secret", &base32(secret))
+        .append_pair("issuer", ISSUER)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP.to_string());
+    uri.into()
+}
+
+/// Returns a recovery code of 80 random bits, like `abcd-efgh-ijkl-mnop`.
+fn recovery_code() -> String {
+    let code = base32(&rand::random::<[u8; 10]>()).to_lowercase();
+    let groups: Vec<&str> = (0..code.len())
+        .step_by(4)
+        .map(|i| &code[i..i + 4])
+        .collect();
+    groups.join("-")
+}
+
+/// Returns the hash a recovery code is stored as, ignoring the formatting
+/// users might get wrong.
+fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    token::hash(&code)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// Whether users with `role` have to use a second factor.
+fn required(role: Role) -> ApiResult<bool> {
+    let Ok(required) = std::env::var("TOTP_REQUIRED_ROLE") else {
+        return Ok(false);
+    };
+    let required: Role = required
+        .parse()
+        .map_err(|err: String| ApiError::InternalError(InternalError::new(err.into())))?;
+    Ok(role >= required)
+}
+
+#[derive(Debug, FromRow)]
+struct Credential {
+    secret: Vec<u8>,
+    last_used_step: i64,
+    confirmed: bool,
+}
+
+async fn credential(pool: &PgPool, user_id: i64) -> ApiResult<Option<Credential>> {
+    Ok(sqlx::query_as(
+        "SELECT secret, last_used_step, confirmed_at IS NOT NULL AS confirmed
+         FROM totp_credentials WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// A code, recovery code or passkey assertion submitted along with a
+/// password.
+#[derive(Debug, Default, Deserialize)]
+pub struct SecondFactor {
+    pub totp_code: Option<String>,
+    pub recovery_code: Option<String>,
+    pub passkey: Option<AssertionCredential>,
+}
+
+/// Checks the second factor of a user with a confirmed credential, using up
+/// the code or recovery code.
+async fn redeem(
+    pool: &PgPool,
+    user_id: i64,
+    credential: &Credential,
+    factor: &SecondFactor,
+) -> ApiResult<()> {
+    let redeemed = if let Some(code) = &factor.totp_code {
+        match verify(
+            &credential.secret,
+            code,
+            now(),
+            credential.last_used_step as u64,
+        ) {
+            // Concurrent logins with the same code race here, and only one
+            // of them wins.
+            Some(step) => sqlx::query(
+                "UPDATE totp_credentials SET last_used_step = $2
+                 WHERE user_id = $1 AND last_used_step < $2",
+            )
+            .bind(user_id)
+            .bind(step as i64)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+            None => 0,
+        }
+    } else if let Some(code) = &factor.recovery_code {
+        let redeemed = sqlx::query(
+            "UPDATE recovery_codes SET used_at = now()
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(hash_recovery_code(code))
+        .execute(pool)
+        .await?
+        .rows_affected();
+        if redeemed > 0 {
+            tracing::info!(user = user_id, "used recovery code");
+        }
+        redeemed
+    } else {
+        return Err(SecondFactorRequired::new("two-factor code required".to_string()).into());
+    };
+    if redeemed == 0 {
+        return Err(Unauthenticated::new("invalid two-factor code".to_string()).into());
+    }
+    Ok(())
+}
+
+/// Checks the second factor of a user logging in with the right password.
+/// Returns whether their session has to be restricted to enrolling, because
+/// they need a second factor but have none yet.
+pub async fn check(pool: &PgPool, user: &User, factor: &SecondFactor) -> ApiResult<bool> {
+    if let Some(passkey) = &factor.passkey {
+        webauthn::verify_second_factor(pool, user.id, passkey).await?;
+        return Ok(false);
+    }
+    match credential(pool, user.id).await? {
+        Some(credential) if credential.confirmed => {
+            redeem(pool, user.id, &credential, factor).await?;
+            Ok(false)
+        }
+        _ if webauthn::registered(pool, user.id).await? => {
+            Err(SecondFactorRequired::new("passkey required".to_string()).into())
+        }
+        _ => required(user.role),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Enrolment {
+    /// The secret in base32, for typing into apps that cannot scan codes.
+    secret: String,
+    uri: String,
+}
+
+async fn enrol(
+    Extension(pool): Extension<PgPool>,
+    EnrollingUser(user): EnrollingUser,
+) -> ApiResult<Json<Enrolment>> {
+    let secret = rand::random::<[u8; 20]>();
+    // Starting over replaces an unconfirmed secret, never a confirmed one.
+    let enrolled = sqlx::query(
+        "INSERT INTO totp_credentials (user_id, secret) VALUES ($1, $2)
+         ON CONFLICT (user_id) DO UPDATE
+         SET secret = EXCLUDED.secret, last_used_step = 0, created_at = now()
+         WHERE totp_credentials.confirmed_at IS NULL",
+    )
+    .bind(user.id)
+    .bind(&secret[..])
+    .execute(&pool)
+    .await?;
+    if enrolled.rows_affected() == 0 {
+        return Err(SecondFactorEnabled::new(user.username).into());
+    }
+    Ok(Json(Enrolment {
+        secret: base32(&secret),
+        uri: provisioning_uri(&user.username, &secret),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct Confirmation {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryCodes {
+    /// Shown only this once.
+    recovery_codes: Vec<String>,
+}
+
+async fn confirm(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    EnrollingUser(user): EnrollingUser,
+    payload: Result<Json<Confirmation>, JsonRejection>,
+) -> ApiResult<Json<RecoveryCodes>> {
+    let Json(confirmation) = payload?;
+    let credential = credential(&pool, user.id)
+        .await?
+        .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?;
+    if credential.confirmed {
+        return Err(SecondFactorEnabled::new(user.username).into());
+    }
+    let step = verify(&credential.secret, &confirmation.code, now(), 0)
+        .ok_or_else(|| Unauthenticated::new("invalid two-factor code".to_string()))?;
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE totp_credentials SET confirmed_at = now(), last_used_step = $2
+         WHERE user_id = $1",
+    )
+    .bind(user.id)
+    .bind(step as i64)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, unnest($2::TEXT[])")
+        .bind(user.id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    if let Some(id) = session::from_headers(&headers)? {
+        session::lift_restriction(&pool, id).await?;
+    }
+    audit::record(&pool, Some(user.id), "totp_enabled", &user.username).await?;
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+async fn disable(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    OwnUser(user): OwnUser,
+    payload: Result<Json<SecondFactor>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    let Json(factor) = payload?;
+    if required(user.role)? {
+        return Err(Forbidden::new("disable_two_factor".to_string()).into());
+    }
+    let credential = credential(&pool, user.id)
+        .await?
+        .filter(|credential| credential.confirmed)
+        .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?;
+    // A session alone must not be enough to weaken the account.
+    redeem(&pool, user.id, &credential, &factor).await?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM totp_credentials WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    audit::record(&pool, Some(user.id), "totp_disabled", &user.username).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/enrol", post(enrol))
+        .route("/confirm", post(confirm))
+        .route("/disable", post(disable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc_test_vectors() {
+        // RFC 4226, appendix D.
+        assert_eq!(hotp(SECRET, 0), 755224);
+        assert_eq!(hotp(SECRET, 9), 520489);
+        // RFC 6238, appendix B, truncated to six digits.
+        assert_eq!(hotp(SECRET, 59 / STEP), 287082);
+        assert_eq!(hotp(SECRET, 1111111109 / STEP), 81804);
+        assert_eq!(base32(SECRET), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32(b"f"), "MY");
+    }
+
+    #[test]
+    fn drift_and_replay() {
+        let now = 1111111109;
+        let step = now / STEP;
+        assert_eq!(verify(SECRET, "081804", now, 0), Some(step));
+        assert_eq!(verify(SECRET, "081 804", now, 0), Some(step));
+        assert_eq!(verify(SECRET, "081804", now + STEP, 0), Some(step));
+        assert_eq!(verify(SECRET, "081804", now + 2 * STEP, 0), None);
+        assert_eq!(verify(SECRET, "081804", now, step), None);
+        assert_eq!(verify(SECRET, "81804", now, 0), None);
+    }
+
+    #[test]
+    fn enrolment() {
+        assert_eq!(
+            provisioning_uri("jdoe", SECRET),
+            "otpauth://totp/CeresForge:jdoe?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=CeresForge&algorithm=SHA1&digits=6&period=30"
+        );
+        let code = recovery_code();
+        assert_eq!(code.len(), 19);
+        assert_eq!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&code.replace('-', "").to_uppercase())
+        );
+        assert_ne!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&recovery_code())
+        );
+    }
+}
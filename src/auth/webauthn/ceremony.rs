@@ -0,0 +1,415 @@
+//! Verification of what authenticators return from registration and
+//! authentication ceremonies (WebAuthn Level 2, sections 7.1 and 7.2).
+//!
+//! Only attestation format `none` is accepted: we ask browsers not to
+//! attest, since we have no use for knowing the authenticator's make.
+//! Public keys are COSE keys, ES256 or RS256, stored as the authenticator
+//! encoded them.
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use ciborium::Value;
+use p256::ecdsa;
+use rsa::{BigUint, RsaPublicKey, pkcs1v15, signature::Verifier};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// COSE algorithm identifiers of the signatures we verify.
+pub const ES256: i64 = -7;
+pub const RS256: i64 = -257;
+
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Who ceremonies are performed for: the RP ID is our host name, which
+/// credentials are scoped to, and the origin is what browsers report.
+#[derive(Debug)]
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+/// Checks the client data of a ceremony of type `kind`, `webauthn.create`
+/// or `webauthn.get`, and returns its challenge, base64url encoded.
+pub fn client_data(json: &[u8], kind: &str, rp: &RelyingParty) -> Result<String, String> {
+    let client_data: ClientData =
+        serde_json::from_slice(json).map_err(|err| format!("malformed client data: {err}"))?;
+    if client_data.kind != kind {
+        return Err(format!("expected {kind}, got {}", client_data.kind));
+    }
+    if client_data.origin != rp.origin || client_data.cross_origin {
+        return Err(format!("unexpected origin {}", client_data.origin));
+    }
+    Ok(client_data.challenge)
+}
+
+/// A credential created by a registration ceremony.
+#[derive(Debug, PartialEq)]
+pub struct Credential {
+    pub id: Vec<u8>,
+    /// COSE_Key encoded.
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Debug)]
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions, when present.
+    rest: &'a [u8],
+}
+
+fn authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData<'_>, String> {
+    if bytes.len() < 37 {
+        return Err("authenticator data too short".to_string());
+    }
+    Ok(AuthenticatorData {
+        rp_id_hash: &bytes[..32],
+        flags: bytes[32],
+        sign_count: u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]),
+        rest: &bytes[37..],
+    })
+}
+
+/// Checks the parts of authenticator data both ceremonies have in common.
+fn check_flags(
+    data: &AuthenticatorData,
+    rp: &RelyingParty,
+    user_verification: bool,
+) -> Result<(), String> {
+    if data.rp_id_hash != Sha256::digest(rp.id.as_bytes()).as_slice() {
+        return Err("credential is for another relying party".to_string());
+    }
+    if data.flags & USER_PRESENT == 0 {
+        return Err("user was not present".to_string());
+    }
+    if user_verification && data.flags & USER_VERIFIED == 0 {
+        return Err("user was not verified".to_string());
+    }
+    Ok(())
+}
+
+fn map_entry<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, value)| value)
+}
+
+/// Verifies an attestation object with format `none` and returns the
+/// credential it creates.
+pub fn verify_attestation(
+    attestation_object: &[u8],
+    rp: &RelyingParty,
+    user_verification: bool,
+) -> Result<Credential, String> {
+    let object: Value = ciborium::from_reader(attestation_object)
+        .map_err(|err| format!("malformed attestation object: {err}"))?;
+    let object = object.as_map().ok_or("attestation object is not a map")?;
+    let format = map_entry(object, "fmt").and_then(Value::as_text);
+    if format != Some("none") {
+        return Err(format!("unsupported attestation format {format:?}"));
+    }
+    let auth_data = map_entry(object, "authData")
+        .and_then(Value::as_bytes)
+        .ok_or("attestation object has no authenticator data")?;
+    let data = authenticator_data(auth_data)?;
+    check_flags(&data, rp, user_verification)?;
+    if data.flags & ATTESTED_CREDENTIAL == 0 {
+        return Err("no attested credential data".to_string());
+    }
+
+    // AAGUID (16 bytes), credential ID length (2), credential ID, public key.
+    let rest = data.rest;
+    if rest.len() < 18 {
+        return Err("attested credential data too short".to_string());
+    }
+    let id_length = usize::from(u16::from_be_bytes([rest[16], rest[17]]));
+    let id = rest
+        .get(18..18 + id_length)
+        .ok_or("credential ID out of bounds")?;
+    let mut key = &rest[18 + id_length..];
+    let before = key.len();
+    let cose: Value =
+        ciborium::from_reader(&mut key).map_err(|err| format!("malformed public key: {err}"))?;
+    let public_key = &rest[18 + id_length..18 + id_length + before - key.len()];
+    PublicKey::from_cose(&cose)?;
+    Ok(Credential {
+        id: id.to_vec(),
+        public_key: public_key.to_vec(),
+        sign_count: data.sign_count,
+    })
+}
+
+#[derive(Debug)]
+enum PublicKey {
+    Es256(ecdsa::VerifyingKey),
+    Rs256(RsaPublicKey),
+}
+
+impl PublicKey {
+    fn from_cose(key: &Value) -> Result<Self, String> {
+        let map = key.as_map().ok_or("public key is not a map")?;
+        let param = |label: i64| {
+            map.iter()
+                .find(|(k, _)| k.as_integer() == Some(label.into()))
+                .map(|(_, value)| value)
+        };
+        let integer = |label| param(label).and_then(Value::as_integer).map(i128::from);
+        let bytes = |label| param(label).and_then(Value::as_bytes);
+        // Key type 1, algorithm 3; the rest depends on the key type.
+        match (integer(1), integer(3)) {
+            (Some(2), Some(alg)) if alg == ES256.into() && integer(-1) == Some(1) => {
+                let (x, y) = bytes(-2)
+                    .zip(bytes(-3))
+                    .ok_or("EC2 key lacks coordinates")?;
+                if x.len() != 32 || y.len() != 32 {
+                    return Err("EC2 coordinates have the wrong length".to_string());
+                }
+                let point = p256::EncodedPoint::from_affine_coordinates(
+                    x.as_slice().into(),
+                    y.as_slice().into(),
+                    false,
+                );
+                ecdsa::VerifyingKey::from_encoded_point(&point)
+                    .map(PublicKey::Es256)
+                    .map_err(|_| "EC2 key is not on P-256".to_string())
+            }
+            (Some(3), Some(alg)) if alg == RS256.into() => {
+                let (n, e) = bytes(-1).zip(bytes(-2)).ok_or("RSA key lacks parameters")?;
+                RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+                    .map(PublicKey::Rs256)
+                    .map_err(|err| format!("invalid RSA key: {err}"))
+            }
+            (kty, alg) => Err(format!(
+                "unsupported key type {kty:?} with algorithm {alg:?}"
+            )),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PublicKey::Es256(key) => ecdsa::Signature::from_der(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            PublicKey::Rs256(key) => {
+                pkcs1v15::Signature::try_from(signature).is_ok_and(|signature| {
+                    pkcs1v15::VerifyingKey::<Sha256>::new(key.clone())
+                        .verify(message, &signature)
+                        .is_ok()
+                })
+            }
+        }
+    }
+}
+
+/// What an authentication ceremony returned for a credential.
+#[derive(Debug)]
+pub struct Assertion<'a> {
+    pub client_data_json: &'a [u8],
+    pub authenticator_data: &'a [u8],
+    pub signature: &'a [u8],
+}
+
+/// Verifies an assertion made with `public_key`, whose signature counter
+/// stood at `sign_count`, and returns the new counter value.
+pub fn verify_assertion(
+    assertion: &Assertion,
+    public_key: &[u8],
+    sign_count: u32,
+    rp: &RelyingParty,
+    user_verification: bool,
+) -> Result<u32, String> {
+    let data = authenticator_data(assertion.authenticator_data)?;
+    check_flags(&data, rp, user_verification)?;
+    let cose: Value =
+        ciborium::from_reader(public_key).map_err(|err| format!("malformed public key: {err}"))?;
+    let mut signed = assertion.authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(assertion.client_data_json));
+    if !PublicKey::from_cose(&cose)?.verify(&signed, assertion.signature) {
+        return Err("signature mismatch".to_string());
+    }
+    // Authenticators without a counter always report zero. Otherwise, a
+    // counter not moving forward means the credential was cloned.
+    if (data.sign_count != 0 || sign_count != 0) && data.sign_count <= sign_count {
+        return Err(format!(
+            "signature counter went from {sign_count} to {}",
+            data.sign_count
+        ));
+    }
+    Ok(data.sign_count)
+}
+
+/// Decodes the base64url fields of the JSON serialization of credentials.
+pub fn decode(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    BASE64_URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|err| format!("malformed {field}: {err}"))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use p256::ecdsa::{SigningKey, signature::Signer};
+    use serde_json::json;
+
+    pub fn relying_party() -> RelyingParty {
+        RelyingParty {
+            id: "ceresforge.example.edu".to_string(),
+            origin: "https://ceresforge.example.edu".to_string(),
+        }
+    }
+
+    /// A software authenticator holding a single ES256 credential.
+    pub struct Authenticator {
+        pub credential_id: Vec<u8>,
+        key: SigningKey,
+        pub sign_count: u32,
+    }
+
+    impl Authenticator {
+        pub fn new() -> Self {
+            Authenticator {
+                credential_id: b"software credential".to_vec(),
+                key: SigningKey::from_slice(&[7; 32]).unwrap(),
+                sign_count: 0,
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let key = Value::Map(vec![
+                (1.into(), 2.into()),
+                (3.into(), ES256.into()),
+                ((-1).into(), 1.into()),
+                ((-2).into(), Value::Bytes(point.x().unwrap().to_vec())),
+                ((-3).into(), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&key, &mut bytes).unwrap();
+            bytes
+        }
+
+        fn authenticator_data(&self, rp_id: &str, flags: u8, attested: bool) -> Vec<u8> {
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(flags | if attested { ATTESTED_CREDENTIAL } else { 0 });
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            if attested {
+                data.extend_from_slice(&[0; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                data.extend_from_slice(&self.cose_key());
+            }
+            data
+        }
+
+        pub fn client_data(kind: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            serde_json::to_vec(&json!({
+                "type": kind,
+                "challenge": challenge,
+                "origin": origin,
+                "crossOrigin": false,
+            }))
+            .unwrap()
+        }
+
+        /// Returns the attestation object of `navigator.credentials.create`.
+        pub fn create(&self, rp_id: &str) -> Vec<u8> {
+            let object = Value::Map(vec![
+                ("fmt".into(), "none".into()),
+                ("attStmt".into(), Value::Map(Vec::new())),
+                (
+                    "authData".into(),
+                    Value::Bytes(self.authenticator_data(
+                        rp_id,
+                        USER_PRESENT | USER_VERIFIED,
+                        true,
+                    )),
+                ),
+            ]);
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&object, &mut bytes).unwrap();
+            bytes
+        }
+
+        /// Returns authenticator data and signature of
+        /// `navigator.credentials.get`.
+        pub fn get(&mut self, rp_id: &str, client_data_json: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            self.sign_count += 1;
+            let data = self.authenticator_data(rp_id, USER_PRESENT | USER_VERIFIED, false);
+            let mut signed = data.clone();
+            signed.extend_from_slice(&Sha256::digest(client_data_json));
+            let signature: ecdsa::Signature = self.key.sign(&signed);
+            (data, signature.to_der().as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn client_data_checks() {
+        let rp = relying_party();
+        let json = Authenticator::client_data("webauthn.get", "abc", &rp.origin);
+        assert_eq!(client_data(&json, "webauthn.get", &rp).unwrap(), "abc");
+        assert!(client_data(&json, "webauthn.create", &rp).is_err());
+        let phished = Authenticator::client_data("webauthn.get", "abc", "https://evil.example");
+        assert!(client_data(&phished, "webauthn.get", &rp).is_err());
+    }
+
+    #[test]
+    fn registration_and_authentication() {
+        let rp = relying_party();
+        let mut authenticator = Authenticator::new();
+        let credential = verify_attestation(&authenticator.create(&rp.id), &rp, true).unwrap();
+        assert_eq!(credential.id, authenticator.credential_id);
+        assert_eq!(credential.sign_count, 0);
+        assert!(verify_attestation(&authenticator.create("evil.example"), &rp, true).is_err());
+
+        let client_data_json = Authenticator::client_data("webauthn.get", "abc", &rp.origin);
+        let (data, signature) = authenticator.get(&rp.id, &client_data_json);
+        let assertion = Assertion {
+            client_data_json: &client_data_json,
+            authenticator_data: &data,
+            signature: &signature,
+        };
+        assert_eq!(
+            verify_assertion(&assertion, &credential.public_key, 0, &rp, true),
+            Ok(1)
+        );
+        // A replayed assertion does not move the counter forward.
+        assert!(verify_assertion(&assertion, &credential.public_key, 1, &rp, true).is_err());
+
+        let tampered = Authenticator::client_data("webauthn.get", "abd", &rp.origin);
+        let assertion = Assertion {
+            client_data_json: &tampered,
+            ..assertion
+        };
+        assert_eq!(
+            verify_assertion(&assertion, &credential.public_key, 0, &rp, true),
+            Err("signature mismatch".to_string())
+        );
+    }
+
+    #[test]
+    fn attestation_formats() {
+        let rp = relying_party();
+        let authenticator = Authenticator::new();
+        let mut object: Value = ciborium::from_reader(&authenticator.create(&rp.id)[..]).unwrap();
+        object.as_map_mut().unwrap()[0].1 = "packed".into();
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&object, &mut bytes).unwrap();
+        assert!(
+            verify_attestation(&bytes, &rp, true)
+                .unwrap_err()
+                .starts_with("unsupported attestation format")
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! Passkeys: WebAuthn credentials replacing passwords, or complementing them
+//! as a second factor.
+//!
+//! Our relying party ID is the host of `BASE_URL`. Options and credentials
+//! are exchanged in the JSON serialization of WebAuthn Level 3, so browsers
+//! can use `PublicKeyCredential.parseCreationOptionsFromJSON` and `toJSON`.
+//! Passkeys are discoverable credentials with user verification, so logging
+//! in with one needs neither username nor password and counts as two
+//! factors. Passkeys used as a second factor after a password only need to
+//! prove the user's presence.
+
+mod ceremony;
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{
+            Forbidden, InternalError, InvalidWebauthnResponse, ResourceNotFound, Unauthenticated,
+        },
+    },
+    audit,
+    auth::{
+        session::{self, AuthMethod, CurrentUser, EnrollingUser, Origin, OwnUser},
+        throttle::{Attempt, ClientAddr},
+        totp::{self, SecondFactor},
+    },
+    users::{self, User},
+};
+use ceremony::{Assertion, RelyingParty, decode};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, Path, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{FromRow, PgPool};
+use url::Url;
+
+const NAME: &str = "CeresForge";
+
+/// How long a ceremony may take, in milliseconds.
+const TIMEOUT: u64 = 5 * 60 * 1000;
+
+fn invalid(reason: impl Into<String>) -> InvalidWebauthnResponse {
+    InvalidWebauthnResponse::new(reason.into())
+}
+
+fn relying_party() -> ApiResult<RelyingParty> {
+    let base_url = Url::parse(&std::env::var("BASE_URL")?).map_err(|err| {
+        ApiError::InternalError(InternalError::new(format!("bad BASE_URL: {err}").into()))
+    })?;
+    let id = base_url.host_str().ok_or_else(|| {
+        ApiError::InternalError(InternalError::new("BASE_URL has no host".into()))
+    })?;
+    Ok(RelyingParty {
+        id: id.to_string(),
+        origin: base_url.origin().ascii_serialization(),
+    })
+}
+
+/// The user handle authenticators store along with a passkey. It must not
+/// identify the user to anyone else, which our user IDs do not.
+fn user_handle(user_id: i64) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(user_id.to_be_bytes())
+}
+
+/// Starts a ceremony, for `user_id` if it is a registration, and returns its
+/// challenge.
+async fn challenge(pool: &PgPool, user_id: Option<i64>) -> ApiResult<String> {
+    sqlx::query("DELETE FROM webauthn_challenges WHERE created_at < now() - interval '5 minutes'")
+        .execute(pool)
+        .await?;
+    let challenge = BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    sqlx::query("INSERT INTO webauthn_challenges (challenge, user_id) VALUES ($1, $2)")
+        .bind(&challenge)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(challenge)
+}
+
+/// Uses up a challenge, which has to have been issued for `user_id`.
+async fn consume(pool: &PgPool, challenge: &str, user_id: Option<i64>) -> ApiResult<()> {
+    let consumed = sqlx::query(
+        "DELETE FROM webauthn_challenges
+         WHERE challenge = $1 AND user_id IS NOT DISTINCT FROM $2
+           AND created_at > now() - interval '5 minutes'",
+    )
+    .bind(challenge)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    if consumed.rows_affected() == 0 {
+        return Err(invalid("unknown or expired challenge").into());
+    }
+    Ok(())
+}
+
+/// Whether a user has any passkeys.
+pub async fn registered(pool: &PgPool, user_id: i64) -> ApiResult<bool> {
+    Ok(
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM webauthn_credentials WHERE user_id = $1)")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?,
+    )
+}
+
+async fn registration_options(
+    Extension(pool): Extension<PgPool>,
+    EnrollingUser(user): EnrollingUser,
+) -> ApiResult<Json<Value>> {
+    let rp = relying_party()?;
+    let existing: Vec<Vec<u8>> =
+        sqlx::query_scalar("SELECT credential_id FROM webauthn_credentials WHERE user_id = $1")
+            .bind(user.id)
+            .fetch_all(&pool)
+            .await?;
+    let challenge = challenge(&pool, Some(user.id)).await?;
+    Ok(Json(json!({
+        "challenge": challenge,
+        "rp": { "id": rp.id, "name": NAME },
+        "user": {
+            "id": user_handle(user.id),
+            "name": user.username,
+            "displayName": user.display_name.as_deref().unwrap_or(&user.username),
+        },
+        "pubKeyCredParams": [
+            { "type": "public-key", "alg": ceremony::ES256 },
+            { "type": "public-key", "alg": ceremony::RS256 },
+        ],
+        "timeout": TIMEOUT,
+        "excludeCredentials": existing
+            .iter()
+            .map(|id| json!({ "type": "public-key", "id": BASE64_URL_SAFE_NO_PAD.encode(id) }))
+            .collect::<Vec<_>>(),
+        "authenticatorSelection": {
+            "residentKey": "required",
+            "requireResidentKey": true,
+            "userVerification": "required",
+        },
+        "attestation": "none",
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistrationCredential {
+    response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct Registration {
+    /// What the user calls the passkey, to tell it from their others.
+    name: String,
+    credential: RegistrationCredential,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Passkey {
+    id: i64,
+    name: String,
+    created_at: i64,
+    last_used_at: Option<i64>,
+}
+
+const COLUMNS: &str = "id, name,
+    extract(epoch FROM created_at)::BIGINT AS created_at,
+    extract(epoch FROM last_used_at)::BIGINT AS last_used_at";
+
+async fn register(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    EnrollingUser(user): EnrollingUser,
+    payload: Result<Json<Registration>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(registration) = payload?;
+    let rp = relying_party()?;
+    let response = &registration.credential.response;
+    let client_data_json = decode("clientDataJSON", &response.client_data_json).map_err(invalid)?;
+    let challenge =
+        ceremony::client_data(&client_data_json, "webauthn.create", &rp).map_err(invalid)?;
+    consume(&pool, &challenge, Some(user.id)).await?;
+    let attestation_object =
+        decode("attestationObject", &response.attestation_object).map_err(invalid)?;
+    let credential =
+        ceremony::verify_attestation(&attestation_object, &rp, true).map_err(invalid)?;
+
+    let passkey: Passkey = sqlx::query_as(&format!(
+        "INSERT INTO webauthn_credentials (user_id, credential_id, name, public_key, sign_count)
+         VALUES ($1, $2, $3, $4, $5)
+         RETURNING {COLUMNS}"
+    ))
+    .bind(user.id)
+    .bind(&credential.id)
+    .bind(&registration.name)
+    .bind(&credential.public_key)
+    .bind(i64::from(credential.sign_count))
+    .fetch_one(&pool)
+    .await
+    .map_err(|err| match err.as_database_error() {
+        Some(db) if db.is_unique_violation() => invalid("passkey is already registered").into(),
+        _ => ApiError::from(err),
+    })?;
+    // A passkey satisfies a second factor requirement as well as TOTP does.
+    if let Some(id) = session::from_headers(&headers)? {
+        session::lift_restriction(&pool, id).await?;
+    }
+    audit::record(
+        &pool,
+        Some(user.id),
+        "passkey_registered",
+        &registration.name,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(passkey)).into_response())
+}
+
+async fn authentication_options(Extension(pool): Extension<PgPool>) -> ApiResult<Json<Value>> {
+    let rp = relying_party()?;
+    let challenge = challenge(&pool, None).await?;
+    Ok(Json(json!({
+        "challenge": challenge,
+        "rpId": rp.id,
+        "timeout": TIMEOUT,
+        "allowCredentials": [],
+        "userVerification": "required",
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    user_handle: Option<String>,
+}
+
+/// The JSON serialization of the credential `navigator.credentials.get`
+/// returns.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionCredential {
+    raw_id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Debug, FromRow)]
+struct Stored {
+    passkey_id: i64,
+    public_key: Vec<u8>,
+    sign_count: i64,
+    #[sqlx(flatten)]
+    user: User,
+}
+
+/// Verifies an assertion, which has to be made by a passkey of `user_id` if
+/// given, and returns the passkey's user.
+async fn authenticate(
+    pool: &PgPool,
+    credential: &AssertionCredential,
+    user_id: Option<i64>,
+    user_verification: bool,
+) -> ApiResult<User> {
+    let rp = relying_party()?;
+    let response = &credential.response;
+    let client_data_json = decode("clientDataJSON", &response.client_data_json).map_err(invalid)?;
+    let authenticator_data =
+        decode("authenticatorData", &response.authenticator_data).map_err(invalid)?;
+    let signature = decode("signature", &response.signature).map_err(invalid)?;
+    let credential_id = decode("rawId", &credential.raw_id).map_err(invalid)?;
+    let challenge =
+        ceremony::client_data(&client_data_json, "webauthn.get", &rp).map_err(invalid)?;
+    consume(pool, &challenge, None).await?;
+
+    let stored: Stored = sqlx::query_as(
+        "SELECT webauthn_credentials.id AS passkey_id, webauthn_credentials.public_key,
+                webauthn_credentials.sign_count,
+                users.id, users.username, users.email, users.display_name, users.role
+         FROM webauthn_credentials JOIN users ON users.id = webauthn_credentials.user_id
+         WHERE webauthn_credentials.credential_id = $1",
+    )
+    .bind(&credential_id)
+    .fetch_optional(pool)
+    .await?
+    .filter(|stored: &Stored| user_id.is_none_or(|user_id| stored.user.id == user_id))
+    .ok_or_else(|| Unauthenticated::new("unknown passkey".to_string()))?;
+    if let Some(handle) = &response.user_handle {
+        if *handle != user_handle(stored.user.id) {
+            return Err(Unauthenticated::new("unknown passkey".to_string()).into());
+        }
+    }
+    let assertion = Assertion {
+        client_data_json: &client_data_json,
+        authenticator_data: &authenticator_data,
+        signature: &signature,
+    };
+    let sign_count = ceremony::verify_assertion(
+        &assertion,
+        &stored.public_key,
+        stored.sign_count as u32,
+        &rp,
+        user_verification,
+    )
+    .map_err(|reason| {
+        tracing::warn!(
+            user = stored.user.username,
+            passkey = stored.passkey_id,
+            reason,
+            "passkey assertion rejected"
+        );
+        Unauthenticated::new("passkey verification failed".to_string())
+    })?;
+    sqlx::query(
+        "UPDATE webauthn_credentials SET sign_count = $2, last_used_at = now() WHERE id = $1",
+    )
+    .bind(stored.passkey_id)
+    .bind(i64::from(sign_count))
+    .execute(pool)
+    .await?;
+    Ok(stored.user)
+}
+
+/// Checks a passkey used as second factor by a user who gave their
+/// password.
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: i64,
+    credential: &AssertionCredential,
+) -> ApiResult<()> {
+    authenticate(pool, credential, Some(user_id), false).await?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct Login {
+    credential: AssertionCredential,
+}
+
+async fn login(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    addr: ClientAddr,
+    payload: Result<Json<Login>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(login) = payload?;
+    let attempt = Attempt::begin(&pool, addr, None).await?;
+    let user = authenticate(&pool, &login.credential, None, true).await;
+    let user = attempt.record(&pool, user).await?;
+    attempt.succeed(&pool, &user).await?;
+    let cookie = session::create(
+        &pool,
+        &headers,
+        user.id,
+        Origin {
+            method: AuthMethod::Passkey,
+            addr: attempt.addr(),
+        },
+        None,
+    )
+    .await?;
+    Ok(([(header::SET_COOKIE, cookie)], Json(user)).into_response())
+}
+
+async fn list(
+    Extension(pool): Extension<PgPool>,
+    CurrentUser(user): CurrentUser,
+) -> ApiResult<Json<Vec<Passkey>>> {
+    let passkeys = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM webauthn_credentials WHERE user_id = $1 ORDER BY id"
+    ))
+    .bind(user.id)
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(passkeys))
+}
+
+/// Removes a passkey. A stolen session is not enough for that: the user
+/// has to prove a second factor again, like for disabling TOTP, and may not
+/// remove their last way of logging in.
+async fn remove(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    OwnUser(user): OwnUser,
+    Path(id): Path<i64>,
+    payload: Result<Json<SecondFactor>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    let Json(factor) = payload?;
+    totp::check(&pool, &user, &factor).await?;
+    let mut tx = pool.begin().await?;
+    let removed = sqlx::query("DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    if removed.rows_affected() == 0 {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    if !users::can_log_in(&mut tx, user.id).await? {
+        return Err(Forbidden::new("remove_last_passkey".to_string()).into());
+    }
+    tx.commit().await?;
+    audit::record(&pool, Some(user.id), "passkey_removed", &id.to_string()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/register/options", post(registration_options))
+        .route("/register", post(register))
+        .route("/login/options", post(authentication_options))
+        .route("/login", post(login))
+        .route("/passkeys", get(list))
+        .route("/passkeys/{id}", delete(remove))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ceremony::tests::{Authenticator, relying_party};
+
+    #[test]
+    fn browser_serialization() {
+        let rp = relying_party();
+        let mut authenticator = Authenticator::new();
+        let client_data_json = Authenticator::client_data("webauthn.get", "abc", &rp.origin);
+        let (data, signature) = authenticator.get(&rp.id, &client_data_json);
+        let encode = |bytes: &[u8]| BASE64_URL_SAFE_NO_PAD.encode(bytes);
+        let credential: AssertionCredential = serde_json::from_value(json!({
+            "id": encode(&authenticator.credential_id),
+            "rawId": encode(&authenticator.credential_id),
+            "type": "public-key",
+            "authenticatorAttachment": "platform",
+            "clientExtensionResults": {},
+            "response": {
+                "clientDataJSON": encode(&client_data_json),
+                "authenticatorData": encode(&data),
+                "signature": encode(&signature),
+                "userHandle": user_handle(1),
+            },
+        }))
+        .unwrap();
+        assert_eq!(
+            decode("rawId", &credential.raw_id).unwrap(),
+            authenticator.credential_id
+        );
+        assert_eq!(
+            credential.response.user_handle.as_deref(),
+            Some("AAAAAAAAAAE")
+        );
+    }
+}
@@ -0,0 +1,207 @@
+pub mod submissions;
+
+use crate::{
+    api::{
+        ApiError, ApiResult,
+        error::{CourseExists, ResourceNotFound},
+    },
+    audit,
+    auth::rbac::{Authorized, require},
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, Path, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// Roles within a course, from least to most privileged.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "course_role", rename_all = "lowercase")]
+pub enum CourseRole {
+    Student,
+    Ta,
+    Instructor,
+}
+
+#[derive(Clone, Debug, Serialize, FromRow)]
+pub struct Course {
+    pub id: i64,
+    pub slug: String,
+    pub name: String,
+    /// Forgejo organization holding the course's repositories.
+    pub organization: Option<String>,
+}
+
+pub async fn find(pool: &PgPool, slug: &str) -> ApiResult<Option<Course>> {
+    Ok(
+        sqlx::query_as("SELECT id, slug, name, organization FROM courses WHERE slug = $1")
+            .bind(slug)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+pub async fn by_organization(pool: &PgPool, organization: &str) -> ApiResult<Option<Course>> {
+    Ok(
+        sqlx::query_as("SELECT id, slug, name, organization FROM courses WHERE organization = $1")
+            .bind(organization)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+/// Returns the role of a user in a course they are a member of.
+pub async fn role(pool: &PgPool, course_id: i64, user_id: i64) -> ApiResult<Option<CourseRole>> {
+    Ok(
+        sqlx::query_scalar("SELECT role FROM course_members WHERE course_id = $1 AND user_id = $2")
+            .bind(course_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct NewCourse {
+    slug: String,
+    name: String,
+    organization: Option<String>,
+}
+
+async fn create(
+    Extension(pool): Extension<PgPool>,
+    authorized: Authorized<require::CreateCourse>,
+    payload: Result<Json<NewCourse>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(new) = payload?;
+    let mut tx = pool.begin().await?;
+    let course: Course = sqlx::query_as(
+        "INSERT INTO courses (slug, name, organization) VALUES ($1, $2, $3)
+         RETURNING id, slug, name, organization",
+    )
+    .bind(&new.slug)
+    .bind(&new.name)
+    .bind(&new.organization)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| match err.as_database_error() {
+        Some(db) if db.is_unique_violation() => CourseExists::new(new.slug.clone()).into(),
+        _ => ApiError::from(err),
+    })?;
+    // Whoever creates a course teaches it.
+    sqlx::query("INSERT INTO course_members (course_id, user_id, role) VALUES ($1, $2, $3)")
+        .bind(course.id)
+        .bind(authorized.user.id)
+        .bind(CourseRole::Instructor)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    tracing::info!(
+        user = authorized.user.username,
+        course = course.slug,
+        "created course"
+    );
+    Ok((StatusCode::CREATED, Json(course)).into_response())
+}
+
+async fn show(authorized: Authorized<require::ViewCourse>) -> ApiResult<Json<Course>> {
+    let course = authorized
+        .course
+        .expect("course permissions come with a course");
+    Ok(Json(course))
+}
+
+#[derive(Debug, Deserialize)]
+struct Membership {
+    role: CourseRole,
+}
+
+async fn set_member(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageCourse>,
+    Path((_, username)): Path<(String, String)>,
+    payload: Result<Json<Membership>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    let Json(membership) = payload?;
+    let course = authorized
+        .course
+        .expect("course permissions come with a course");
+    // SCIM groups leave enrollments made here alone.
+    let added = sqlx::query(
+        "INSERT INTO course_members (course_id, user_id, role)
+         SELECT $1, id, $3 FROM users WHERE username = $2
+         ON CONFLICT (course_id, user_id) DO UPDATE
+         SET role = EXCLUDED.role, scim_group_id = NULL",
+    )
+    .bind(course.id)
+    .bind(&username)
+    .bind(membership.role)
+    .execute(&pool)
+    .await?;
+    if added.rows_affected() == 0 {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "course_member_set",
+        &format!("{username} is {:?} in {}", membership.role, course.slug),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_member(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageCourse>,
+    Path((_, username)): Path<(String, String)>,
+) -> ApiResult<StatusCode> {
+    let course = authorized
+        .course
+        .expect("course permissions come with a course");
+    let removed = sqlx::query(
+        "DELETE FROM course_members
+         WHERE course_id = $1 AND user_id = (SELECT id FROM users WHERE username = $2)",
+    )
+    .bind(course.id)
+    .bind(&username)
+    .execute(&pool)
+    .await?;
+    if removed.rows_affected() == 0 {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "course_member_removed",
+        &format!("{username} from {}", course.slug),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", post(create))
+        .route("/{course}", get(show))
+        .route(
+            "/{course}/members/{username}",
+            put(set_member).delete(remove_member),
+        )
+        .route("/{course}/submissions", post(submissions::create))
+        .route("/{course}/submissions/{id}", get(submissions::show))
+        .route(
+            "/{course}/submissions/{id}/grading",
+            post(submissions::grade),
+        )
+}
@@ -0,0 +1,351 @@
+//! Work handed in to a course, and its grading.
+//!
+//! A submission is a commit a student pushed to one of the course's
+//! repositories, or opened a pull request there with. To grade one, an
+//! instructor, or a dispatcher using their token, asks for a grading job and
+//! hands its job token to a runner, which reports the result with it; see
+//! [`crate::auth::job`]. Students see the results of their own submissions,
+//! TAs and instructors those of everyone in the course.
+
+use crate::{
+    api::{
+        ApiResult,
+        error::{Forbidden, InvalidGrade, ResourceNotFound},
+    },
+    audit,
+    auth::{
+        job::{self, JobToken},
+        rbac::{self, Authorized, Permission, require},
+    },
+    courses,
+};
+
+use axum::{
+    Extension, Json,
+    extract::{OriginalUri, Path, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+
+/// How long a runner has to report a result unless the job says otherwise.
+pub const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+pub const MAX_JOB_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// The one endpoint a grading job's token is good for.
+pub fn result_path(id: i64) -> String {
+    format!("/api/submissions/{id}/result")
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Submission {
+    id: i64,
+    #[serde(skip)]
+    user_id: i64,
+    username: String,
+    repository: String,
+    sha: String,
+    created_at: i64,
+    score: Option<f64>,
+    max_score: Option<f64>,
+    feedback: Option<String>,
+    graded_at: Option<i64>,
+}
+
+/// Returns submission `id` to course `course_id`.
+async fn find(pool: &PgPool, course_id: i64, id: i64) -> ApiResult<Option<Submission>> {
+    Ok(sqlx::query_as(
+        "SELECT submissions.id, submissions.user_id, users.username, submissions.repository, submissions.sha,
+                extract(epoch FROM submissions.created_at)::BIGINT AS created_at,
+                grading_results.score, grading_results.max_score, grading_results.feedback,
+                extract(epoch FROM grading_results.reported_at)::BIGINT AS graded_at
+         FROM submissions
+         JOIN users ON users.id = submissions.user_id
+         LEFT JOIN grading_results ON grading_results.submission_id = submissions.id
+         WHERE submissions.course_id = $1 AND submissions.id = $2",
+    )
+    .bind(course_id)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewSubmission {
+    repository: String,
+    sha: String,
+}
+
+/// Whether `repository` is one of the course's, which live in its Forgejo
+/// organization.
+fn in_organization(organization: Option<&str>, repository: &str) -> bool {
+    match (organization, repository.split_once('/')) {
+        (Some(organization), Some((owner, name))) => {
+            owner.eq_ignore_ascii_case(organization) && !name.is_empty() && !name.contains('/')
+        }
+        _ => false,
+    }
+}
+
+/// Whether user `user_id` made commit `sha` of `repository` their own, by
+/// pushing it or by opening the pull request it heads, as far as Forgejo's
+/// webhooks told us through their linked Forgejo identity.
+async fn is_own(pool: &PgPool, user_id: i64, repository: &str, sha: &str) -> ApiResult<bool> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS (
+                    SELECT 1 FROM forgejo_pushes
+                    WHERE repository = $1 AND after_sha = $2 AND user_id = $3
+                )
+             OR EXISTS (
+                    SELECT 1 FROM forgejo_pull_requests
+                    WHERE repository = $1 AND head_sha = $2 AND author_user_id = $3
+                )",
+    )
+    .bind(repository)
+    .bind(sha)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Hands in a commit of the course's repositories that the student pushed
+/// or opened a pull request with.
+pub async fn create(
+    Extension(pool): Extension<PgPool>,
+    authorized: Authorized<require::Submit>,
+    payload: Result<Json<NewSubmission>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(new) = payload?;
+    let course = authorized
+        .course
+        .expect("course permissions come with a course");
+    if !in_organization(course.organization.as_deref(), &new.repository)
+        || !is_own(&pool, authorized.user.id, &new.repository, &new.sha).await?
+    {
+        return Err(Forbidden::new("submit_repository".to_string()).into());
+    }
+    let id: i64 = sqlx::query_scalar(
+        "INSERT INTO submissions (course_id, user_id, repository, sha)
+         VALUES ($1, $2, $3, $4)
+         RETURNING id",
+    )
+    .bind(course.id)
+    .bind(authorized.user.id)
+    .bind(&new.repository)
+    .bind(&new.sha)
+    .fetch_one(&pool)
+    .await?;
+    tracing::info!(
+        user = authorized.user.username,
+        course = course.slug,
+        submission = id,
+        "submitted"
+    );
+    let submission = find(&pool, course.id, id)
+        .await?
+        .expect("the submission was just created");
+    Ok((StatusCode::CREATED, Json(submission)).into_response())
+}
+
+pub async fn show(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ViewCourse>,
+    Path((_, id)): Path<(String, i64)>,
+) -> ApiResult<Json<Submission>> {
+    let course = authorized
+        .course
+        .expect("course permissions come with a course");
+    let user = authorized.user;
+    let submission = find(&pool, course.id, id)
+        .await?
+        .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?;
+    // Other students' submissions are as good as absent.
+    if submission.user_id != user.id {
+        let course_role = courses::role(&pool, course.id, user.id).await?;
+        if !rbac::allows(user.role, course_role, Permission::ViewGrades) {
+            return Err(ResourceNotFound::new(uri.to_string()).into());
+        }
+    }
+    Ok(Json(submission))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewJob {
+    /// Seconds the runner may take, at most [`MAX_JOB_TIMEOUT`].
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Job {
+    /// The token for reporting the result, shown only this once.
+    token: String,
+    result_url: String,
+    /// Seconds the token can be used for.
+    expires_in: u64,
+}
+
+/// Starts grading a submission, returning what the runner needs to report
+/// its result.
+pub async fn grade(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageCourse>,
+    Path((_, id)): Path<(String, i64)>,
+    payload: Result<Json<NewJob>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(new) = payload?;
+    let course = authorized
+        .course
+        .expect("course permissions come with a course");
+    if find(&pool, course.id, id).await?.is_none() {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    let expires_in = new
+        .timeout
+        .map_or(DEFAULT_JOB_TIMEOUT, Duration::from_secs)
+        .min(MAX_JOB_TIMEOUT)
+        .as_secs();
+    let path = result_path(id);
+    let token = job::issue(&path, job::now() + expires_in)?;
+    let base_url = std::env::var("BASE_URL")?;
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "grading_started",
+        &format!("submission {id} in {}", course.slug),
+    )
+    .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(Job {
+            token,
+            result_url: format!("{}{path}", base_url.trim_end_matches('/')),
+            expires_in,
+        }),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Grade {
+    score: f64,
+    max_score: f64,
+    feedback: Option<String>,
+}
+
+impl Grade {
+    fn check(&self) -> Result<(), String> {
+        if !(self.max_score.is_finite() && self.max_score > 0.0) {
+            return Err("max_score must be positive".to_string());
+        }
+        if !(0.0..=self.max_score).contains(&self.score) {
+            return Err("score must be between 0 and max_score".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Where a runner reports the result of a grading job. A runner may report
+/// again, say after a retry, until its token expires.
+pub async fn report(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    _: JobToken,
+    Path(id): Path<i64>,
+    payload: Result<Json<Grade>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    let Json(grade) = payload?;
+    grade.check().map_err(InvalidGrade::new)?;
+    let reported = sqlx::query(
+        "INSERT INTO grading_results (submission_id, score, max_score, feedback)
+         SELECT id, $2, $3, $4 FROM submissions WHERE id = $1
+         ON CONFLICT (submission_id) DO UPDATE
+         SET score = EXCLUDED.score,
+             max_score = EXCLUDED.max_score,
+             feedback = EXCLUDED.feedback,
+             reported_at = now()",
+    )
+    .bind(id)
+    .bind(grade.score)
+    .bind(grade.max_score)
+    .bind(&grade.feedback)
+    .execute(&pool)
+    .await?;
+    if reported.rows_affected() == 0 {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    tracing::info!(submission = id, score = grade.score, "graded submission");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grade(score: f64, max_score: f64) -> Grade {
+        Grade {
+            score,
+            max_score,
+            feedback: None,
+        }
+    }
+
+    #[test]
+    fn grades() {
+        grade(0.0, 10.0).check().unwrap();
+        grade(10.0, 10.0).check().unwrap();
+        assert!(grade(10.5, 10.0).check().is_err());
+        assert!(grade(-1.0, 10.0).check().is_err());
+        assert!(grade(f64::NAN, 10.0).check().is_err());
+        assert!(grade(0.0, 0.0).check().is_err());
+        assert!(grade(1.0, f64::INFINITY).check().is_err());
+        assert_eq!(result_path(42), "/api/submissions/42/result");
+    }
+
+    #[test]
+    fn course_repositories() {
+        assert!(in_organization(Some("algo-2026"), "algo-2026/jdoe"));
+        assert!(in_organization(Some("Algo-2026"), "algo-2026/jdoe"));
+        assert!(!in_organization(Some("algo-2026"), "jdoe/algo-2026"));
+        assert!(!in_organization(Some("algo-2026"), "algo-2026-old/jdoe"));
+        assert!(!in_organization(Some("algo-2026"), "algo-2026/"));
+        assert!(!in_organization(Some("algo-2026"), "algo-2026"));
+        assert!(!in_organization(None, "algo-2026/jdoe"));
+    }
+
+    #[test]
+    fn instructor_tokens_grade() {
+        use crate::{
+            auth::{
+                rbac::RequiredPermission,
+                token::{Grant, Scope},
+            },
+            courses::CourseRole,
+            users::{Role, User},
+        };
+
+        // What `grade` demands of a token, an instructor may put into one.
+        let permission = <require::ManageCourse as RequiredPermission>::PERMISSION;
+        assert!(permission.scope().mintable_by(Role::Instructor));
+        let grant = Grant {
+            token_id: 1,
+            user: User {
+                id: 1,
+                username: "jdoe".to_string(),
+                email: None,
+                display_name: None,
+                role: Role::Instructor,
+            },
+            scopes: vec![Scope::ManageCourses],
+        };
+        assert!(grant.allows(permission.scope()));
+        assert!(rbac::allows(
+            grant.user.role,
+            Some(CourseRole::Instructor),
+            permission
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! Forgejo Actions results.
+//!
+//! Forgejo Actions reports every job of a workflow run as a commit status whose
+//! context names the workflow and job and whose target URL points at the job
+//! log. Statuses arrive through `status` webhook events and, when an API token
+//! is configured, are also polled for the head commit of every push.
+
+use crate::{
+    api::ApiResult,
+    auth::rbac::{Authorized, require},
+    forgejo::{Repository, User, client::Client, review},
+};
+
+use axum::{Extension, Json, extract::Path};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const POLL_ATTEMPTS: usize = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct CommitStatus {
+    context: String,
+    state: String,
+    description: Option<String>,
+    target_url: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct Status {
+    pub sha: String,
+    #[serde(flatten)]
+    status: CommitStatus,
+    pub repository: Repository,
+    sender: Option<User>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CombinedStatus {
+    state: String,
+    statuses: Vec<CommitStatus>,
+}
+
+impl CombinedStatus {
+    /// Whether no job of the commit is still pending.
+    fn finished(&self) -> bool {
+        self.state != "pending"
+    }
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Job {
+    pub context: String,
+    pub state: String,
+    pub description: Option<String>,
+    pub target_url: Option<String>,
+}
+
+async fn record(
+    pool: &PgPool,
+    repository: &str,
+    sha: &str,
+    status: &CommitStatus,
+) -> ApiResult<()> {
+    sqlx::query(
+        "INSERT INTO forgejo_action_jobs (repository, sha, context, state, description, target_url)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (repository, sha, context) DO UPDATE
+         SET state = EXCLUDED.state,
+             description = EXCLUDED.description,
+             target_url = EXCLUDED.target_url,
+             updated_at = now()",
+    )
+    .bind(repository)
+    .bind(sha)
+    .bind(&status.context)
+    .bind(&status.state)
+    .bind(&status.description)
+    .bind(&status.target_url)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn handle_status(pool: &PgPool, status: &Status) -> ApiResult<()> {
+    record(
+        pool,
+        &status.repository.full_name,
+        &status.sha,
+        &status.status,
+    )
+    .await
+}
+
+async fn poll_once(pool: &PgPool, client: &Client, repository: &str, sha: &str) -> ApiResult<bool> {
+    let combined: CombinedStatus = client
+        .get(&format!("/repos/{repository}/commits/{sha}/status"))
+        .await?;
+    for status in &combined.statuses {
+        record(pool, repository, sha, status).await?;
+    }
+    Ok(combined.finished())
+}
+
+/// Polls the combined commit status of `sha` until no job is pending.
+pub async fn poll(pool: PgPool, client: Client, repository: String, sha: String) {
+    for _ in 0..POLL_ATTEMPTS {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        match poll_once(&pool, &client, &repository, &sha).await {
+            Ok(true) => {
+                if let Err(err) = review::review_commit(&pool, &client, &repository, &sha).await {
+                    tracing::warn!("reviewing {repository}@{sha}: {err}");
+                }
+                return;
+            }
+            Ok(false) => (),
+            Err(err) => tracing::warn!("polling actions for {repository}@{sha}: {err}"),
+        }
+    }
+}
+
+pub async fn jobs_for(pool: &PgPool, repository: &str, sha: &str) -> ApiResult<Vec<Job>> {
+    let jobs = sqlx::query_as(
+        "SELECT context, state, description, target_url
+         FROM forgejo_action_jobs
+         WHERE repository = $1 AND sha = $2
+         ORDER BY context",
+    )
+    .bind(repository)
+    .bind(sha)
+    .fetch_all(pool)
+    .await?;
+    Ok(jobs)
+}
+
+/// Returns the jobs run for commit `sha`, to the course staff: the logs they
+/// link to are the work of whoever owns the repository.
+pub async fn jobs(
+    Extension(pool): Extension<PgPool>,
+    _: Authorized<require::ViewGrades>,
+    Path((owner, repo, sha)): Path<(String, String, String)>,
+) -> ApiResult<Json<Vec<Job>>> {
+    let jobs = jobs_for(&pool, &format!("{owner}/{repo}"), &sha).await?;
+    Ok(Json(jobs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_statuses() {
+        let status: Status = serde_json::from_str(
+            r#"{
+                "id": 12,
+                "sha": "4e3f0c1a",
+                "context": "ci / test (push)",
+                "state": "failure",
+                "description": "Failing after 42s",
+                "target_url": "https://forgejo.example/org/repo/actions/runs/7/jobs/1",
+                "repository": {
+                    "name": "repo",
+                    "full_name": "org/repo",
+                    "owner": {"id": 3, "username": "org"},
+                    "description": null
+                },
+                "sender": {"id": 5, "username": "jdoe"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(status.sha, "4e3f0c1a");
+        assert_eq!(status.repository.full_name, "org/repo");
+        assert_eq!(status.status.context, "ci / test (push)");
+        assert_eq!(status.status.state, "failure");
+        assert_eq!(
+            status.status.description.as_deref(),
+            Some("Failing after 42s")
+        );
+        assert!(status.status.target_url.unwrap().ends_with("/jobs/1"));
+
+        let combined: CombinedStatus = serde_json::from_str(
+            r#"{
+                "state": "pending",
+                "sha": "4e3f0c1a",
+                "statuses": [
+                    {"context": "ci / lint (push)", "state": "success", "target_url": null},
+                    {"context": "ci / test (push)", "state": "pending"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(!combined.finished());
+        assert_eq!(combined.statuses.len(), 2);
+        assert_eq!(combined.statuses[0].state, "success");
+        assert_eq!(combined.statuses[1].description, None);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::api::{ApiError, ApiResult, error::InternalError};
+
+use serde::{Serialize, de::DeserializeOwned};
+use url::Url;
+
+/// Token-authenticated client for the Forgejo REST API.
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl Client {
+    pub fn new(base_url: &str, token: &str) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Builds a client from `FORGEJO_URL` and `FORGEJO_TOKEN`.
+    pub fn from_env() -> ApiResult<Self> {
+        let base_url = std::env::var("FORGEJO_URL")?;
+        let token = std::env::var("FORGEJO_TOKEN")?;
+        Ok(Client::new(&base_url, &token))
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}/api/v1{}", self.base_url, path))
+            .header("authorization", format!("token {}", self.token))
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> ApiResult<T> {
+        let response = self
+            .request(reqwest::Method::GET, path)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// Whether `url` is on this Forgejo instance, so that it may see the token.
+    pub fn is_own(&self, url: &str) -> bool {
+        match (Url::parse(url), Url::parse(&self.base_url)) {
+            (Ok(url), Ok(base_url)) => url.origin() == base_url.origin(),
+            _ => false,
+        }
+    }
+
+    /// Fetches a plain text page of the Forgejo web interface, like a job
+    /// log. URLs elsewhere are refused, as they would receive the token.
+    pub async fn get_text(&self, url: &str) -> ApiResult<String> {
+        if !self.is_own(url) {
+            return Err(ApiError::InternalError(InternalError::new(
+                format!("{url} is not on Forgejo").into(),
+            )));
+        }
+        let response = self
+            .http
+            .get(url)
+            .header("authorization", format!("token {}", self.token))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.text().await?)
+    }
+
+    pub async fn send<T: DeserializeOwned, B: Serialize>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: &B,
+    ) -> ApiResult<T> {
+        let response = self
+            .request(method, path)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn post<T: DeserializeOwned, B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> ApiResult<T> {
+        self.send(reqwest::Method::POST, path, body).await
+    }
+
+    pub async fn patch<T: DeserializeOwned, B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> ApiResult<T> {
+        self.send(reqwest::Method::PATCH, path, body).await
+    }
+
+    pub async fn delete(&self, path: &str) -> ApiResult<()> {
+        self.request(reqwest::Method::DELETE, path)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn own_urls() {
+        let client = Client::new("https://forgejo.example/", "secret");
+        assert!(client.is_own("https://forgejo.example/org/repo/actions/runs/1/jobs/0"));
+        assert!(client.is_own("https://forgejo.example:443/logs"));
+        assert!(!client.is_own("http://forgejo.example/logs"));
+        assert!(!client.is_own("https://forgejo.example.evil/logs"));
+        assert!(!client.is_own("https://forgejo.example:8443/logs"));
+        assert!(!client.is_own("/org/repo/actions/runs/1"));
+    }
+
+    #[tokio::test]
+    async fn refuses_foreign_urls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/logs", listener.local_addr().unwrap());
+        let client = Client::new("https://forgejo.example", "secret");
+        assert!(client.get_text(&url).await.is_err());
+        let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(accepted.is_err(), "the token was sent to {url}");
+    }
+}
@@ -1,15 +1,30 @@
-use crate::api::{
-    ApiResult,
-    error::{
-        MismatchedSignature, UnsupportedMediaType, UnsupportedUserAgent, UnsupportedWebhookEvent,
+mod actions;
+mod client;
+pub mod peer_review;
+mod review;
+
+use crate::{
+    api::{
+        ApiResult,
+        error::{
+            MismatchedSignature, UnsupportedMediaType, UnsupportedUserAgent,
+            UnsupportedWebhookEvent,
+        },
+        header_get_required,
     },
-    header_get_required,
+    users::{self, identities},
 };
 
-use axum::{Json, Router, body::Bytes, http::HeaderMap, routing::post};
+use axum::{
+    Extension, Json, Router,
+    body::Bytes,
+    http::HeaderMap,
+    routing::{get, post},
+};
 use hmac::{Hmac, Mac};
 use serde::Deserialize;
 use sha2::Sha256;
+use sqlx::PgPool;
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
@@ -95,6 +110,44 @@ struct Push {
     organization: Option<Organization>,
 }
 
+impl Push {
+    /// Whether the push deleted its ref, leaving `after` all zeros.
+    fn is_deletion(&self) -> bool {
+        self.deleted == Some(true) || self.after.bytes().all(|b| b == b'0')
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct Branch {
+    label: String,
+    r#ref: String,
+    sha: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    id: i64,
+    number: i64,
+    title: String,
+    state: String,
+    user: User,
+    head: Branch,
+    base: Branch,
+    html_url: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct PullRequestEvent {
+    action: String,
+    number: i64,
+    pull_request: PullRequest,
+    repository: Repository,
+    sender: Option<User>,
+}
+
 fn hex_digest(secret: &str, bytes: &[u8]) -> ApiResult<String> {
     let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
     mac.update(bytes);
@@ -132,20 +185,124 @@ fn check_signature(signature: &str, bytes: &[u8]) -> ApiResult<()> {
     }
 }
 
-fn handle_event(event: &str, bytes: &Bytes) -> ApiResult<()> {
+fn spawn_review(pool: &PgPool, repository: String, sha: String) {
+    if let Ok(client) = client::Client::from_env() {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(err) = review::review_commit(&pool, &client, &repository, &sha).await {
+                tracing::warn!("reviewing {repository}@{sha}: {err}");
+            }
+        });
+    }
+}
+
+/// Returns the user a Forgejo user is linked to, if any.
+async fn resolve(pool: &PgPool, user: &User) -> ApiResult<Option<users::User>> {
+    let resolved = identities::forgejo_user(pool, user.id).await?;
+    if resolved.is_none() {
+        tracing::debug!(
+            forgejo_user = user.username,
+            "Forgejo user is not linked to anyone"
+        );
+    }
+    Ok(resolved)
+}
+
+async fn handle_push(pool: &PgPool, push: &Push) -> ApiResult<()> {
+    let user = resolve(pool, &push.pusher).await?;
+    sqlx::query(
+        "INSERT INTO forgejo_pushes
+             (repository, ref, before_sha, after_sha, pusher_id, pusher_username, user_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&push.repository.full_name)
+    .bind(&push.r#ref)
+    .bind(&push.before)
+    .bind(&push.after)
+    .bind(push.pusher.id)
+    .bind(&push.pusher.username)
+    .bind(user.map(|user| user.id))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn handle_pull_request(pool: &PgPool, event: PullRequestEvent) -> ApiResult<()> {
+    let author = resolve(pool, &event.pull_request.user).await?;
+    sqlx::query(
+        "INSERT INTO forgejo_pull_requests
+             (repository, number, head_sha, state, author_id, author_username, author_user_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (repository, number) DO UPDATE
+         SET head_sha = EXCLUDED.head_sha, state = EXCLUDED.state,
+             author_user_id = EXCLUDED.author_user_id",
+    )
+    .bind(&event.repository.full_name)
+    .bind(event.pull_request.number)
+    .bind(&event.pull_request.head.sha)
+    .bind(&event.pull_request.state)
+    .bind(event.pull_request.user.id)
+    .bind(&event.pull_request.user.username)
+    .bind(author.map(|author| author.id))
+    .execute(pool)
+    .await?;
+
+    if event.pull_request.state == "open" {
+        spawn_review(
+            pool,
+            event.repository.full_name,
+            event.pull_request.head.sha,
+        );
+    }
+    Ok(())
+}
+
+async fn handle_event(pool: &PgPool, event: &str, bytes: &Bytes) -> ApiResult<()> {
     match event {
         "push" => {
-            let Json(_push): Json<Push> = Json::from_bytes(&bytes)?;
+            let Json(push): Json<Push> = Json::from_bytes(bytes)?;
+            handle_push(pool, &push).await?;
+            // A deleted branch points at no commit with jobs to wait for.
+            if push.is_deletion() {
+                return Ok(());
+            }
+            if let Ok(client) = client::Client::from_env() {
+                tokio::spawn(actions::poll(
+                    pool.clone(),
+                    client,
+                    push.repository.full_name,
+                    push.after,
+                ));
+            }
         }
         "membership" => {
-            let Json(_membership): Json<Membership> = Json::from_bytes(&bytes)?;
+            let Json(_membership): Json<Membership> = Json::from_bytes(bytes)?;
+        }
+        "pull_request" => {
+            let Json(event): Json<PullRequestEvent> = Json::from_bytes(bytes)?;
+            handle_pull_request(pool, event).await?;
+        }
+        "pull_request_review_approved"
+        | "pull_request_review_rejected"
+        | "pull_request_review_comment" => {
+            let Json(event): Json<PullRequestEvent> = Json::from_bytes(bytes)?;
+            peer_review::handle_review(pool, &event).await?;
+        }
+        "status" => {
+            let Json(status): Json<actions::Status> = Json::from_bytes(bytes)?;
+            actions::handle_status(pool, &status).await?;
+            spawn_review(pool, status.repository.full_name, status.sha);
         }
         _ => return Err(UnsupportedWebhookEvent::new(event.to_string()).into()),
     }
     Ok(())
 }
 
-async fn webhook_handler(headers: HeaderMap, bytes: Bytes) -> ApiResult<()> {
+async fn webhook_handler(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    bytes: Bytes,
+) -> ApiResult<()> {
     let content_type = header_get_required(&headers, "content-type")?;
     let event = header_get_required(&headers, "x-forgejo-event")?;
     let _delivery = header_get_required(&headers, "x-forgejo-delivery")?;
@@ -156,9 +313,18 @@ async fn webhook_handler(headers: HeaderMap, bytes: Bytes) -> ApiResult<()> {
     check_user_agent(user_agent)?;
     check_signature(signature, &bytes)?;
 
-    handle_event(event, &bytes)
+    handle_event(&pool, event, &bytes).await
 }
 
 pub fn routes() -> Router {
-    Router::new().route("/webhook", post(webhook_handler))
+    Router::new()
+        .route("/webhook", post(webhook_handler))
+        .route(
+            "/repos/{owner}/{repo}/commits/{sha}/jobs",
+            get(actions::jobs),
+        )
+        .route(
+            "/repos/{owner}/{repo}/peer-reviews",
+            get(peer_review::participation),
+        )
 }
@@ -0,0 +1,334 @@
+//! Peer review of assignment pull requests.
+//!
+//! An assignment is a Forgejo repository that students open pull requests
+//! against. Once the deadline of a peer review round has passed, every author
+//! is asked to review the pull requests of `reviewers` other authors. The
+//! authors are shuffled into a ring and each one reviews the next ones, so
+//! nobody reviews their own work and every submission gets the same number of
+//! reviewers.
+//!
+//! Named rounds request the reviewers on the pull request itself, retrying
+//! pull requests for which that failed until it succeeds. Anonymous rounds
+//! only record the assignment in CeresForge, so authors do not learn who was
+//! asked to review them; reviewers find theirs at `/api/user/peer-reviews`.
+//! Forgejo still attributes submitted reviews.
+
+use crate::{
+    api::ApiResult,
+    auth::{
+        rbac::{Authorized, require},
+        token::{self, Scoped},
+    },
+    forgejo::{PullRequestEvent, client::Client},
+};
+
+use axum::{Extension, Json, extract::Path};
+use rand::seq::SliceRandom;
+use serde::{Serialize, de::IgnoredAny};
+use sqlx::PgPool;
+use std::time::Duration;
+
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, sqlx::FromRow)]
+struct Round {
+    id: i64,
+    repository: String,
+    reviewers: i32,
+    anonymous: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Submission {
+    number: i64,
+    author_id: i64,
+    author_username: String,
+}
+
+/// Reviewers of a pull request who have not been requested on it yet.
+#[derive(Debug, sqlx::FromRow)]
+struct Unrequested {
+    round_id: i64,
+    repository: String,
+    number: i64,
+    reviewers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestedReviewers<'a> {
+    reviewers: &'a [String],
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Assignment {
+    repository: String,
+    number: i64,
+    completed: bool,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Participation {
+    reviewer: String,
+    assigned: i64,
+    completed: i64,
+    score: f64,
+}
+
+pub async fn create_round(
+    pool: &PgPool,
+    repository: &str,
+    deadline: &str,
+    reviewers: i32,
+    anonymous: bool,
+) -> ApiResult<i64> {
+    let id = sqlx::query_scalar(
+        "INSERT INTO peer_review_rounds (repository, deadline, reviewers, anonymous)
+         VALUES ($1, $2::timestamptz, $3, $4)
+         RETURNING id",
+    )
+    .bind(repository)
+    .bind(deadline)
+    .bind(reviewers)
+    .bind(anonymous)
+    .fetch_one(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Pairs every submission with the submissions of the next `reviewers` authors.
+fn pair(submissions: &[Submission], reviewers: usize) -> Vec<(&Submission, &Submission)> {
+    let reviewers = reviewers.min(submissions.len().saturating_sub(1));
+    let mut pairs = Vec::with_capacity(submissions.len() * reviewers);
+    for (i, reviewer) in submissions.iter().enumerate() {
+        for offset in 1..=reviewers {
+            pairs.push((reviewer, &submissions[(i + offset) % submissions.len()]));
+        }
+    }
+    pairs
+}
+
+async fn assign(pool: &PgPool, round: &Round) -> ApiResult<()> {
+    let mut submissions: Vec<Submission> = sqlx::query_as(
+        "SELECT DISTINCT ON (author_id) number, author_id, author_username
+         FROM forgejo_pull_requests
+         WHERE repository = $1 AND state = 'open' AND author_id IS NOT NULL
+         ORDER BY author_id, number DESC",
+    )
+    .bind(&round.repository)
+    .fetch_all(pool)
+    .await?;
+    submissions.shuffle(&mut rand::rng());
+    let pairs = pair(&submissions, round.reviewers.max(0) as usize);
+
+    let mut tx = pool.begin().await?;
+    for (reviewer, submission) in &pairs {
+        sqlx::query(
+            "INSERT INTO peer_reviews (round_id, number, reviewer_id, reviewer_username, notified_at)
+             VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN now() END)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(round.id)
+        .bind(submission.number)
+        .bind(reviewer.author_id)
+        .bind(&reviewer.author_username)
+        .bind(round.anonymous)
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query("UPDATE peer_review_rounds SET assigned_at = now() WHERE id = $1")
+        .bind(round.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Requests the reviewers of one pull request on it and records that.
+async fn request(pool: &PgPool, client: &Client, unrequested: &Unrequested) -> ApiResult<()> {
+    let _: IgnoredAny = client
+        .post(
+            &format!(
+                "/repos/{}/pulls/{}/requested_reviewers",
+                unrequested.repository, unrequested.number
+            ),
+            &RequestedReviewers {
+                reviewers: &unrequested.reviewers,
+            },
+        )
+        .await?;
+    sqlx::query(
+        "UPDATE peer_reviews SET notified_at = now()
+         WHERE round_id = $1 AND number = $2 AND reviewer_username = ANY($3)",
+    )
+    .bind(unrequested.round_id)
+    .bind(unrequested.number)
+    .bind(&unrequested.reviewers)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Requests the reviewers of named rounds on every pull request where that
+/// has not happened yet.
+async fn request_all(pool: &PgPool, client: &Client) -> ApiResult<()> {
+    let pending: Vec<Unrequested> = sqlx::query_as(
+        "SELECT peer_reviews.round_id, peer_review_rounds.repository, peer_reviews.number,
+                array_agg(peer_reviews.reviewer_username ORDER BY peer_reviews.reviewer_username)
+                    AS reviewers
+         FROM peer_reviews
+         JOIN peer_review_rounds ON peer_review_rounds.id = peer_reviews.round_id
+         WHERE peer_reviews.notified_at IS NULL AND NOT peer_review_rounds.anonymous
+         GROUP BY peer_reviews.round_id, peer_review_rounds.repository, peer_reviews.number",
+    )
+    .fetch_all(pool)
+    .await?;
+    for unrequested in &pending {
+        if let Err(err) = request(pool, client, unrequested).await {
+            tracing::warn!(
+                "requesting reviewers on {}#{}: {err}",
+                unrequested.repository,
+                unrequested.number
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Assigns reviewers for every round whose deadline has passed, and
+/// requests them on the pull requests of named rounds.
+pub async fn schedule(pool: PgPool) {
+    loop {
+        let rounds: Result<Vec<Round>, sqlx::Error> = sqlx::query_as(
+            "SELECT id, repository, reviewers, anonymous
+             FROM peer_review_rounds
+             WHERE assigned_at IS NULL AND deadline <= now()",
+        )
+        .fetch_all(&pool)
+        .await;
+        match rounds {
+            Ok(rounds) => {
+                for round in &rounds {
+                    if let Err(err) = assign(&pool, round).await {
+                        tracing::warn!("assigning peer review round {}: {err}", round.id);
+                    }
+                }
+            }
+            Err(err) => tracing::warn!("loading peer review rounds: {err}"),
+        }
+        if let Ok(client) = Client::from_env() {
+            if let Err(err) = request_all(&pool, &client).await {
+                tracing::warn!("requesting peer reviewers: {err}");
+            }
+        }
+        tokio::time::sleep(SCHEDULE_INTERVAL).await;
+    }
+}
+
+/// Marks the review of `event.sender` on the reviewed pull request as done.
+pub(super) async fn handle_review(pool: &PgPool, event: &PullRequestEvent) -> ApiResult<()> {
+    let Some(sender) = &event.sender else {
+        return Ok(());
+    };
+    sqlx::query(
+        "UPDATE peer_reviews SET completed_at = now()
+         WHERE completed_at IS NULL
+           AND reviewer_id = $1
+           AND number = $2
+           AND round_id IN (SELECT id FROM peer_review_rounds WHERE repository = $3)",
+    )
+    .bind(sender.id)
+    .bind(event.pull_request.number)
+    .bind(&event.repository.full_name)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the pull requests the current user was asked to review, through
+/// their linked Forgejo identity.
+pub async fn assigned(
+    Extension(pool): Extension<PgPool>,
+    Scoped(user, _): Scoped<token::require::ReadCourses>,
+) -> ApiResult<Json<Vec<Assignment>>> {
+    let assignments = sqlx::query_as(
+        "SELECT peer_review_rounds.repository, peer_reviews.number,
+                peer_reviews.completed_at IS NOT NULL AS completed
+         FROM peer_reviews
+         JOIN peer_review_rounds ON peer_review_rounds.id = peer_reviews.round_id
+         JOIN identities ON identities.kind = 'forgejo'
+                        AND identities.subject = peer_reviews.reviewer_id::TEXT
+         WHERE identities.user_id = $1
+         ORDER BY peer_review_rounds.deadline DESC, peer_reviews.number",
+    )
+    .bind(user.id)
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(assignments))
+}
+
+pub async fn participation(
+    Extension(pool): Extension<PgPool>,
+    _: Authorized<require::ViewGrades>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> ApiResult<Json<Vec<Participation>>> {
+    let participation = sqlx::query_as(
+        "SELECT reviewer_username AS reviewer,
+                count(*) AS assigned,
+                count(completed_at) AS completed,
+                count(completed_at)::float8 / count(*) AS score
+         FROM peer_reviews
+         JOIN peer_review_rounds ON peer_review_rounds.id = peer_reviews.round_id
+         WHERE peer_review_rounds.repository = $1
+         GROUP BY reviewer_username
+         ORDER BY reviewer_username",
+    )
+    .bind(format!("{owner}/{repo}"))
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(participation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn submissions(count: i64) -> Vec<Submission> {
+        (1..=count)
+            .map(|id| Submission {
+                number: id * 10,
+                author_id: id,
+                author_username: format!("student{id}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pairs_in_a_ring() {
+        let submissions = submissions(5);
+        let pairs = pair(&submissions, 2);
+        assert_eq!(pairs.len(), 10);
+        let mut given = HashMap::new();
+        let mut received = HashMap::new();
+        for (reviewer, reviewed) in &pairs {
+            assert_ne!(reviewer.author_id, reviewed.author_id);
+            *given.entry(reviewer.author_id).or_insert(0) += 1;
+            *received.entry(reviewed.number).or_insert(0) += 1;
+        }
+        assert!(given.values().all(|&count| count == 2));
+        assert!(received.values().all(|&count| count == 2));
+        assert_eq!(given.len(), 5);
+        assert_eq!(received.len(), 5);
+    }
+
+    #[test]
+    fn pairs_at_most_everyone_else() {
+        let submissions = submissions(3);
+        let pairs = pair(&submissions, 3);
+        assert_eq!(pairs.len(), 6);
+        assert!(pairs.iter().all(|(a, b)| a.author_id != b.author_id));
+        assert_eq!(pair(&submissions, 10).len(), 6);
+        assert!(pair(&submissions[..1], 2).is_empty());
+        assert!(pair(&[], 2).is_empty());
+    }
+}
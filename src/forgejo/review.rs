@@ -0,0 +1,364 @@
+//! Automated feedback on Forgejo pull requests.
+//!
+//! Every analysed pull request gets exactly one summary comment from
+//! CeresForge, which is edited in place on later pushes. Line-level findings
+//! come from the annotations jobs print to their logs, like
+//! `::error file=src/lib.rs,line=3::message`, and are posted as a review on
+//! the analysed commit. Forgejo cannot dismiss comment reviews, so earlier
+//! CeresForge reviews are deleted and only the latest one stays.
+
+use crate::{
+    api::ApiResult,
+    forgejo::{User, actions::Job, client::Client},
+};
+
+use serde::{Deserialize, Serialize, de::IgnoredAny};
+use sqlx::PgPool;
+
+const MARKER: &str = "<!-- ceresforge -->";
+
+/// At most this many findings are posted, so a noisy linter cannot bury the
+/// pull request.
+const MAX_FINDINGS: usize = 50;
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub path: String,
+    pub line: u64,
+    pub body: String,
+}
+
+/// Reverses the percent-escaping of workflow command values.
+fn unescape(value: &str) -> String {
+    value
+        .replace("%0A", "\n")
+        .replace("%0D", "\r")
+        .replace("%3A", ":")
+        .replace("%2C", ",")
+        .replace("%25", "%")
+}
+
+impl Finding {
+    /// Parses a log line holding an `::error`, `::warning` or `::notice`
+    /// annotation with a file and line, printed by job `context`.
+    pub fn parse(context: &str, line: &str) -> Option<Self> {
+        let command = &line[line.find("::")? + 2..];
+        let (level, rest) = command.split_once(' ')?;
+        if !matches!(level, "error" | "warning" | "notice") {
+            return None;
+        }
+        let (properties, message) = rest.split_once("::")?;
+        let mut path = None;
+        let mut number = None;
+        for property in properties.split(',') {
+            match property.trim().split_once('=')? {
+                ("file", value) => path = Some(unescape(value)),
+                ("line", value) => number = value.parse().ok(),
+                _ => (),
+            }
+        }
+        Some(Finding {
+            path: path?,
+            line: number?,
+            body: format!("**{context}** {level}: {}", unescape(message.trim_end())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+    pub summary: String,
+    pub findings: Vec<Finding>,
+}
+
+impl Analysis {
+    /// Summarizes `jobs`, with findings from the annotations in `logs`, each
+    /// a job context and its log.
+    pub fn from_jobs(jobs: &[Job], logs: &[(&str, String)]) -> Self {
+        let passed = jobs.iter().filter(|job| job.state == "success").count();
+        let mut summary = format!(
+            "**CeresForge**: {passed} of {} jobs passed.\n\n| Job | Result |\n| --- | --- |\n",
+            jobs.len()
+        );
+        for job in jobs {
+            let name = match &job.target_url {
+                Some(url) => format!("[{}]({url})", job.context),
+                None => job.context.clone(),
+            };
+            summary.push_str(&format!("| {name} | {} |\n", job.state));
+        }
+        let findings = logs
+            .iter()
+            .flat_map(|(context, log)| log.lines().filter_map(|line| Finding::parse(context, line)))
+            .take(MAX_FINDINGS)
+            .collect();
+        Analysis { summary, findings }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Comment {
+    id: i64,
+    body: String,
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct Review {
+    id: i64,
+    body: String,
+    user: User,
+}
+
+#[derive(Debug, Serialize)]
+struct CommentBody<'a> {
+    body: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ReviewComment<'a> {
+    path: &'a str,
+    body: &'a str,
+    new_position: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateReview<'a> {
+    body: &'a str,
+    commit_id: &'a str,
+    event: &'a str,
+    comments: Vec<ReviewComment<'a>>,
+}
+
+/// Posts `analysis` of commit `sha` to pull request `number`.
+pub async fn publish(
+    client: &Client,
+    repository: &str,
+    number: i64,
+    sha: &str,
+    analysis: &Analysis,
+) -> ApiResult<()> {
+    let me: User = client.get("/user").await?;
+    let body = format!("{MARKER}\n{}", analysis.summary);
+
+    let comments: Vec<Comment> = client
+        .get(&format!("/repos/{repository}/issues/{number}/comments"))
+        .await?;
+    let previous = comments
+        .iter()
+        .find(|comment| comment.user.id == me.id && comment.body.starts_with(MARKER));
+    let _: IgnoredAny = match previous {
+        Some(comment) => {
+            client
+                .patch(
+                    &format!("/repos/{repository}/issues/comments/{}", comment.id),
+                    &CommentBody { body: &body },
+                )
+                .await?
+        }
+        None => {
+            client
+                .post(
+                    &format!("/repos/{repository}/issues/{number}/comments"),
+                    &CommentBody { body: &body },
+                )
+                .await?
+        }
+    };
+
+    // Findings of earlier commits go even if this one has none.
+    let reviews: Vec<Review> = client
+        .get(&format!("/repos/{repository}/pulls/{number}/reviews"))
+        .await?;
+    for review in reviews
+        .iter()
+        .filter(|review| review.user.id == me.id && review.body.starts_with(MARKER))
+    {
+        client
+            .delete(&format!(
+                "/repos/{repository}/pulls/{number}/reviews/{}",
+                review.id
+            ))
+            .await?;
+    }
+
+    if analysis.findings.is_empty() {
+        return Ok(());
+    }
+
+    let review = CreateReview {
+        body: &format!(
+            "{MARKER}\nCeresForge found {} issues.",
+            analysis.findings.len()
+        ),
+        commit_id: sha,
+        event: "COMMENT",
+        comments: analysis
+            .findings
+            .iter()
+            .map(|finding| ReviewComment {
+                path: &finding.path,
+                body: &finding.body,
+                new_position: finding.line,
+            })
+            .collect(),
+    };
+    let _: IgnoredAny = client
+        .post(
+            &format!("/repos/{repository}/pulls/{number}/reviews"),
+            &review,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Claims publishing the analysis of `sha` on pull request `number`.
+/// Returns false if it has been claimed before, by this or a concurrent run.
+async fn claim(pool: &PgPool, repository: &str, number: i64, sha: &str) -> ApiResult<bool> {
+    let claimed = sqlx::query(
+        "INSERT INTO forgejo_reviews (repository, number, sha) VALUES ($1, $2, $3)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(repository)
+    .bind(number)
+    .bind(sha)
+    .execute(pool)
+    .await?;
+    Ok(claimed.rows_affected() > 0)
+}
+
+async fn release(pool: &PgPool, repository: &str, number: i64, sha: &str) -> ApiResult<()> {
+    sqlx::query("DELETE FROM forgejo_reviews WHERE repository = $1 AND number = $2 AND sha = $3")
+        .bind(repository)
+        .bind(number)
+        .bind(sha)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Publishes the Actions results of `sha` on the open pull requests it
+/// heads once every job has finished, and only once on each.
+pub async fn review_commit(
+    pool: &PgPool,
+    client: &Client,
+    repository: &str,
+    sha: &str,
+) -> ApiResult<()> {
+    let jobs = crate::forgejo::actions::jobs_for(pool, repository, sha).await?;
+    if jobs.is_empty() || jobs.iter().any(|job| job.state == "pending") {
+        return Ok(());
+    }
+
+    let numbers: Vec<i64> = sqlx::query_scalar(
+        "SELECT number FROM forgejo_pull_requests
+         WHERE repository = $1 AND head_sha = $2 AND state = 'open'",
+    )
+    .bind(repository)
+    .bind(sha)
+    .fetch_all(pool)
+    .await?;
+
+    let mut claimed = Vec::with_capacity(numbers.len());
+    for number in numbers {
+        if claim(pool, repository, number, sha).await? {
+            claimed.push(number);
+        }
+    }
+    if claimed.is_empty() {
+        return Ok(());
+    }
+
+    let mut logs = Vec::new();
+    for job in &jobs {
+        // Anyone who can post a commit status chooses its target URL.
+        let Some(url) = job.target_url.as_ref().filter(|url| client.is_own(url)) else {
+            continue;
+        };
+        match client.get_text(&format!("{url}/logs")).await {
+            Ok(log) => logs.push((job.context.as_str(), log)),
+            Err(err) => tracing::warn!(
+                "fetching log of {} for {repository}@{sha}: {err}",
+                job.context
+            ),
+        }
+    }
+
+    let analysis = Analysis::from_jobs(&jobs, &logs);
+    for number in claimed {
+        if let Err(err) = publish(client, repository, number, sha, &analysis).await {
+            // A later status event gets to try again.
+            release(pool, repository, number, sha).await?;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(context: &str, state: &str, target_url: Option<&str>) -> Job {
+        Job {
+            context: context.to_string(),
+            state: state.to_string(),
+            description: None,
+            target_url: target_url.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn summarizes_jobs() {
+        let jobs = [
+            job("ci / lint", "success", None),
+            job(
+                "ci / test",
+                "failure",
+                Some("https://forgejo.example/org/repo/actions/runs/7/jobs/1"),
+            ),
+        ];
+        let analysis = Analysis::from_jobs(&jobs, &[]);
+        assert_eq!(
+            analysis.summary,
+            "**CeresForge**: 1 of 2 jobs passed.\n\n\
+             | Job | Result |\n\
+             | --- | --- |\n\
+             | ci / lint | success |\n\
+             | [ci / test](https://forgejo.example/org/repo/actions/runs/7/jobs/1) | failure |\n"
+        );
+        assert!(analysis.findings.is_empty());
+    }
+
+    #[test]
+    fn finds_annotations() {
+        let log = "\
+            2024-05-01T10:00:00.0000000Z Running tests\n\
+            2024-05-01T10:00:01.0000000Z ::error file=src/lib.rs,line=12,col=5::assertion failed%0Aleft: 1\n\
+            ::warning file=src/a%2Cb.rs,line=3::unused variable `x`\n\
+            ::notice title=Done::no file, not a finding\n\
+            ::error file=src/lib.rs,line=x::no line number\n\
+            ::debug file=src/lib.rs,line=1::not an annotation\n";
+        let jobs = [job("ci / test", "failure", None)];
+        let analysis = Analysis::from_jobs(&jobs, &[("ci / test", log.to_string())]);
+        assert_eq!(
+            analysis.findings,
+            [
+                Finding {
+                    path: "src/lib.rs".to_string(),
+                    line: 12,
+                    body: "**ci / test** error: assertion failed\nleft: 1".to_string(),
+                },
+                Finding {
+                    path: "src/a,b.rs".to_string(),
+                    line: 3,
+                    body: "**ci / test** warning: unused variable `x`".to_string(),
+                },
+            ]
+        );
+
+        let noisy = "::warning file=src/lib.rs,line=1::lint\n".repeat(MAX_FINDINGS + 10);
+        let analysis = Analysis::from_jobs(&jobs, &[("ci / lint", noisy)]);
+        assert_eq!(analysis.findings.len(), MAX_FINDINGS);
+    }
+}
@@ -0,0 +1,315 @@
+//! Outbound mail over SMTP.
+//!
+//! Mail is sent through `SMTP_HOST` on `SMTP_PORT` (default 587), from
+//! `MAIL_FROM`, logging in with `SMTP_USERNAME` and `SMTP_PASSWORD` if set.
+//! `SMTP_TLS` is `starttls` (the default), `tls` for implicit TLS, usually
+//! on port 465, or `none` for a local sink like Mailpit. Without `SMTP_HOST`
+//! no mail is sent, and features depending on it are off.
+//!
+//! Every [`Email`] comes in plain text and HTML, rendered from the
+//! templates in `src/mail/templates`.
+
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+};
+use quick_xml::escape::escape;
+use std::{borrow::Cow, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encryption {
+    StartTls,
+    Implicit,
+    None,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub encryption: Encryption,
+    pub credentials: Option<(String, String)>,
+    pub from: Mailbox,
+}
+
+impl Config {
+    /// Returns the SMTP configuration, or `None` if mail is off.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(host) = std::env::var("SMTP_HOST") else {
+            return Ok(None);
+        };
+        let port = match std::env::var("SMTP_PORT") {
+            Ok(port) => port
+                .parse()
+                .map_err(|_| format!("SMTP_PORT is not a port: {port}"))?,
+            Err(_) => 587,
+        };
+        let encryption = match std::env::var("SMTP_TLS").as_deref() {
+            Err(_) | Ok("starttls") => Encryption::StartTls,
+            Ok("tls") => Encryption::Implicit,
+            Ok("none") => Encryption::None,
+            Ok(mode) => return Err(format!("unknown SMTP_TLS mode {mode}")),
+        };
+        let credentials = match (
+            std::env::var("SMTP_USERNAME"),
+            std::env::var("SMTP_PASSWORD"),
+        ) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        let from = std::env::var("MAIL_FROM")
+            .map_err(|_| "MAIL_FROM is not set".to_string())?
+            .parse()
+            .map_err(|err| format!("MAIL_FROM is not an address: {err}"))?;
+        Ok(Some(Config {
+            host,
+            port,
+            encryption,
+            credentials,
+            from,
+        }))
+    }
+
+    fn transport(
+        &self,
+    ) -> Result<AsyncSmtpTransport<Tokio1Executor>, lettre::transport::smtp::Error> {
+        let builder = match self.encryption {
+            Encryption::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?
+            }
+            Encryption::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+            Encryption::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host),
+        };
+        let builder = builder.port(self.port).timeout(Some(TIMEOUT));
+        Ok(match &self.credentials {
+            Some((username, password)) => builder
+                .credentials(Credentials::new(username.clone(), password.clone()))
+                .build(),
+            None => builder.build(),
+        })
+    }
+}
+
+/// Whether mail is configured, for features that are useless without it.
+pub fn enabled() -> bool {
+    std::env::var("SMTP_HOST").is_ok()
+}
+
+/// A message to a user.
+#[derive(Debug)]
+pub enum Email<'a> {
+    Verification { username: &'a str, link: &'a str },
+    PasswordReset { username: &'a str, link: &'a str },
+    Login { username: &'a str, addr: &'a str },
+}
+
+/// Fills in the `{name}` placeholders of a template with `values`, passed
+/// through `encode`.
+fn fill(template: &str, values: &[(&str, &str)], encode: fn(&str) -> Cow<'_, str>) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |filled, (name, value)| {
+            filled.replace(&format!("{{{name}}}"), &encode(value))
+        })
+}
+
+impl Email<'_> {
+    pub fn subject(&self) -> &'static str {
+        match self {
+            Email::Verification { .. } => "Verify your email address",
+            Email::PasswordReset { .. } => "Reset your password",
+            Email::Login { .. } => "New login to your account",
+        }
+    }
+
+    fn templates(&self) -> (&'static str, &'static str) {
+        match self {
+            Email::Verification { .. } => (
+                include_str!("templates/verification.txt"),
+                include_str!("templates/verification.html"),
+            ),
+            Email::PasswordReset { .. } => (
+                include_str!("templates/password-reset.txt"),
+                include_str!("templates/password-reset.html"),
+            ),
+            Email::Login { .. } => (
+                include_str!("templates/login.txt"),
+                include_str!("templates/login.html"),
+            ),
+        }
+    }
+
+    fn values(&self) -> Vec<(&'static str, &str)> {
+        match *self {
+            Email::Verification { username, link } | Email::PasswordReset { username, link } => {
+                vec![("username", username), ("link", link)]
+            }
+            Email::Login { username, addr } => vec![("username", username), ("addr", addr)],
+        }
+    }
+
+    pub fn text(&self) -> String {
+        fill(self.templates().0, &self.values(), |value| {
+            Cow::Borrowed(value)
+        })
+    }
+
+    pub fn html(&self) -> String {
+        fill(self.templates().1, &self.values(), |value| escape(value))
+    }
+
+    fn message(&self, from: Mailbox, to: &str) -> Result<Message, String> {
+        let to: Mailbox = to
+            .parse()
+            .map_err(|err| format!("invalid address {to}: {err}"))?;
+        Message::builder()
+            .from(from)
+            .to(to)
+            .subject(self.subject())
+            .multipart(MultiPart::alternative_plain_html(self.text(), self.html()))
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Sends a message, waiting for the server to accept it.
+pub async fn deliver(config: &Config, message: Message) -> Result<(), String> {
+    config
+        .transport()
+        .map_err(|err| err.to_string())?
+        .send(message)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+/// Sends an email in the background, so that neither a slow nor a failing
+/// mail server holds up the request. Failures are logged.
+pub fn send(to: &str, email: &Email<'_>) {
+    let subject = email.subject();
+    let config = match Config::from_env() {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            tracing::debug!(to, subject, "mail is off, not sending");
+            return;
+        }
+        Err(err) => {
+            tracing::error!("invalid mail configuration: {err}");
+            return;
+        }
+    };
+    let message = match email.message(config.from.clone(), to) {
+        Ok(message) => message,
+        Err(err) => {
+            tracing::error!(to, subject, "composing mail failed: {err}");
+            return;
+        }
+    };
+    let to = to.to_string();
+    tokio::spawn(async move {
+        match deliver(&config, message).await {
+            Ok(()) => tracing::info!(to, subject, "sent mail"),
+            Err(err) => tracing::error!(to, subject, "sending mail failed: {err}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn renders_text_and_html() {
+        let email = Email::PasswordReset {
+            username: "jdoe",
+            link: "https://ceresforge.example/auth/email/reset#a&b",
+        };
+        let text = email.text();
+        assert!(text.starts_with("Hi jdoe,\n"));
+        assert!(text.contains("\nhttps://ceresforge.example/auth/email/reset#a&b\n"));
+        assert!(!text.contains('{'));
+        let html = email.html();
+        assert!(html.contains("<a href=\"https://ceresforge.example/auth/email/reset#a&amp;b\">"));
+
+        let html = Email::Login {
+            username: "<script>",
+            addr: "192.0.2.1",
+        }
+        .html();
+        assert!(html.contains("Hi &lt;script&gt;,"));
+        assert!(html.contains("from 192.0.2.1."));
+    }
+
+    /// Accepts one message like an SMTP sink would and returns it.
+    async fn sink(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+        let mut message = String::new();
+        let mut in_data = false;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    writer.write_all(b"250 queued\r\n").await.unwrap();
+                } else {
+                    message.push_str(&line);
+                    message.push('\n');
+                }
+                continue;
+            }
+            let command = line
+                .split(' ')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_uppercase();
+            let reply: &[u8] = match command.as_str() {
+                "EHLO" => b"250 sink\r\n",
+                "DATA" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                "QUIT" => b"221 bye\r\n",
+                _ => b"250 ok\r\n",
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        message
+    }
+
+    #[tokio::test]
+    async fn delivers_to_local_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = tokio::spawn(sink(listener));
+
+        let config = Config {
+            host: "127.0.0.1".to_string(),
+            port,
+            encryption: Encryption::None,
+            credentials: None,
+            from: "CeresForge <noreply@ceresforge.example>".parse().unwrap(),
+        };
+        let email = Email::Verification {
+            username: "jdoe",
+            link: "https://ceresforge.example/auth/email/verification?token=abc",
+        };
+        let message = email
+            .message(config.from.clone(), "jdoe@uni.example")
+            .unwrap();
+        deliver(&config, message).await.unwrap();
+
+        let message = received.await.unwrap();
+        assert!(message.contains("To: jdoe@uni.example"));
+        assert!(message.contains("Subject: Verify your email address"));
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains("Content-Type: text/html; charset=utf-8"));
+    }
+}
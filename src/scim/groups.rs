@@ -0,0 +1,502 @@
+//! SCIM groups, which enroll their members in a course.
+//!
+//! A group is named after the slug of a course, optionally followed by the
+//! role its members have there: `algo-2026` enrolls students,
+//! `algo-2026:ta` teaching assistants and `algo-2026:instructor`
+//! instructors. The course has to exist first. As a user has one role per
+//! course, joining one of its groups moves them out of the others, and
+//! leaving a group or deleting it ends their enrollment. Groups only manage
+//! the enrollments they made: someone enrolled in the course in CeresForge
+//! keeps their enrollment and role whatever the groups say.
+
+use crate::{
+    courses::{self, CourseRole},
+    scim::{
+        ListQuery, ListResponse, Meta, Op, PatchRequest, Scim, ScimError, ScimResult, parse_filter,
+        parse_id,
+    },
+};
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgConnection, PgPool};
+
+pub const SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+
+const COLUMNS: &str = "scim_groups.id, courses.id AS course_id, courses.slug, scim_groups.role,
+    scim_groups.external_id,
+    to_char(scim_groups.created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"')
+        AS created,
+    to_char(scim_groups.updated_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"')
+        AS last_modified";
+
+const FROM: &str = "scim_groups JOIN courses ON courses.id = scim_groups.course_id";
+
+#[derive(Debug, FromRow)]
+struct Row {
+    id: i64,
+    course_id: i64,
+    slug: String,
+    role: CourseRole,
+    external_id: Option<String>,
+    created: String,
+    last_modified: String,
+}
+
+/// Returns the name of the group enrolling users in course `slug` with
+/// `role`.
+fn display_name(slug: &str, role: CourseRole) -> String {
+    match role {
+        CourseRole::Student => slug.to_string(),
+        CourseRole::Ta => format!("{slug}:ta"),
+        CourseRole::Instructor => format!("{slug}:instructor"),
+    }
+}
+
+/// Returns the course slug and role a group name stands for.
+fn parse_display_name(name: &str) -> (&str, CourseRole) {
+    match name.rsplit_once(':') {
+        Some((slug, "student")) => (slug, CourseRole::Student),
+        Some((slug, "ta")) => (slug, CourseRole::Ta),
+        Some((slug, "instructor")) => (slug, CourseRole::Instructor),
+        _ => (name, CourseRole::Student),
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Member {
+    value: String,
+    display: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupResource {
+    schemas: [&'static str; 1],
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_id: Option<String>,
+    display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    members: Option<Vec<Member>>,
+    meta: Meta,
+}
+
+async fn members(pool: &PgPool, row: &Row) -> ScimResult<Vec<Member>> {
+    Ok(sqlx::query_as(
+        "SELECT users.id::TEXT AS value, users.username AS display
+         FROM course_members JOIN users ON users.id = course_members.user_id
+         WHERE course_members.scim_group_id = $1
+         ORDER BY users.id",
+    )
+    .bind(row.id)
+    .fetch_all(pool)
+    .await?)
+}
+
+async fn resource(pool: &PgPool, row: Row, with_members: bool) -> ScimResult<GroupResource> {
+    let members = match with_members {
+        true => Some(members(pool, &row).await?),
+        false => None,
+    };
+    Ok(GroupResource {
+        schemas: [SCHEMA],
+        id: row.id.to_string(),
+        external_id: row.external_id,
+        display_name: display_name(&row.slug, row.role),
+        members,
+        meta: Meta::new("Group", row.id, row.created, row.last_modified)?,
+    })
+}
+
+async fn find(pool: &PgPool, id: i64) -> ScimResult<Row> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM {FROM} WHERE scim_groups.id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ScimError::not_found(format!("no group {id}")))
+}
+
+#[derive(Debug, Deserialize)]
+struct MemberRef {
+    value: String,
+}
+
+fn user_id(value: &str) -> ScimResult<i64> {
+    value
+        .parse()
+        .map_err(|_| ScimError::bad_request("invalidValue", format!("no user {value}")))
+}
+
+fn member_ids(value: &Value) -> ScimResult<Vec<i64>> {
+    Vec::<MemberRef>::deserialize(value)
+        .map_err(|_| ScimError::bad_request("invalidValue", "bad members".to_string()))?
+        .iter()
+        .map(|member| user_id(&member.value))
+        .collect()
+}
+
+async fn add_member(tx: &mut PgConnection, row: &Row, user_id: i64) -> ScimResult<()> {
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !exists {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            format!("no user {user_id}"),
+        ));
+    }
+    sqlx::query(
+        "INSERT INTO course_members (course_id, user_id, role, scim_group_id)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (course_id, user_id) DO UPDATE
+         SET role = EXCLUDED.role, scim_group_id = EXCLUDED.scim_group_id
+         WHERE course_members.scim_group_id IS NOT NULL",
+    )
+    .bind(row.course_id)
+    .bind(user_id)
+    .bind(row.role)
+    .bind(row.id)
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+async fn remove_members(
+    tx: &mut PgConnection,
+    row: &Row,
+    user_ids: Option<&[i64]>,
+) -> ScimResult<()> {
+    sqlx::query(
+        "DELETE FROM course_members
+         WHERE scim_group_id = $1 AND ($2::BIGINT[] IS NULL OR user_id = ANY($2))",
+    )
+    .bind(row.id)
+    .bind(user_ids)
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+/// Checks that a new name for a group stands for the course and role it
+/// already enrolls in.
+fn check_rename(row: &Row, name: &str) -> ScimResult<()> {
+    if parse_display_name(name) != (row.slug.as_str(), row.role) {
+        return Err(ScimError::bad_request(
+            "mutability",
+            format!(
+                "group {} cannot be renamed to {name}",
+                display_name(&row.slug, row.role)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The filter on `members` in a path like `members[value eq "42"]`.
+fn member_filter(path: &str) -> Option<&str> {
+    let filter = path.strip_prefix("members[")?.strip_suffix(']')?;
+    Some(filter)
+}
+
+/// Applies a patch operation to a group, updating `external_id` if it
+/// changes that.
+async fn apply(
+    tx: &mut PgConnection,
+    row: &Row,
+    external_id: &mut Option<String>,
+    op: Op,
+    path: Option<&str>,
+    value: Option<&Value>,
+) -> ScimResult<()> {
+    let path = path.map(str::to_ascii_lowercase);
+    match (op, path.as_deref(), value) {
+        (Op::Add, Some("members"), Some(value)) => {
+            for user_id in member_ids(value)? {
+                add_member(tx, row, user_id).await?;
+            }
+        }
+        (Op::Replace, Some("members"), Some(value)) => {
+            remove_members(tx, row, None).await?;
+            for user_id in member_ids(value)? {
+                add_member(tx, row, user_id).await?;
+            }
+        }
+        (Op::Remove, Some("members"), Some(value)) => {
+            remove_members(tx, row, Some(&member_ids(value)?)).await?;
+        }
+        (Op::Remove, Some("members"), None) => remove_members(tx, row, None).await?,
+        (Op::Remove, Some(path), _) if member_filter(path).is_some() => {
+            let filter = parse_filter(member_filter(path).unwrap_or_default())?;
+            if filter.attribute != "value" {
+                return Err(ScimError::bad_request(
+                    "invalidFilter",
+                    "members can only be filtered by value".to_string(),
+                ));
+            }
+            remove_members(tx, row, Some(&[user_id(&filter.value)?])).await?;
+        }
+        (Op::Add | Op::Replace, Some("displayname"), Some(Value::String(name))) => {
+            check_rename(row, name)?
+        }
+        (Op::Add | Op::Replace, Some("externalid"), Some(Value::String(id))) => {
+            *external_id = Some(id.clone())
+        }
+        (Op::Remove, Some("externalid"), _) => *external_id = None,
+        (Op::Add | Op::Replace, None, Some(Value::Object(attributes))) => {
+            // Without a path, the value holds attributes to set.
+            for (path, value) in attributes {
+                Box::pin(apply(tx, row, external_id, op, Some(path), Some(value))).await?;
+            }
+        }
+        (_, path, _) => {
+            return Err(ScimError::bad_request(
+                "invalidPath",
+                format!("unsupported group patch {}", path.unwrap_or("without path")),
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn store_external_id(
+    tx: &mut PgConnection,
+    id: i64,
+    external_id: Option<&str>,
+) -> ScimResult<()> {
+    sqlx::query("UPDATE scim_groups SET external_id = $2, updated_at = now() WHERE id = $1")
+        .bind(id)
+        .bind(external_id)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+pub async fn list(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult<Scim<ListResponse<GroupResource>>> {
+    let (course, role, external_id) = match query.filter.as_deref().map(parse_filter).transpose()? {
+        None => (None, None, None),
+        Some(filter) => match filter.attribute.as_str() {
+            "displayname" => {
+                let (slug, role) = parse_display_name(&filter.value);
+                (Some(slug.to_string()), Some(role), None)
+            }
+            "externalid" => (None, None, Some(filter.value)),
+            _ => {
+                return Err(ScimError::bad_request(
+                    "invalidFilter",
+                    format!("cannot filter groups by {}", filter.attribute),
+                ));
+            }
+        },
+    };
+    let condition = "($1::TEXT IS NULL OR courses.slug = $1)
+                     AND ($2::course_role IS NULL OR scim_groups.role = $2)
+                     AND ($3::TEXT IS NULL OR scim_groups.external_id = $3)";
+    let total: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {FROM} WHERE {condition}"))
+        .bind(&course)
+        .bind(role)
+        .bind(&external_id)
+        .fetch_one(&pool)
+        .await?;
+    let rows: Vec<Row> = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM {FROM} WHERE {condition}
+         ORDER BY scim_groups.id OFFSET $4 LIMIT $5"
+    ))
+    .bind(&course)
+    .bind(role)
+    .bind(&external_id)
+    .bind(query.offset())
+    .bind(query.limit())
+    .fetch_all(&pool)
+    .await?;
+    let mut groups = Vec::with_capacity(rows.len());
+    for row in rows {
+        groups.push(resource(&pool, row, !query.excludes("members")).await?);
+    }
+    Ok(Scim(ListResponse::new(&query, total, groups)))
+}
+
+pub async fn show(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult<Scim<GroupResource>> {
+    let id = parse_id("group", &id)?;
+    let row = find(&pool, id).await?;
+    Ok(Scim(
+        resource(&pool, row, !query.excludes("members")).await?,
+    ))
+}
+
+/// A group as sent for creating or replacing it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewGroup {
+    display_name: String,
+    external_id: Option<String>,
+    #[serde(default)]
+    members: Vec<MemberRef>,
+}
+
+impl NewGroup {
+    fn member_ids(&self) -> ScimResult<Vec<i64>> {
+        self.members
+            .iter()
+            .map(|member| user_id(&member.value))
+            .collect()
+    }
+}
+
+pub async fn create(
+    Extension(pool): Extension<PgPool>,
+    payload: Result<Json<NewGroup>, JsonRejection>,
+) -> ScimResult<Response> {
+    let Json(new) = payload?;
+    let (slug, role) = parse_display_name(&new.display_name);
+    let course = courses::find(&pool, slug)
+        .await?
+        .ok_or_else(|| ScimError::bad_request("invalidValue", format!("no course {slug}")))?;
+    let mut tx = pool.begin().await?;
+    let id: i64 = sqlx::query_scalar(
+        "INSERT INTO scim_groups (course_id, role, external_id) VALUES ($1, $2, $3)
+         RETURNING id",
+    )
+    .bind(course.id)
+    .bind(role)
+    .bind(&new.external_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| match err.as_database_error() {
+        Some(db) if db.is_unique_violation() => ScimError::new(
+            StatusCode::CONFLICT,
+            Some("uniqueness"),
+            format!("group {} exists already", new.display_name),
+        ),
+        _ => err.into(),
+    })?;
+    let row: Row = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM {FROM} WHERE scim_groups.id = $1"
+    ))
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+    for user_id in new.member_ids()? {
+        add_member(&mut tx, &row, user_id).await?;
+    }
+    tx.commit().await?;
+    tracing::info!(group = new.display_name, "created SCIM group");
+    Ok((StatusCode::CREATED, Scim(resource(&pool, row, true).await?)).into_response())
+}
+
+pub async fn replace(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    payload: Result<Json<NewGroup>, JsonRejection>,
+) -> ScimResult<Scim<GroupResource>> {
+    let id = parse_id("group", &id)?;
+    let Json(new) = payload?;
+    let row = find(&pool, id).await?;
+    check_rename(&row, &new.display_name)?;
+    let mut tx = pool.begin().await?;
+    remove_members(&mut tx, &row, None).await?;
+    for user_id in new.member_ids()? {
+        add_member(&mut tx, &row, user_id).await?;
+    }
+    store_external_id(&mut tx, id, new.external_id.as_deref()).await?;
+    tx.commit().await?;
+    Ok(Scim(resource(&pool, find(&pool, id).await?, true).await?))
+}
+
+pub async fn update(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    payload: Result<Json<PatchRequest>, JsonRejection>,
+) -> ScimResult<Scim<GroupResource>> {
+    let id = parse_id("group", &id)?;
+    let Json(patch) = payload?;
+    let row = find(&pool, id).await?;
+    let mut external_id = row.external_id.clone();
+    let mut tx = pool.begin().await?;
+    for operation in &patch.operations {
+        apply(
+            &mut tx,
+            &row,
+            &mut external_id,
+            operation.op()?,
+            operation.path.as_deref(),
+            operation.value.as_ref(),
+        )
+        .await?;
+    }
+    store_external_id(&mut tx, id, external_id.as_deref()).await?;
+    tx.commit().await?;
+    Ok(Scim(resource(&pool, find(&pool, id).await?, true).await?))
+}
+
+pub async fn remove(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> ScimResult<StatusCode> {
+    let id = parse_id("group", &id)?;
+    let row = find(&pool, id).await?;
+    let mut tx = pool.begin().await?;
+    remove_members(&mut tx, &row, None).await?;
+    sqlx::query("DELETE FROM scim_groups WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    tracing::info!(
+        group = display_name(&row.slug, row.role),
+        "deleted SCIM group"
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn group_names() {
+        for role in [CourseRole::Student, CourseRole::Ta, CourseRole::Instructor] {
+            let name = display_name("algo-2026", role);
+            assert_eq!(parse_display_name(&name), ("algo-2026", role));
+        }
+        assert_eq!(
+            parse_display_name("algo-2026:student"),
+            ("algo-2026", CourseRole::Student)
+        );
+        assert_eq!(
+            parse_display_name("ws:2026"),
+            ("ws:2026", CourseRole::Student)
+        );
+    }
+
+    #[test]
+    fn members() {
+        assert_eq!(
+            member_ids(&json!([{"value": "42"}, {"value": "7", "display": "bob"}])).unwrap(),
+            vec![42, 7]
+        );
+        assert!(member_ids(&json!([{"value": "alice"}])).is_err());
+        assert_eq!(
+            member_filter("members[value eq \"42\"]"),
+            Some("value eq \"42\"")
+        );
+        assert_eq!(member_filter("members"), None);
+    }
+}
@@ -0,0 +1,384 @@
+//! SCIM 2.0 provisioning, after RFC 7643 and RFC 7644.
+//!
+//! Lets the identity management system of an institution create, update
+//! and deactivate users, and enroll them in courses through groups; see
+//! [`groups`]. It authenticates with the bearer token in `SCIM_TOKEN`, and
+//! SCIM is off without one. Errors take the SCIM error shape rather than
+//! that of [`ApiError`].
+//!
+//! Only what provisioning clients rely on is supported: filtering by
+//! equality on a single attribute, paging, and `add`, `replace` and
+//! `remove` patch operations.
+
+mod groups;
+mod users;
+
+use crate::{api::ApiError, auth::csrf};
+
+use axum::{
+    Json, Router,
+    extract::{Request, rejection::JsonRejection},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+pub const ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const LIST_RESPONSE: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+
+/// Most resources returned in one page.
+pub const MAX_RESULTS: i64 = 200;
+
+/// An error in the shape of RFC 7644, section 3.12.
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+pub type ScimResult<T> = Result<T, ScimError>;
+
+impl ScimError {
+    pub fn new(status: StatusCode, scim_type: Option<&'static str>, detail: String) -> Self {
+        ScimError {
+            status,
+            scim_type,
+            detail,
+        }
+    }
+
+    pub fn bad_request(scim_type: &'static str, detail: String) -> Self {
+        ScimError::new(StatusCode::BAD_REQUEST, Some(scim_type), detail)
+    }
+
+    pub fn not_found(detail: String) -> Self {
+        ScimError::new(StatusCode::NOT_FOUND, None, detail)
+    }
+}
+
+impl From<ApiError> for ScimError {
+    fn from(err: ApiError) -> Self {
+        let scim_type = match &err {
+            ApiError::UsernameTaken(_) => Some("uniqueness"),
+            _ => None,
+        };
+        if err.status().is_server_error() {
+            tracing::error!("SCIM request failed: {err}");
+        }
+        ScimError::new(err.status(), scim_type, err.to_string())
+    }
+}
+
+impl From<sqlx::Error> for ScimError {
+    fn from(err: sqlx::Error) -> Self {
+        ApiError::from(err).into()
+    }
+}
+
+impl From<JsonRejection> for ScimError {
+    fn from(rejection: JsonRejection) -> Self {
+        ScimError::bad_request("invalidSyntax", rejection.body_text())
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "schemas": [ERROR],
+            "status": self.status.as_str(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = scim_type.into();
+        }
+        (self.status, Scim(body)).into_response()
+    }
+}
+
+/// A response body of media type `application/scim+json`.
+#[derive(Debug)]
+pub struct Scim<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Scim<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.0).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/scim+json"),
+        );
+        response
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    resource_type: &'static str,
+    created: String,
+    last_modified: String,
+    location: String,
+}
+
+impl Meta {
+    pub fn new(
+        resource_type: &'static str,
+        id: i64,
+        created: String,
+        last_modified: String,
+    ) -> ScimResult<Self> {
+        let base_url = std::env::var("BASE_URL").map_err(ApiError::from)?;
+        Ok(Meta {
+            resource_type,
+            created,
+            last_modified,
+            location: format!(
+                "{}/scim/v2/{resource_type}s/{id}",
+                base_url.trim_end_matches('/')
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    schemas: [&'static str; 1],
+    total_results: i64,
+    start_index: i64,
+    items_per_page: usize,
+    #[serde(rename = "Resources")]
+    resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    pub fn new(query: &ListQuery, total_results: i64, resources: Vec<T>) -> Self {
+        ListResponse {
+            schemas: [LIST_RESPONSE],
+            total_results,
+            start_index: query.offset() + 1,
+            items_per_page: resources.len(),
+            resources,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    start_index: Option<i64>,
+    count: Option<i64>,
+    excluded_attributes: Option<String>,
+}
+
+impl ListQuery {
+    /// Resources to skip; `startIndex` counts from 1.
+    pub fn offset(&self) -> i64 {
+        self.start_index.unwrap_or(1).max(1) - 1
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.count.unwrap_or(MAX_RESULTS).clamp(0, MAX_RESULTS)
+    }
+
+    pub fn excludes(&self, attribute: &str) -> bool {
+        self.excluded_attributes.as_deref().is_some_and(|excluded| {
+            excluded
+                .split(',')
+                .any(|excluded| excluded.trim().eq_ignore_ascii_case(attribute))
+        })
+    }
+}
+
+/// A filter like `userName eq "alice"`, the only kind supported. The
+/// attribute is lowercased, as attribute names are case-insensitive.
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    pub attribute: String,
+    pub value: String,
+}
+
+pub fn parse_filter(filter: &str) -> ScimResult<Filter> {
+    let invalid = || {
+        ScimError::bad_request(
+            "invalidFilter",
+            format!("unsupported filter {filter}; only `attribute eq \"value\"` is"),
+        )
+    };
+    let mut words = filter.trim().splitn(3, ' ');
+    let (Some(attribute), Some(operator), Some(value)) = (words.next(), words.next(), words.next())
+    else {
+        return Err(invalid());
+    };
+    if !operator.eq_ignore_ascii_case("eq") {
+        return Err(invalid());
+    }
+    let value: String = serde_json::from_str(value.trim()).map_err(|_| invalid())?;
+    Ok(Filter {
+        attribute: attribute.to_ascii_lowercase(),
+        value,
+    })
+}
+
+/// Parses the ID in a resource path; IDs that cannot exist are not found.
+pub fn parse_id(resource_type: &str, id: &str) -> ScimResult<i64> {
+    id.parse()
+        .map_err(|_| ScimError::not_found(format!("no {resource_type} {id}")))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Replace,
+    Remove,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+impl PatchOperation {
+    /// The operation; some clients capitalize it.
+    pub fn op(&self) -> ScimResult<Op> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(Op::Add),
+            "replace" => Ok(Op::Replace),
+            "remove" => Ok(Op::Remove),
+            _ => Err(ScimError::bad_request(
+                "invalidSyntax",
+                format!("unknown patch operation {}", self.op),
+            )),
+        }
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, token) = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then_some(token.trim())
+}
+
+/// Middleware rejecting requests without the SCIM token.
+async fn authenticate(request: Request, next: Next) -> Response {
+    let Ok(expected) = std::env::var("SCIM_TOKEN") else {
+        return ScimError::not_found("SCIM is not enabled".to_string()).into_response();
+    };
+    match bearer(request.headers()) {
+        Some(token) if !expected.is_empty() && csrf::equal(token, &expected) => {
+            next.run(request).await
+        }
+        _ => ScimError::new(
+            StatusCode::UNAUTHORIZED,
+            None,
+            "invalid SCIM token".to_string(),
+        )
+        .into_response(),
+    }
+}
+
+async fn service_provider_config() -> Scim<Value> {
+    Scim(json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+        "patch": {"supported": true},
+        "bulk": {"supported": false, "maxOperations": 0, "maxPayloadSize": 0},
+        "filter": {"supported": true, "maxResults": MAX_RESULTS},
+        "changePassword": {"supported": false},
+        "sort": {"supported": false},
+        "etag": {"supported": false},
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "Bearer token",
+            "description": "The token configured in SCIM_TOKEN",
+        }],
+    }))
+}
+
+async fn fallback() -> ScimError {
+    ScimError::not_found("no such endpoint".to_string())
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/ServiceProviderConfig", get(service_provider_config))
+        .route("/Users", get(users::list).post(users::create))
+        .route(
+            "/Users/{id}",
+            get(users::show)
+                .put(users::replace)
+                .patch(users::update)
+                .delete(users::deactivate),
+        )
+        .route("/Groups", get(groups::list).post(groups::create))
+        .route(
+            "/Groups/{id}",
+            get(groups::show)
+                .put(groups::replace)
+                .patch(groups::update)
+                .delete(groups::remove),
+        )
+        .fallback(fallback)
+        .layer(middleware::from_fn(authenticate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            parse_filter("userName eq \"alice@uni.example\"").unwrap(),
+            Filter {
+                attribute: "username".to_string(),
+                value: "alice@uni.example".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_filter("displayName EQ \"algo 2026\"").unwrap().value,
+            "algo 2026"
+        );
+        assert!(parse_filter("userName sw \"a\"").is_err());
+        assert!(parse_filter("userName eq alice").is_err());
+        assert!(parse_filter("userName").is_err());
+    }
+
+    #[test]
+    fn paging() {
+        let query: ListQuery =
+            serde_json::from_value(json!({"startIndex": 11, "count": 1000})).unwrap();
+        assert_eq!(query.offset(), 10);
+        assert_eq!(query.limit(), MAX_RESULTS);
+        let query: ListQuery =
+            serde_json::from_value(json!({"excludedAttributes": "meta, Members"})).unwrap();
+        assert_eq!(query.offset(), 0);
+        assert!(query.excludes("members"));
+    }
+
+    #[test]
+    fn errors() {
+        let response = ScimError::bad_request("invalidFilter", "bad".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/scim+json"
+        );
+    }
+}
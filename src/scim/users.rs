@@ -0,0 +1,497 @@
+//! SCIM users.
+//!
+//! Users created here are students until given another role, and log in
+//! through the SAML or OpenID provider in `SCIM_ISSUER`: their first login
+//! there adopts the user whose external ID is their NameID or subject
+//! rather than creating another one; see [`crate::users::provision`].
+//! Deleting a user only deactivates it, which ends its sessions and keeps
+//! it from logging in, so its work stays around.
+
+use crate::{
+    audit,
+    auth::session,
+    scim::{
+        ListQuery, ListResponse, Meta, Op, PatchOperation, PatchRequest, Scim, ScimError,
+        ScimResult, parse_filter, parse_id,
+    },
+    users::Role,
+};
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+
+pub const SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+
+const COLUMNS: &str = "id, username, email, display_name, active, external_id,
+    to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS created,
+    to_char(updated_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS last_modified";
+
+#[derive(Debug, FromRow)]
+struct Row {
+    id: i64,
+    username: String,
+    email: Option<String>,
+    display_name: Option<String>,
+    active: bool,
+    external_id: Option<String>,
+    created: String,
+    last_modified: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Email {
+    value: String,
+    #[serde(default)]
+    primary: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserResource {
+    schemas: [&'static str; 1],
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_id: Option<String>,
+    user_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    emails: Vec<Email>,
+    active: bool,
+    meta: Meta,
+}
+
+impl TryFrom<Row> for UserResource {
+    type Error = ScimError;
+
+    fn try_from(row: Row) -> ScimResult<Self> {
+        Ok(UserResource {
+            schemas: [SCHEMA],
+            id: row.id.to_string(),
+            external_id: row.external_id,
+            user_name: row.username,
+            display_name: row.display_name,
+            emails: row
+                .email
+                .into_iter()
+                .map(|value| Email {
+                    value,
+                    primary: true,
+                })
+                .collect(),
+            active: row.active,
+            meta: Meta::new("User", row.id, row.created, row.last_modified)?,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Name {
+    formatted: Option<String>,
+    given_name: Option<String>,
+    family_name: Option<String>,
+}
+
+impl Name {
+    fn display(&self) -> Option<String> {
+        if self.formatted.is_some() {
+            return self.formatted.clone();
+        }
+        let parts: Vec<&str> = [&self.given_name, &self.family_name]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// A user as sent for creating or replacing it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewUser {
+    user_name: String,
+    external_id: Option<String>,
+    display_name: Option<String>,
+    #[serde(default)]
+    name: Name,
+    #[serde(default)]
+    emails: Vec<Email>,
+    #[serde(default = "default_active")]
+    active: bool,
+}
+
+/// The primary address, or else the first.
+fn primary(emails: Vec<Email>) -> Option<String> {
+    let primary = emails.iter().position(|email| email.primary).unwrap_or(0);
+    emails.into_iter().nth(primary).map(|email| email.value)
+}
+
+/// The attributes of a user SCIM can change.
+#[derive(Debug, PartialEq)]
+struct Attributes {
+    username: String,
+    external_id: Option<String>,
+    display_name: Option<String>,
+    email: Option<String>,
+    active: bool,
+}
+
+impl From<NewUser> for Attributes {
+    fn from(new: NewUser) -> Self {
+        Attributes {
+            display_name: new.display_name.or_else(|| new.name.display()),
+            username: new.user_name,
+            external_id: new.external_id,
+            email: primary(new.emails),
+            active: new.active,
+        }
+    }
+}
+
+impl From<Row> for Attributes {
+    fn from(row: Row) -> Self {
+        Attributes {
+            username: row.username,
+            external_id: row.external_id,
+            display_name: row.display_name,
+            email: row.email,
+            active: row.active,
+        }
+    }
+}
+
+fn invalid_value(path: &str) -> ScimError {
+    ScimError::bad_request("invalidValue", format!("bad value for {path}"))
+}
+
+fn string(path: &str, value: &Value) -> ScimResult<String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| invalid_value(path))
+}
+
+/// Some clients send booleans as strings, like `"False"`.
+fn boolean(path: &str, value: &Value) -> ScimResult<bool> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        Value::String(value) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(value) if value.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(invalid_value(path)),
+    }
+}
+
+impl Attributes {
+    /// Sets attribute `path` to `value`, or clears it without a value.
+    /// Attributes CeresForge does not keep are ignored.
+    fn set(&mut self, path: &str, value: Option<&Value>) -> ScimResult<()> {
+        let lowercase = path.to_ascii_lowercase();
+        match (lowercase.as_str(), value) {
+            ("active", Some(value)) => self.active = boolean(path, value)?,
+            ("username", Some(value)) => self.username = string(path, value)?,
+            ("username" | "active", None) => {
+                return Err(ScimError::bad_request(
+                    "mutability",
+                    format!("{path} cannot be removed"),
+                ));
+            }
+            ("externalid", value) => {
+                self.external_id = value.map(|value| string(path, value)).transpose()?
+            }
+            ("displayname" | "name.formatted", value) => {
+                self.display_name = value.map(|value| string(path, value)).transpose()?
+            }
+            ("name", value) => {
+                self.display_name = match value {
+                    Some(value) => Name::deserialize(value)
+                        .map_err(|_| invalid_value(path))?
+                        .display(),
+                    None => None,
+                }
+            }
+            ("emails", value) => {
+                self.email = match value {
+                    Some(value) => {
+                        primary(Vec::<Email>::deserialize(value).map_err(|_| invalid_value(path))?)
+                    }
+                    None => None,
+                }
+            }
+            // Like `emails[type eq "work"].value`.
+            (path, value) if path.starts_with("emails[") && path.ends_with("].value") => {
+                self.email = value.map(|value| string(path, value)).transpose()?
+            }
+            (path, _) => tracing::debug!(path, "ignoring SCIM user attribute"),
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, operation: &PatchOperation) -> ScimResult<()> {
+        let op = operation.op()?;
+        match (&operation.path, &operation.value) {
+            (Some(path), _) if op == Op::Remove => self.set(path, None),
+            (Some(path), Some(value)) => self.set(path, Some(value)),
+            // Without a path, the value holds attributes to set.
+            (None, Some(Value::Object(attributes))) if op != Op::Remove => {
+                for (path, value) in attributes {
+                    self.set(path, Some(value))?;
+                }
+                Ok(())
+            }
+            _ => Err(ScimError::bad_request(
+                "noTarget",
+                "patch operation has no path or value to apply".to_string(),
+            )),
+        }
+    }
+}
+
+async fn find(pool: &PgPool, id: i64) -> ScimResult<Row> {
+    sqlx::query_as(&format!("SELECT {COLUMNS} FROM users WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ScimError::not_found(format!("no user {id}")))
+}
+
+fn conflict(err: sqlx::Error, attributes: &Attributes) -> ScimError {
+    match err.as_database_error() {
+        Some(db) if db.is_unique_violation() => ScimError::new(
+            StatusCode::CONFLICT,
+            Some("uniqueness"),
+            format!("user {} exists already", attributes.username),
+        ),
+        _ => err.into(),
+    }
+}
+
+/// Writes `attributes` to user `id`, ending its sessions if it was
+/// deactivated.
+async fn store(pool: &PgPool, id: i64, attributes: &Attributes) -> ScimResult<UserResource> {
+    let was_active: bool = sqlx::query_scalar("SELECT active FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ScimError::not_found(format!("no user {id}")))?;
+    let row: Row = sqlx::query_as(&format!(
+        "UPDATE users
+         SET username = $2, external_id = $3, display_name = $4, email = $5, active = $6,
+             updated_at = now()
+         WHERE id = $1
+         RETURNING {COLUMNS}"
+    ))
+    .bind(id)
+    .bind(&attributes.username)
+    .bind(&attributes.external_id)
+    .bind(&attributes.display_name)
+    .bind(&attributes.email)
+    .bind(attributes.active)
+    .fetch_one(pool)
+    .await
+    .map_err(|err| conflict(err, attributes))?;
+    if was_active && !row.active {
+        session::end_others(pool, id, None).await?;
+        audit::record(pool, Some(id), "scim_user_deactivated", &row.username).await?;
+    } else if !was_active && row.active {
+        audit::record(pool, Some(id), "scim_user_activated", &row.username).await?;
+    }
+    row.try_into()
+}
+
+pub async fn list(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult<Scim<ListResponse<UserResource>>> {
+    let (username, external_id) = match query.filter.as_deref().map(parse_filter).transpose()? {
+        None => (None, None),
+        Some(filter) => match filter.attribute.as_str() {
+            "username" => (Some(filter.value), None),
+            "externalid" => (None, Some(filter.value)),
+            _ => {
+                return Err(ScimError::bad_request(
+                    "invalidFilter",
+                    format!("cannot filter users by {}", filter.attribute),
+                ));
+            }
+        },
+    };
+    let condition = "($1::TEXT IS NULL OR username = $1)
+                     AND ($2::TEXT IS NULL OR external_id = $2)";
+    let total: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM users WHERE {condition}"))
+        .bind(&username)
+        .bind(&external_id)
+        .fetch_one(&pool)
+        .await?;
+    let rows: Vec<Row> = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM users WHERE {condition} ORDER BY id OFFSET $3 LIMIT $4"
+    ))
+    .bind(&username)
+    .bind(&external_id)
+    .bind(query.offset())
+    .bind(query.limit())
+    .fetch_all(&pool)
+    .await?;
+    let users = rows
+        .into_iter()
+        .map(UserResource::try_from)
+        .collect::<ScimResult<_>>()?;
+    Ok(Scim(ListResponse::new(&query, total, users)))
+}
+
+pub async fn show(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> ScimResult<Scim<UserResource>> {
+    let id = parse_id("user", &id)?;
+    Ok(Scim(find(&pool, id).await?.try_into()?))
+}
+
+pub async fn create(
+    Extension(pool): Extension<PgPool>,
+    payload: Result<Json<NewUser>, JsonRejection>,
+) -> ScimResult<Response> {
+    let Json(new) = payload?;
+    let attributes = Attributes::from(new);
+    let row: Row = sqlx::query_as(&format!(
+        "INSERT INTO users
+             (username, external_id, display_name, email, active, role, scim_provisioned)
+         VALUES ($1, $2, $3, $4, $5, $6, true)
+         RETURNING {COLUMNS}"
+    ))
+    .bind(&attributes.username)
+    .bind(&attributes.external_id)
+    .bind(&attributes.display_name)
+    .bind(&attributes.email)
+    .bind(attributes.active)
+    .bind(Role::Student)
+    .fetch_one(&pool)
+    .await
+    .map_err(|err| conflict(err, &attributes))?;
+    audit::record(&pool, Some(row.id), "scim_user_created", &row.username).await?;
+    Ok((StatusCode::CREATED, Scim(UserResource::try_from(row)?)).into_response())
+}
+
+pub async fn replace(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    payload: Result<Json<NewUser>, JsonRejection>,
+) -> ScimResult<Scim<UserResource>> {
+    let id = parse_id("user", &id)?;
+    let Json(new) = payload?;
+    Ok(Scim(store(&pool, id, &Attributes::from(new)).await?))
+}
+
+pub async fn update(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+    payload: Result<Json<PatchRequest>, JsonRejection>,
+) -> ScimResult<Scim<UserResource>> {
+    let id = parse_id("user", &id)?;
+    let Json(patch) = payload?;
+    let mut attributes = Attributes::from(find(&pool, id).await?);
+    for operation in &patch.operations {
+        attributes.apply(operation)?;
+    }
+    Ok(Scim(store(&pool, id, &attributes).await?))
+}
+
+pub async fn deactivate(
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<String>,
+) -> ScimResult<StatusCode> {
+    let id = parse_id("user", &id)?;
+    let mut attributes = Attributes::from(find(&pool, id).await?);
+    attributes.active = false;
+    store(&pool, id, &attributes).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(attributes: &mut Attributes, operations: Value) -> ScimResult<()> {
+        let patch: PatchRequest =
+            serde_json::from_value(json!({"Operations": operations})).unwrap();
+        for operation in &patch.operations {
+            attributes.apply(operation)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn new_user() {
+        let new: NewUser = serde_json::from_value(json!({
+            "schemas": [SCHEMA],
+            "userName": "alice@uni.example",
+            "name": {"givenName": "Alice", "familyName": "Liddell"},
+            "emails": [
+                {"value": "alice@home.example"},
+                {"value": "alice@uni.example", "primary": true, "type": "work"}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            Attributes::from(new),
+            Attributes {
+                username: "alice@uni.example".to_string(),
+                external_id: None,
+                display_name: Some("Alice Liddell".to_string()),
+                email: Some("alice@uni.example".to_string()),
+                active: true,
+            }
+        );
+    }
+
+    #[test]
+    fn patches() {
+        let mut attributes = Attributes {
+            username: "alice".to_string(),
+            external_id: None,
+            display_name: Some("Alice".to_string()),
+            email: None,
+            active: true,
+        };
+        patch(
+            &mut attributes,
+            json!([
+                {"op": "Replace", "path": "active", "value": "False"},
+                {"op": "replace", "path": "emails[type eq \"work\"].value", "value": "a@uni.example"},
+                {"op": "add", "value": {"externalId": "e-1", "title": "Student"}},
+                {"op": "remove", "path": "displayName"}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            attributes,
+            Attributes {
+                username: "alice".to_string(),
+                external_id: Some("e-1".to_string()),
+                display_name: None,
+                email: Some("a@uni.example".to_string()),
+                active: false,
+            }
+        );
+        assert!(
+            patch(
+                &mut attributes,
+                json!([{"op": "remove", "path": "userName"}])
+            )
+            .is_err()
+        );
+        assert!(patch(&mut attributes, json!([{"op": "move", "path": "active"}])).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! The sessions and personal access tokens a user is logged in with.
+//!
+//! Users can see where they are logged in and end sessions they do not
+//! recognize, or all but the current one. Admins can do the same for any
+//! user and revoke their tokens too, to lock out whoever took over an
+//! account; what they do is audited.
+
+use crate::{
+    api::{ApiResult, error::ResourceNotFound},
+    audit,
+    auth::{
+        rbac::{Authorized, require},
+        session::{self, ABSOLUTE_TIMEOUT, AuthMethod, IDLE_TIMEOUT},
+        token::{self, ApiToken, Scoped},
+    },
+    users::User,
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, Path},
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get},
+};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+
+/// Returns a short description like "Firefox on Linux" of the device a
+/// `User-Agent` header comes from.
+pub fn describe(user_agent: &str) -> String {
+    const BROWSERS: &[(&str, &str)] = &[
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ];
+    const SYSTEMS: &[(&str, &str)] = &[
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ];
+    let find = |names: &[(&str, &'static str)]| {
+        names
+            .iter()
+            .find(|(token, _)| user_agent.contains(token))
+            .map(|(_, name)| *name)
+    };
+    match (find(BROWSERS), find(SYSTEMS)) {
+        (Some(browser), Some(system)) => format!("{browser} on {system}"),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        // Other clients, like curl/8.5.0, lead with their name.
+        (None, None) => user_agent
+            .split(['/', ' '])
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("Unknown device")
+            .to_string(),
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Row {
+    id: i64,
+    auth_method: AuthMethod,
+    addr: String,
+    user_agent: Option<String>,
+    created_at: i64,
+    last_seen_at: i64,
+    /// Whether the request listing the sessions was made with this one.
+    current: bool,
+    /// Whether two-factor authentication still has to be set up.
+    restricted: bool,
+    /// Whom the user is viewing the site as.
+    impersonating: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Listed {
+    #[serde(flatten)]
+    row: Row,
+    device: Option<String>,
+}
+
+/// Returns the live sessions of a user, most recently used first.
+async fn sessions_of(pool: &PgPool, user_id: i64, current: Option<&str>) -> ApiResult<Vec<Listed>> {
+    let rows: Vec<Row> = sqlx::query_as(
+        "SELECT sessions.handle AS id, sessions.auth_method, sessions.addr,
+                sessions.user_agent,
+                extract(epoch FROM sessions.created_at)::BIGINT AS created_at,
+                extract(epoch FROM sessions.last_seen_at)::BIGINT AS last_seen_at,
+                sessions.id IS NOT DISTINCT FROM $2 AS current,
+                sessions.restricted,
+                impersonated.username AS impersonating
+         FROM sessions
+         LEFT JOIN users impersonated ON impersonated.id = sessions.impersonating_id
+         WHERE sessions.user_id = $1
+           AND sessions.last_seen_at > now() - $3
+           AND sessions.created_at > now() - $4
+         ORDER BY sessions.last_seen_at DESC",
+    )
+    .bind(user_id)
+    .bind(current)
+    .bind(IDLE_TIMEOUT)
+    .bind(ABSOLUTE_TIMEOUT)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Listed {
+            device: row.user_agent.as_deref().map(describe),
+            row,
+        })
+        .collect())
+}
+
+/// Ends session `id` of a user. Returns whether there was such a session.
+async fn end_session(pool: &PgPool, user_id: i64, id: i64) -> ApiResult<bool> {
+    let ended = sqlx::query("DELETE FROM sessions WHERE handle = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(ended.rows_affected() > 0)
+}
+
+async fn list(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    Scoped(user, _): Scoped<token::require::Admin>,
+) -> ApiResult<Json<Vec<Listed>>> {
+    let current = session::from_headers(&headers)?;
+    Ok(Json(sessions_of(&pool, user.id, current).await?))
+}
+
+async fn revoke(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    Scoped(user, _): Scoped<token::require::Admin>,
+    Path(id): Path<i64>,
+) -> ApiResult<StatusCode> {
+    if !end_session(&pool, user.id, id).await? {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    tracing::info!(user = user.username, session = id, "ended session");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Ends every session but the one the request was made with.
+async fn revoke_others(
+    Extension(pool): Extension<PgPool>,
+    headers: HeaderMap,
+    Scoped(user, _): Scoped<token::require::Admin>,
+) -> ApiResult<StatusCode> {
+    let ended = session::end_others(&pool, user.id, session::from_headers(&headers)?).await?;
+    tracing::info!(user = user.username, ended, "ended other sessions");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns the user an admin route is about.
+async fn user(pool: &PgPool, uri: &OriginalUri, username: &str) -> ApiResult<User> {
+    Ok(sqlx::query_as(
+        "SELECT id, username, email, display_name, role FROM users WHERE username = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?)
+}
+
+async fn user_sessions(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    _: Authorized<require::ManageUsers>,
+    Path(username): Path<String>,
+) -> ApiResult<Json<Vec<Listed>>> {
+    let user = user(&pool, &uri, &username).await?;
+    Ok(Json(sessions_of(&pool, user.id, None).await?))
+}
+
+async fn revoke_user_session(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageUsers>,
+    Path((username, id)): Path<(String, i64)>,
+) -> ApiResult<StatusCode> {
+    let user = user(&pool, &uri, &username).await?;
+    if !end_session(&pool, user.id, id).await? {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "session_revoked",
+        &format!("{username} session {id}"),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn revoke_user_sessions(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageUsers>,
+    Path(username): Path<String>,
+) -> ApiResult<StatusCode> {
+    let user = user(&pool, &uri, &username).await?;
+    let ended = session::end_others(&pool, user.id, None).await?;
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "sessions_revoked",
+        &format!("{username}: {ended} sessions"),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn user_tokens(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    _: Authorized<require::ManageUsers>,
+    Path(username): Path<String>,
+) -> ApiResult<Json<Vec<ApiToken>>> {
+    let user = user(&pool, &uri, &username).await?;
+    Ok(Json(token::of_user(&pool, user.id).await?))
+}
+
+async fn revoke_user_token(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    authorized: Authorized<require::ManageUsers>,
+    Path((username, id)): Path<(String, i64)>,
+) -> ApiResult<StatusCode> {
+    let user = user(&pool, &uri, &username).await?;
+    if !token::revoke_of_user(&pool, user.id, id).await? {
+        return Err(ResourceNotFound::new(uri.to_string()).into());
+    }
+    audit::record(
+        &pool,
+        Some(authorized.user.id),
+        "token_revoked",
+        &format!("{username} token {id}"),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Routes for the current user's sessions.
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list).delete(revoke_others))
+        .route("/{id}", delete(revoke))
+}
+
+/// Routes for admins, under the username of the user they are about.
+pub fn admin_routes() -> Router {
+    Router::new()
+        .route("/sessions", get(user_sessions).delete(revoke_user_sessions))
+        .route("/sessions/{id}", delete(revoke_user_session))
+        .route("/tokens", get(user_tokens))
+        .route("/tokens/{id}", delete(revoke_user_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices() {
+        assert_eq!(
+            describe("Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"),
+            "Firefox on Linux"
+        );
+        assert_eq!(
+            describe(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36 Edg/130.0.0.0"
+            ),
+            "Edge on Windows"
+        );
+        assert_eq!(
+            describe(
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 18_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 Mobile/15E148 Safari/604.1"
+            ),
+            "Safari on iOS"
+        );
+        assert_eq!(
+            describe(
+                "Mozilla/5.0 (Linux; Android 14) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Mobile Safari/537.36"
+            ),
+            "Chrome on Android"
+        );
+        assert_eq!(describe("curl/8.5.0"), "curl");
+        assert_eq!(describe(""), "Unknown device");
+    }
+}
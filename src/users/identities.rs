@@ -0,0 +1,196 @@
+//! External identities linked to users.
+//!
+//! A person may log in through SAML, OpenID Connect and Forgejo, and push
+//! to Forgejo, each under an identity of its own. All of them lead to the
+//! same user once linked, which happens on first login through a provider
+//! or, for a user who is already logged in, through the linking flow in
+//! [`crate::auth::link`]. A local password belongs to the user itself.
+
+use crate::{
+    api::{
+        ApiResult,
+        error::{Forbidden, IdentityLinked, ResourceNotFound},
+    },
+    audit,
+    auth::{
+        session::CurrentUser,
+        token::{self, Scoped},
+    },
+    users::{self, User},
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{OriginalUri, Path},
+    http::StatusCode,
+    routing::{delete, get},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "identity_kind", rename_all = "lowercase")]
+pub enum IdentityKind {
+    Saml,
+    Oidc,
+    Forgejo,
+}
+
+impl IdentityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdentityKind::Saml => "saml",
+            IdentityKind::Oidc => "oidc",
+            IdentityKind::Forgejo => "forgejo",
+        }
+    }
+}
+
+/// An identity at an external provider.
+#[derive(Clone, Debug, PartialEq, FromRow)]
+pub struct Identity {
+    pub kind: IdentityKind,
+    /// SAML entity ID or OpenID issuer; empty for Forgejo.
+    pub issuer: String,
+    /// SAML NameID, OpenID subject or Forgejo user ID.
+    pub subject: String,
+    pub display_name: Option<String>,
+}
+
+impl Identity {
+    pub fn saml(idp: &str, name_id: &str, display_name: Option<&str>) -> Self {
+        Identity {
+            kind: IdentityKind::Saml,
+            issuer: idp.to_string(),
+            subject: name_id.to_string(),
+            display_name: display_name.map(str::to_string),
+        }
+    }
+
+    pub fn oidc(issuer: &str, sub: &str, display_name: Option<&str>) -> Self {
+        Identity {
+            kind: IdentityKind::Oidc,
+            issuer: issuer.to_string(),
+            subject: sub.to_string(),
+            display_name: display_name.map(str::to_string),
+        }
+    }
+
+    /// The Forgejo user with ID `id`, which is also their OpenID subject at
+    /// Forgejo.
+    pub fn forgejo(id: &str, username: Option<&str>) -> Self {
+        Identity {
+            kind: IdentityKind::Forgejo,
+            issuer: String::new(),
+            subject: id.to_string(),
+            display_name: username.map(str::to_string),
+        }
+    }
+}
+
+/// Links an identity to user `user_id`, or refreshes its display name if it
+/// already is. Fails with [`IdentityLinked`] if it belongs to someone else.
+pub async fn insert(
+    tx: &mut sqlx::PgConnection,
+    user_id: i64,
+    identity: &Identity,
+) -> ApiResult<()> {
+    let linked = sqlx::query(
+        "INSERT INTO identities (kind, issuer, subject, user_id, display_name)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (kind, issuer, subject) DO UPDATE
+         SET display_name = EXCLUDED.display_name
+         WHERE identities.user_id = EXCLUDED.user_id",
+    )
+    .bind(identity.kind)
+    .bind(&identity.issuer)
+    .bind(&identity.subject)
+    .bind(user_id)
+    .bind(&identity.display_name)
+    .execute(tx)
+    .await?;
+    if linked.rows_affected() == 0 {
+        return Err(IdentityLinked::new(identity.subject.clone()).into());
+    }
+    Ok(())
+}
+
+/// Returns the user the Forgejo user with ID `forgejo_id` is linked to.
+pub async fn forgejo_user(pool: &PgPool, forgejo_id: i64) -> ApiResult<Option<User>> {
+    Ok(sqlx::query_as(
+        "SELECT users.id, users.username, users.email, users.display_name, users.role
+         FROM identities JOIN users ON users.id = identities.user_id
+         WHERE identities.kind = 'forgejo' AND identities.subject = $1",
+    )
+    .bind(forgejo_id.to_string())
+    .fetch_optional(pool)
+    .await?)
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Linked {
+    id: i64,
+    kind: IdentityKind,
+    issuer: String,
+    subject: String,
+    display_name: Option<String>,
+    created_at: i64,
+}
+
+async fn list(
+    Extension(pool): Extension<PgPool>,
+    CurrentUser(user): CurrentUser,
+) -> ApiResult<Json<Vec<Linked>>> {
+    let identities = sqlx::query_as(
+        "SELECT id, kind, issuer, subject, display_name,
+                extract(epoch FROM created_at)::BIGINT AS created_at
+         FROM identities WHERE user_id = $1 ORDER BY id",
+    )
+    .bind(user.id)
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(identities))
+}
+
+async fn unlink(
+    Extension(pool): Extension<PgPool>,
+    uri: OriginalUri,
+    Scoped(user, _): Scoped<token::require::Admin>,
+    Path(id): Path<i64>,
+) -> ApiResult<StatusCode> {
+    let mut tx = pool.begin().await?;
+    let identity: Identity = sqlx::query_as(
+        "DELETE FROM identities WHERE id = $1 AND user_id = $2
+         RETURNING kind, issuer, subject, display_name",
+    )
+    .bind(id)
+    .bind(user.id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ResourceNotFound::new(uri.to_string()))?;
+    // Users have to keep some way of logging in.
+    if !users::can_log_in(&mut tx, user.id).await? {
+        return Err(Forbidden::new("unlink_last_identity".to_string()).into());
+    }
+    tx.commit().await?;
+    audit::record(
+        &pool,
+        Some(user.id),
+        "identity_unlinked",
+        &format!(
+            "{} {} {}",
+            identity.kind.as_str(),
+            identity.issuer,
+            identity.subject
+        ),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list))
+        .route("/{id}", delete(unlink))
+}
@@ -0,0 +1,284 @@
+pub mod devices;
+pub mod identities;
+
+use crate::{
+    api::{ApiResult, error::UsernameTaken},
+    auth::{impersonation::Impersonator, session::CurrentUser},
+};
+pub use identities::{Identity, IdentityKind};
+
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// Global roles, from least to most privileged.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "role", rename_all = "lowercase")]
+pub enum Role {
+    Student,
+    Ta,
+    Instructor,
+    Admin,
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "student" => Ok(Role::Student),
+            "ta" => Ok(Role::Ta),
+            "instructor" => Ok(Role::Instructor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role {role}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, FromRow)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub role: Role,
+}
+
+/// What an identity provider tells us about a user.
+#[derive(Debug, PartialEq)]
+pub struct Profile {
+    pub username: String,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub role: Role,
+}
+
+fn username_taken(err: sqlx::Error, username: &str) -> crate::api::ApiError {
+    match err.as_database_error() {
+        Some(db) if db.is_unique_violation() => UsernameTaken::new(username.to_string()).into(),
+        _ => err.into(),
+    }
+}
+
+const COLUMNS: &str = "id, username, email, display_name, role";
+
+async fn find(tx: &mut sqlx::PgConnection, id: i64) -> ApiResult<User> {
+    Ok(
+        sqlx::query_as(&format!("SELECT {COLUMNS} FROM users WHERE id = $1"))
+            .bind(id)
+            .fetch_one(tx)
+            .await?,
+    )
+}
+
+/// Creates the user for the first login of an external identity.
+async fn create(tx: &mut sqlx::PgConnection, profile: &Profile) -> ApiResult<User> {
+    sqlx::query_as(&format!(
+        "INSERT INTO users (username, email, display_name, role)
+         VALUES ($1, $2, $3, $4)
+         RETURNING {COLUMNS}"
+    ))
+    .bind(&profile.username)
+    .bind(&profile.email)
+    .bind(&profile.display_name)
+    .bind(profile.role)
+    .fetch_one(tx)
+    .await
+    .map_err(|err| username_taken(err, &profile.username))
+}
+
+/// Brings a user up to date with the `profile` their primary identity
+/// comes with, including the role the provider's mapping yields. They keep
+/// their username if someone else has taken the new one.
+async fn update(tx: &mut sqlx::PgConnection, id: i64, profile: &Profile) -> ApiResult<User> {
+    Ok(sqlx::query_as(&format!(
+        "UPDATE users
+         SET username = CASE
+                 WHEN EXISTS (SELECT 1 FROM users AS other WHERE other.username = $2)
+                 THEN username ELSE $2
+             END,
+             email = $3, display_name = $4, role = $5, updated_at = now()
+         WHERE id = $1
+         RETURNING {COLUMNS}"
+    ))
+    .bind(id)
+    .bind(&profile.username)
+    .bind(&profile.email)
+    .bind(&profile.display_name)
+    .bind(profile.role)
+    .fetch_one(tx)
+    .await?)
+}
+
+/// Whether the identity provider that issued `identity` is `scim_issuer`,
+/// the one the SCIM client speaks for.
+fn vouches_for_scim(scim_issuer: Option<&str>, identity: &Identity) -> bool {
+    identity.kind != IdentityKind::Forgejo
+        && scim_issuer.is_some_and(|issuer| !issuer.is_empty() && issuer == identity.issuer)
+}
+
+/// Returns the user created through SCIM that `identity` stands for, if
+/// no identity from its provider is linked to the user yet, so that its
+/// first login finds the user instead of colliding with it. Only identities from the provider in
+/// `SCIM_ISSUER` are trusted with that, and the user's external ID has to
+/// be the identity's subject, its SAML NameID or OpenID subject.
+async fn scim_provisioned(
+    tx: &mut sqlx::PgConnection,
+    identity: &Identity,
+) -> ApiResult<Option<i64>> {
+    let scim_issuer = std::env::var("SCIM_ISSUER").ok();
+    if !vouches_for_scim(scim_issuer.as_deref(), identity) {
+        return Ok(None);
+    }
+    Ok(sqlx::query_scalar(
+        "SELECT id FROM users
+         WHERE external_id = $1 AND scim_provisioned
+           AND NOT EXISTS (
+               SELECT 1 FROM identities
+               WHERE identities.user_id = users.id AND kind = $2 AND issuer = $3
+           )",
+    )
+    .bind(&identity.subject)
+    .bind(identity.kind)
+    .bind(&identity.issuer)
+    .fetch_optional(tx)
+    .await?)
+}
+
+/// Returns the user behind an external identity, creating it on first
+/// login. The identity a user was created through is their primary one,
+/// and logins through it bring the user up to date with `profile`; other
+/// identities linked to the user only log them in.
+pub async fn provision(pool: &PgPool, identity: &Identity, profile: &Profile) -> ApiResult<User> {
+    let mut tx = pool.begin().await?;
+    let linked: Option<(i64, bool)> = sqlx::query_as(
+        "SELECT identities.user_id, users.primary_identity_id IS NOT DISTINCT FROM identities.id
+         FROM identities JOIN users ON users.id = identities.user_id
+         WHERE identities.kind = $1 AND identities.issuer = $2 AND identities.subject = $3",
+    )
+    .bind(identity.kind)
+    .bind(&identity.issuer)
+    .bind(&identity.subject)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let (user, created) = match linked {
+        Some((id, true)) => (update(&mut tx, id, profile).await?, false),
+        Some((id, false)) => (find(&mut tx, id).await?, false),
+        // The directory that provisioned a user keeps their profile.
+        None => match scim_provisioned(&mut tx, identity).await? {
+            Some(id) => (find(&mut tx, id).await?, false),
+            None => (create(&mut tx, profile).await?, true),
+        },
+    };
+    identities::insert(&mut tx, user.id, identity).await?;
+    if created {
+        sqlx::query(
+            "UPDATE users SET primary_identity_id = identities.id
+             FROM identities
+             WHERE users.id = $1
+               AND identities.kind = $2 AND identities.issuer = $3 AND identities.subject = $4",
+        )
+        .bind(user.id)
+        .bind(identity.kind)
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .execute(&mut *tx)
+        .await?;
+    }
+    if linked.is_none() {
+        tracing::info!(
+            user = user.username,
+            kind = identity.kind.as_str(),
+            issuer = identity.issuer,
+            "provisioned user"
+        );
+    }
+    tx.commit().await?;
+    Ok(user)
+}
+
+/// Whether user `user_id` still has some way of logging in: a linked
+/// identity, a local password or a passkey.
+pub async fn can_log_in(conn: &mut sqlx::PgConnection, user_id: i64) -> ApiResult<bool> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM identities WHERE user_id = $1)
+             OR EXISTS (SELECT 1 FROM users WHERE id = $1 AND password_hash IS NOT NULL)
+             OR EXISTS (SELECT 1 FROM webauthn_credentials WHERE user_id = $1)",
+    )
+    .bind(user_id)
+    .fetch_one(conn)
+    .await?)
+}
+
+/// Creates a user with a local password.
+pub async fn create_local(
+    pool: &PgPool,
+    profile: &Profile,
+    password_hash: &str,
+) -> ApiResult<User> {
+    let user: User = sqlx::query_as(
+        "INSERT INTO users (username, email, display_name, role, password_hash)
+         VALUES ($1, $2, $3, $4, $5)
+         RETURNING id, username, email, display_name, role",
+    )
+    .bind(&profile.username)
+    .bind(&profile.email)
+    .bind(&profile.display_name)
+    .bind(profile.role)
+    .bind(password_hash)
+    .fetch_one(pool)
+    .await
+    .map_err(|err| username_taken(err, &profile.username))?;
+    tracing::info!(user = user.username, "registered local user");
+    Ok(user)
+}
+
+/// Sets the local password of a user, who may also log in through SAML.
+pub async fn set_password_hash(pool: &PgPool, id: i64, password_hash: &str) -> ApiResult<()> {
+    sqlx::query("UPDATE users SET password_hash = $2, updated_at = now() WHERE id = $1")
+        .bind(id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Current {
+    #[serde(flatten)]
+    user: User,
+    /// Who is viewing the site as the user, for showing a banner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    impersonator: Option<Impersonator>,
+}
+
+/// Returns the logged in user, or the one being impersonated.
+pub async fn current(
+    CurrentUser(user): CurrentUser,
+    impersonator: Option<Extension<Impersonator>>,
+) -> Json<Current> {
+    Json(Current {
+        user,
+        impersonator: impersonator.map(|Extension(impersonator)| impersonator),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scim_issuer() {
+        let idp = "https://idp.example.edu/idp/shibboleth";
+        let saml = Identity::saml(idp, "jdoe", None);
+        assert!(vouches_for_scim(Some(idp), &saml));
+        assert!(!vouches_for_scim(None, &saml));
+        assert!(!vouches_for_scim(Some("https://other.example.org"), &saml));
+        let oidc = Identity::oidc("https://accounts.example.com", "jdoe", None);
+        assert!(!vouches_for_scim(Some(idp), &oidc));
+        assert!(!vouches_for_scim(Some(""), &Identity::forgejo("1", None)));
+    }
+}